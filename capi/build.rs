@@ -0,0 +1,12 @@
+//! Writes `include/ate_chip.h` from the `extern "C"` functions in src/lib.rs, using the settings in cbindgen.toml
+use std::path::Path;
+
+fn main() {
+    let dir = std::env::var("CARGO_MANIFEST_DIR").expect("cargo sets this");
+    let config = cbindgen::Config::from_file(Path::new(&dir).join("cbindgen.toml")).expect("cbindgen.toml is valid");
+    cbindgen::generate_with_config(&dir, config)
+        .expect("src/lib.rs can be turned into a header")
+        .write_to_file(Path::new(&dir).join("include/ate_chip.h"));
+    println!("cargo:rerun-if-changed=src/lib.rs");
+    println!("cargo:rerun-if-changed=cbindgen.toml");
+}
@@ -0,0 +1,178 @@
+/* ate-chip, a chip-8 emulator. Generated from capi/src/lib.rs by cbindgen, don't edit by hand */
+
+#ifndef ATE_CHIP_H
+#define ATE_CHIP_H
+
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+
+// Goes up whenever a function changes in a way that breaks existing callers
+#define AC_API_VERSION 1
+
+#define AC_SCREEN_WIDTH 64
+
+#define AC_SCREEN_HEIGHT 32
+
+// Bytes written by `ac_framebuffer`
+#define AC_FRAMEBUFFER_SIZE 256
+
+#define AC_MEMORY_SIZE 4096
+
+// Why the emulator stopped, if it did
+typedef enum ACFaultKind {
+  AC_FAULT_KIND_NONE = 0,
+  // `2NNN` with all 16 stack slots already in use
+  AC_FAULT_KIND_STACK_OVERFLOW = 1,
+  // the program counter went past the end of memory
+  AC_FAULT_KIND_PC_OUT_OF_BOUNDS = 2,
+} ACFaultKind;
+
+// What happened, for calls that can fail
+typedef enum ACStatus {
+  AC_STATUS_OK = 0,
+  // a pointer that shouldn't have been was null
+  AC_STATUS_NULL_POINTER = 1,
+  // an argument was out of range, like a key past `0xF`
+  AC_STATUS_INVALID_ARGUMENT = 2,
+  // a save state that isn't one, or is from an incompatible version
+  AC_STATUS_BAD_STATE = 3,
+  // the program did something no interpreter could carry on from, see `ac_fault`
+  AC_STATUS_FAULT = 4,
+} ACStatus;
+
+// An emulator, with the keypad and settings that go with it
+typedef struct ACCore ACCore;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+// The version of the API this library has, to check against `AC_API_VERSION` when loaded at runtime
+uint32_t ac_api_version(void);
+
+// A new emulator with nothing loaded, running 10 instructions a frame with the default quirks.
+// Free it with `ac_free`
+struct ACCore *ac_new(void);
+
+// # Safety
+// `core` must be null or from `ac_new`, and not used again afterwards
+void ac_free(struct ACCore *core);
+
+// What went wrong with the last call that failed, valid until the next call that fails. Empty if nothing has
+//
+// # Safety
+// `core` must be null or from `ac_new`
+const char *ac_last_error(const struct ACCore *core);
+
+// Resets the emulator and loads `len` bytes of rom at 0x200, keeping the quirks, keys held and where the random
+// numbers are up to, so `ac_seed` can be called before or after
+//
+// # Safety
+// `core` must be from `ac_new`, and `rom` point to `len` readable bytes
+enum ACStatus ac_load_rom(struct ACCore *core,
+                          const uint8_t *rom,
+                          size_t len);
+
+// Behaves like another interpreter: `default`, `cosmac`, `chip48`, `schip` or `xochip`
+//
+// # Safety
+// `core` must be from `ac_new`, and `preset` a nul terminated string
+enum ACStatus ac_set_quirks(struct ACCore *core, const char *preset);
+
+// How many instructions `ac_step_frame` runs, there are 60 frames a second
+//
+// # Safety
+// `core` must be from `ac_new`
+enum ACStatus ac_set_cycles_per_frame(struct ACCore *core, uint32_t cycles);
+
+// Makes the random numbers `CXNN` picks the same every time for the same `seed`
+//
+// # Safety
+// `core` must be from `ac_new`
+enum ACStatus ac_seed(struct ACCore *core, uint64_t seed);
+
+// Presses (or releases) keypad key `key`, `0x0`-`0xF`
+//
+// # Safety
+// `core` must be from `ac_new`
+enum ACStatus ac_set_key(struct ACCore *core, uint8_t key, bool down);
+
+// Runs a 60th of a second, returning `Fault` if the emulator has stopped
+//
+// # Safety
+// `core` must be from `ac_new`
+enum ACStatus ac_step_frame(struct ACCore *core);
+
+// # Safety
+// `core` must be null or from `ac_new`
+enum ACFaultKind ac_fault(const struct ACCore *core);
+
+// Copies the screen into `out`, `AC_FRAMEBUFFER_SIZE` bytes a row at a time,
+// 8 pixels to a byte with the leftmost in the highest bit
+//
+// # Safety
+// `core` must be from `ac_new`, and `out` point to `AC_FRAMEBUFFER_SIZE` writable bytes
+enum ACStatus ac_framebuffer(const struct ACCore *core, uint8_t *out);
+
+// Whether the pixel at `x`, `y` is on, false if it's off the screen
+//
+// # Safety
+// `core` must be null or from `ac_new`
+bool ac_pixel(const struct ACCore *core, uint32_t x, uint32_t y);
+
+// Whether the sound timer is running, and a tone should be playing
+//
+// # Safety
+// `core` must be null or from `ac_new`
+bool ac_sound(const struct ACCore *core);
+
+// Register `VX`, 0 if `x` is past `0xF`
+//
+// # Safety
+// `core` must be null or from `ac_new`
+uint8_t ac_register(const struct ACCore *core, uint8_t x);
+
+// # Safety
+// `core` must be null or from `ac_new`
+uint16_t ac_pc(const struct ACCore *core);
+
+// # Safety
+// `core` must be null or from `ac_new`
+uint16_t ac_index(const struct ACCore *core);
+
+// Copies `len` bytes of memory from `address` into `out`, wrapping around at the end of memory
+//
+// # Safety
+// `core` must be from `ac_new`, and `out` point to `len` writable bytes
+enum ACStatus ac_read_memory(const struct ACCore *core, uint16_t address, uint8_t *out, size_t len);
+
+// Copies `len` bytes from `data` into memory at `address`, wrapping around at the end of memory
+//
+// # Safety
+// `core` must be from `ac_new`, and `data` point to `len` readable bytes
+enum ACStatus ac_write_memory(struct ACCore *core,
+                              uint16_t address,
+                              const uint8_t *data,
+                              size_t len);
+
+// Saves the emulator into `out` if it fits in `capacity` bytes, returning how many bytes the state takes
+// either way. Call with a null `out` to find out how big a buffer to use
+//
+// # Safety
+// `core` must be from `ac_new`, and `out` be null or point to `capacity` writable bytes
+size_t ac_save_state(const struct ACCore *core,
+                     uint8_t *out,
+                     size_t capacity);
+
+// Loads a state from `ac_save_state`, leaving the emulator as it was if it doesn't load
+//
+// # Safety
+// `core` must be from `ac_new`, and `state` point to `len` readable bytes
+enum ACStatus ac_load_state(struct ACCore *core, const uint8_t *state, size_t len);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* ATE_CHIP_H */
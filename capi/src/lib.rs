@@ -0,0 +1,352 @@
+//! A C API for embedding ate-chip in other languages, see `include/ate_chip.h` (generated from this file).
+//!
+//! Everything goes through an [`ACCore`] from [`ac_new`], which is freed with [`ac_free`]. Calls that can fail
+//! return an [`ACStatus`], with [`ac_last_error`] saying what went wrong. None of the calls are thread safe,
+//! but separate cores can be used from separate threads
+use std::ffi::{c_char, CStr, CString};
+use std::ptr;
+use std::slice;
+
+use ate_chip::emulator::{ACEmulator, ACFault};
+use ate_chip::keyboard::{ACKey, ACKeyboard};
+use ate_chip::quirks::ACQuirks;
+use ate_chip::{SCREEN_HEIGHT, SCREEN_WIDTH};
+
+/// Goes up whenever a function changes in a way that breaks existing callers
+pub const AC_API_VERSION: u32 = 1;
+// literals rather than `SCREEN_WIDTH`, so they make it into the header
+pub const AC_SCREEN_WIDTH: u32 = 64;
+pub const AC_SCREEN_HEIGHT: u32 = 32;
+const _: () = assert!(AC_SCREEN_WIDTH == SCREEN_WIDTH as u32 && AC_SCREEN_HEIGHT == SCREEN_HEIGHT as u32);
+/// Bytes written by `ac_framebuffer`
+pub const AC_FRAMEBUFFER_SIZE: usize = 256;
+pub const AC_MEMORY_SIZE: usize = 4096;
+
+/// What happened, for calls that can fail
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ACStatus {
+    Ok = 0,
+    /// a pointer that shouldn't have been was null
+    NullPointer = 1,
+    /// an argument was out of range, like a key past `0xF`
+    InvalidArgument = 2,
+    /// a save state that isn't one, or is from an incompatible version
+    BadState = 3,
+    /// the program did something no interpreter could carry on from, see `ac_fault`
+    Fault = 4,
+}
+
+/// Why the emulator stopped, if it did
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ACFaultKind {
+    None = 0,
+    /// `2NNN` with all 16 stack slots already in use
+    StackOverflow = 1,
+    /// the program counter went past the end of memory
+    PcOutOfBounds = 2,
+}
+
+/// An emulator, with the keypad and settings that go with it
+pub struct ACCore {
+    emulator: ACEmulator,
+    keyboard: ACKeyboard,
+    /// pressed since the last frame, for `FX0A`
+    new_keypress: Option<ACKey>,
+    cycles_per_frame: usize,
+    /// what `ac_last_error` returns
+    error: CString,
+}
+
+impl ACCore {
+    fn fail(&mut self, status: ACStatus, message: impl Into<String>) -> ACStatus {
+        // messages are ours, and never have nuls in
+        self.error = CString::new(message.into()).unwrap_or_default();
+        status
+    }
+}
+
+/// The core behind `core`, if it isn't null
+///
+/// # Safety
+/// `core` must be null or from `ac_new`, and not yet freed
+unsafe fn get<'a>(core: *mut ACCore) -> Option<&'a mut ACCore> {
+    core.as_mut()
+}
+
+/// `len` bytes at `data`, which may be null if `len` is 0
+///
+/// # Safety
+/// `data` must point to `len` readable bytes
+unsafe fn bytes<'a>(data: *const u8, len: usize) -> Option<&'a [u8]> {
+    match (data.is_null(), len) {
+        (true, 0) => Some(&[]),
+        (true, _) => None,
+        (false, _) => Some(slice::from_raw_parts(data, len)),
+    }
+}
+
+/// The version of the API this library has, to check against `AC_API_VERSION` when loaded at runtime
+#[no_mangle]
+pub extern "C" fn ac_api_version() -> u32 {
+    AC_API_VERSION
+}
+
+/// A new emulator with nothing loaded, running 10 instructions a frame with the default quirks.
+/// Free it with `ac_free`
+#[no_mangle]
+pub extern "C" fn ac_new() -> *mut ACCore {
+    Box::into_raw(Box::new(ACCore {
+        emulator: ACEmulator::new(),
+        keyboard: ACKeyboard::new(),
+        new_keypress: None,
+        cycles_per_frame: 10,
+        error: CString::default(),
+    }))
+}
+
+/// # Safety
+/// `core` must be null or from `ac_new`, and not used again afterwards
+#[no_mangle]
+pub unsafe extern "C" fn ac_free(core: *mut ACCore) {
+    if !core.is_null() {
+        drop(Box::from_raw(core));
+    }
+}
+
+/// What went wrong with the last call that failed, valid until the next call that fails. Empty if nothing has
+///
+/// # Safety
+/// `core` must be null or from `ac_new`
+#[no_mangle]
+pub unsafe extern "C" fn ac_last_error(core: *const ACCore) -> *const c_char {
+    match core.as_ref() {
+        Some(core) => core.error.as_ptr(),
+        None => c"core is null".as_ptr(),
+    }
+}
+
+/// Resets the emulator and loads `len` bytes of rom at 0x200, keeping the quirks, keys held and where the random
+/// numbers are up to, so `ac_seed` can be called before or after
+///
+/// # Safety
+/// `core` must be from `ac_new`, and `rom` point to `len` readable bytes
+#[no_mangle]
+pub unsafe extern "C" fn ac_load_rom(core: *mut ACCore, rom: *const u8, len: usize) -> ACStatus {
+    let Some(core) = get(core) else { return ACStatus::NullPointer };
+    let Some(rom) = bytes(rom, len) else { return core.fail(ACStatus::NullPointer, "rom is null") };
+    core.emulator.reset();
+    core.emulator.load_rom(rom);
+    core.new_keypress = None;
+    ACStatus::Ok
+}
+
+/// Behaves like another interpreter: `default`, `cosmac`, `chip48`, `schip` or `xochip`
+///
+/// # Safety
+/// `core` must be from `ac_new`, and `preset` a nul terminated string
+#[no_mangle]
+pub unsafe extern "C" fn ac_set_quirks(core: *mut ACCore, preset: *const c_char) -> ACStatus {
+    let Some(core) = get(core) else { return ACStatus::NullPointer };
+    if preset.is_null() {
+        return core.fail(ACStatus::NullPointer, "preset is null");
+    }
+    let name = CStr::from_ptr(preset).to_string_lossy();
+    match ACQuirks::preset(&name) {
+        Some(quirks) => {
+            core.emulator.quirks = quirks;
+            ACStatus::Ok
+        }
+        None => core.fail(ACStatus::InvalidArgument, format!("there is no quirk preset {:?}", name)),
+    }
+}
+
+/// How many instructions `ac_step_frame` runs, there are 60 frames a second
+///
+/// # Safety
+/// `core` must be from `ac_new`
+#[no_mangle]
+pub unsafe extern "C" fn ac_set_cycles_per_frame(core: *mut ACCore, cycles: u32) -> ACStatus {
+    let Some(core) = get(core) else { return ACStatus::NullPointer };
+    core.cycles_per_frame = cycles as usize;
+    ACStatus::Ok
+}
+
+/// Makes the random numbers `CXNN` picks the same every time for the same `seed`
+///
+/// # Safety
+/// `core` must be from `ac_new`
+#[no_mangle]
+pub unsafe extern "C" fn ac_seed(core: *mut ACCore, seed: u64) -> ACStatus {
+    let Some(core) = get(core) else { return ACStatus::NullPointer };
+    core.emulator.seed(seed);
+    ACStatus::Ok
+}
+
+/// Presses (or releases) keypad key `key`, `0x0`-`0xF`
+///
+/// # Safety
+/// `core` must be from `ac_new`
+#[no_mangle]
+pub unsafe extern "C" fn ac_set_key(core: *mut ACCore, key: u8, down: bool) -> ACStatus {
+    let Some(core) = get(core) else { return ACStatus::NullPointer };
+    let Some(key) = ACKey::from_hex(key) else {
+        return core.fail(ACStatus::InvalidArgument, format!("there is no key {}", key));
+    };
+    if !down {
+        core.keyboard.release(key);
+        return ACStatus::Ok;
+    }
+    if !core.keyboard.is_pressed(&key) {
+        core.new_keypress = core.new_keypress.or(Some(key));
+    }
+    core.keyboard.press(key);
+    ACStatus::Ok
+}
+
+/// Runs a 60th of a second, returning `Fault` if the emulator has stopped
+///
+/// # Safety
+/// `core` must be from `ac_new`
+#[no_mangle]
+pub unsafe extern "C" fn ac_step_frame(core: *mut ACCore) -> ACStatus {
+    let Some(core) = get(core) else { return ACStatus::NullPointer };
+    core.emulator.run_frame(&core.keyboard, core.new_keypress.take(), core.cycles_per_frame);
+    match core.emulator.fault() {
+        Some(fault) => core.fail(ACStatus::Fault, format!("the emulator stopped at {:03X}: {:?}", core.emulator.pc(), fault)),
+        None => ACStatus::Ok,
+    }
+}
+
+/// # Safety
+/// `core` must be null or from `ac_new`
+#[no_mangle]
+pub unsafe extern "C" fn ac_fault(core: *const ACCore) -> ACFaultKind {
+    match core.as_ref().and_then(|core| core.emulator.fault()) {
+        None => ACFaultKind::None,
+        Some(ACFault::StackOverflow) => ACFaultKind::StackOverflow,
+        Some(ACFault::PcOutOfBounds) => ACFaultKind::PcOutOfBounds,
+    }
+}
+
+/// Copies the screen into `out`, `AC_FRAMEBUFFER_SIZE` bytes a row at a time,
+/// 8 pixels to a byte with the leftmost in the highest bit
+///
+/// # Safety
+/// `core` must be from `ac_new`, and `out` point to `AC_FRAMEBUFFER_SIZE` writable bytes
+#[no_mangle]
+pub unsafe extern "C" fn ac_framebuffer(core: *const ACCore, out: *mut u8) -> ACStatus {
+    let Some(core) = core.as_ref() else { return ACStatus::NullPointer };
+    if out.is_null() {
+        return ACStatus::NullPointer;
+    }
+    let packed = core.emulator.framebuffer().packed();
+    ptr::copy_nonoverlapping(packed.as_ptr(), out, packed.len());
+    ACStatus::Ok
+}
+
+/// Whether the pixel at `x`, `y` is on, false if it's off the screen
+///
+/// # Safety
+/// `core` must be null or from `ac_new`
+#[no_mangle]
+pub unsafe extern "C" fn ac_pixel(core: *const ACCore, x: u32, y: u32) -> bool {
+    match core.as_ref() {
+        Some(core) if x < AC_SCREEN_WIDTH && y < AC_SCREEN_HEIGHT => core.emulator.framebuffer().get_pixel(x as usize, y as usize),
+        _ => false,
+    }
+}
+
+/// Whether the sound timer is running, and a tone should be playing
+///
+/// # Safety
+/// `core` must be null or from `ac_new`
+#[no_mangle]
+pub unsafe extern "C" fn ac_sound(core: *const ACCore) -> bool {
+    core.as_ref().is_some_and(|core| core.emulator.should_bleep())
+}
+
+/// Register `VX`, 0 if `x` is past `0xF`
+///
+/// # Safety
+/// `core` must be null or from `ac_new`
+#[no_mangle]
+pub unsafe extern "C" fn ac_register(core: *const ACCore, x: u8) -> u8 {
+    match core.as_ref() {
+        Some(core) if x < 16 => core.emulator.reg(x as usize),
+        _ => 0,
+    }
+}
+
+/// # Safety
+/// `core` must be null or from `ac_new`
+#[no_mangle]
+pub unsafe extern "C" fn ac_pc(core: *const ACCore) -> u16 {
+    core.as_ref().map_or(0, |core| core.emulator.pc())
+}
+
+/// # Safety
+/// `core` must be null or from `ac_new`
+#[no_mangle]
+pub unsafe extern "C" fn ac_index(core: *const ACCore) -> u16 {
+    core.as_ref().map_or(0, |core| core.emulator.index())
+}
+
+/// Copies `len` bytes of memory from `address` into `out`, wrapping around at the end of memory
+///
+/// # Safety
+/// `core` must be from `ac_new`, and `out` point to `len` writable bytes
+#[no_mangle]
+pub unsafe extern "C" fn ac_read_memory(core: *const ACCore, address: u16, out: *mut u8, len: usize) -> ACStatus {
+    let Some(core) = core.as_ref() else { return ACStatus::NullPointer };
+    if out.is_null() && len > 0 {
+        return ACStatus::NullPointer;
+    }
+    let memory = core.emulator.memory();
+    for n in 0..len {
+        *out.add(n) = memory[(address as usize + n) % AC_MEMORY_SIZE];
+    }
+    ACStatus::Ok
+}
+
+/// Copies `len` bytes from `data` into memory at `address`, wrapping around at the end of memory
+///
+/// # Safety
+/// `core` must be from `ac_new`, and `data` point to `len` readable bytes
+#[no_mangle]
+pub unsafe extern "C" fn ac_write_memory(core: *mut ACCore, address: u16, data: *const u8, len: usize) -> ACStatus {
+    let Some(core) = get(core) else { return ACStatus::NullPointer };
+    let Some(data) = bytes(data, len) else { return core.fail(ACStatus::NullPointer, "data is null") };
+    core.emulator.poke(address, data);
+    ACStatus::Ok
+}
+
+/// Saves the emulator into `out` if it fits in `capacity` bytes, returning how many bytes the state takes
+/// either way. Call with a null `out` to find out how big a buffer to use
+///
+/// # Safety
+/// `core` must be from `ac_new`, and `out` be null or point to `capacity` writable bytes
+#[no_mangle]
+pub unsafe extern "C" fn ac_save_state(core: *const ACCore, out: *mut u8, capacity: usize) -> usize {
+    let Some(core) = core.as_ref() else { return 0 };
+    let state = core.emulator.save_state();
+    if !out.is_null() && capacity >= state.len() {
+        ptr::copy_nonoverlapping(state.as_ptr(), out, state.len());
+    }
+    state.len()
+}
+
+/// Loads a state from `ac_save_state`, leaving the emulator as it was if it doesn't load
+///
+/// # Safety
+/// `core` must be from `ac_new`, and `state` point to `len` readable bytes
+#[no_mangle]
+pub unsafe extern "C" fn ac_load_state(core: *mut ACCore, state: *const u8, len: usize) -> ACStatus {
+    let Some(core) = get(core) else { return ACStatus::NullPointer };
+    let Some(state) = bytes(state, len) else { return core.fail(ACStatus::NullPointer, "state is null") };
+    match core.emulator.load_state(state) {
+        Ok(()) => ACStatus::Ok,
+        Err(e) => core.fail(ACStatus::BadState, e),
+    }
+}
@@ -0,0 +1,44 @@
+//! Builds tests/c/test_ate_chip.c against the header and the static library, and runs it
+#![cfg(unix)]
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+fn manifest_dir() -> &'static Path {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+}
+
+/// Where cargo put the libraries, next to the `deps` directory this test runs from
+fn library_dir() -> PathBuf {
+    let exe = std::env::current_exe().expect("tests know where they are");
+    exe.parent().and_then(Path::parent).expect("tests run from target/<profile>/deps").to_path_buf()
+}
+
+fn run(command: &mut Command) {
+    let output = command.output().unwrap_or_else(|e| panic!("couldn't run {:?}: {}", command, e));
+    print!("{}", String::from_utf8_lossy(&output.stdout));
+    eprint!("{}", String::from_utf8_lossy(&output.stderr));
+    assert!(output.status.success(), "{:?} failed", command);
+}
+
+#[test]
+fn c_api() {
+    let exe = Path::new(env!("CARGO_TARGET_TMPDIR")).join("test_ate_chip");
+    let cc = std::env::var("CC").unwrap_or_else(|_| "cc".to_string());
+    run(Command::new(cc)
+        .args(["-std=c99", "-Wall", "-Wextra", "-Werror", "-I"])
+        .arg(manifest_dir().join("include"))
+        .arg(manifest_dir().join("tests/c/test_ate_chip.c"))
+        .arg(library_dir().join("libate_chip_capi.a"))
+        .args(["-lpthread", "-lm", "-ldl", "-lrt", "-o"])
+        .arg(&exe));
+    run(&mut Command::new(&exe));
+}
+
+/// The header is usable from C++ as well
+#[test]
+fn cpp_header() {
+    let cxx = std::env::var("CXX").unwrap_or_else(|_| "c++".to_string());
+    run(Command::new(cxx)
+        .args(["-fsyntax-only", "-Wall", "-Wextra", "-Werror", "-x", "c++"])
+        .arg(manifest_dir().join("include/ate_chip.h")));
+}
@@ -0,0 +1,182 @@
+/* Tests for the C API, against nothing but the header. Built and run by tests/c.rs */
+#include <stdio.h>
+#include <string.h>
+
+#include "ate_chip.h"
+
+static int failures = 0;
+
+#define CHECK(cond)                                                        \
+    do {                                                                   \
+        if (!(cond)) {                                                     \
+            fprintf(stderr, "%s:%d: failed: %s\n", __FILE__, __LINE__, #cond); \
+            failures++;                                                    \
+        }                                                                  \
+    } while (0)
+
+/* draws the 5 from the font at 0, 0 then waits for a key into V1 */
+static const uint8_t DRAW_AND_WAIT[] = {0x61, 0x05, 0xF1, 0x29, 0xD0, 0x05, 0xF1, 0x0A, 0x12, 0x08};
+/* calls itself until the stack runs out */
+static const uint8_t RECURSE[] = {0x22, 0x00};
+/* picks a random number into V0 forever */
+static const uint8_t RANDOM[] = {0xC0, 0xFF, 0x12, 0x00};
+
+static ACCore *load(const uint8_t *rom, size_t len) {
+    ACCore *core = ac_new();
+    CHECK(core != NULL);
+    CHECK(ac_load_rom(core, rom, len) == AC_STATUS_OK);
+    return core;
+}
+
+static void test_version(void) {
+    CHECK(ac_api_version() == AC_API_VERSION);
+}
+
+static void test_framebuffer(void) {
+    ACCore *core = load(DRAW_AND_WAIT, sizeof DRAW_AND_WAIT);
+    CHECK(ac_step_frame(core) == AC_STATUS_OK);
+
+    uint8_t screen[AC_FRAMEBUFFER_SIZE];
+    CHECK(ac_framebuffer(core, screen) == AC_STATUS_OK);
+    const uint8_t five[] = {0xF0, 0x80, 0xF0, 0x10, 0xF0};
+    for (int row = 0; row < 5; row++) {
+        CHECK(screen[row * AC_SCREEN_WIDTH / 8] == five[row]);
+    }
+    CHECK(ac_pixel(core, 0, 0));
+    CHECK(!ac_pixel(core, 4, 0));
+    CHECK(!ac_pixel(core, AC_SCREEN_WIDTH, 0));
+    CHECK(ac_framebuffer(core, NULL) == AC_STATUS_NULL_POINTER);
+    ac_free(core);
+}
+
+static void test_keys(void) {
+    ACCore *core = load(DRAW_AND_WAIT, sizeof DRAW_AND_WAIT);
+    CHECK(ac_step_frame(core) == AC_STATUS_OK);
+    CHECK(ac_pc(core) == 0x208);
+
+    CHECK(ac_set_key(core, 0x7, true) == AC_STATUS_OK);
+    CHECK(ac_step_frame(core) == AC_STATUS_OK);
+    CHECK(ac_register(core, 1) == 0x7);
+    CHECK(ac_register(core, 16) == 0);
+
+    CHECK(ac_set_key(core, 0x10, true) == AC_STATUS_INVALID_ARGUMENT);
+    CHECK(strlen(ac_last_error(core)) > 0);
+    CHECK(ac_set_key(core, 0x7, false) == AC_STATUS_OK);
+    ac_free(core);
+}
+
+static void test_save_state(void) {
+    ACCore *core = load(DRAW_AND_WAIT, sizeof DRAW_AND_WAIT);
+    CHECK(ac_step_frame(core) == AC_STATUS_OK);
+
+    size_t size = ac_save_state(core, NULL, 0);
+    CHECK(size > AC_MEMORY_SIZE);
+    uint8_t state[8192];
+    CHECK(size <= sizeof state);
+    CHECK(ac_save_state(core, state, 1) == size);
+    CHECK(ac_save_state(core, state, sizeof state) == size);
+
+    const uint8_t junk[] = {1, 2, 3};
+    CHECK(ac_write_memory(core, 0x300, junk, sizeof junk) == AC_STATUS_OK);
+    uint8_t read[3];
+    CHECK(ac_read_memory(core, 0x300, read, sizeof read) == AC_STATUS_OK);
+    CHECK(memcmp(read, junk, sizeof junk) == 0);
+
+    CHECK(ac_load_state(core, state, size) == AC_STATUS_OK);
+    CHECK(ac_read_memory(core, 0x300, read, sizeof read) == AC_STATUS_OK);
+    CHECK(read[0] == 0 && read[1] == 0 && read[2] == 0);
+    CHECK(ac_pc(core) == 0x208);
+
+    /* a bad state leaves the emulator alone */
+    CHECK(ac_load_state(core, junk, sizeof junk) == AC_STATUS_BAD_STATE);
+    CHECK(strlen(ac_last_error(core)) > 0);
+    CHECK(ac_pc(core) == 0x208);
+    ac_free(core);
+}
+
+static void test_memory_wraps(void) {
+    ACCore *core = ac_new();
+    const uint8_t bytes[] = {0xAA, 0xBB};
+    CHECK(ac_write_memory(core, AC_MEMORY_SIZE - 1, bytes, sizeof bytes) == AC_STATUS_OK);
+    uint8_t read[2];
+    CHECK(ac_read_memory(core, AC_MEMORY_SIZE - 1, read, sizeof read) == AC_STATUS_OK);
+    CHECK(read[0] == 0xAA && read[1] == 0xBB);
+    CHECK(ac_read_memory(core, 0, read, 1) == AC_STATUS_OK);
+    CHECK(read[0] == 0xBB);
+    ac_free(core);
+}
+
+static void test_fault(void) {
+    ACCore *core = load(RECURSE, sizeof RECURSE);
+    CHECK(ac_fault(core) == AC_FAULT_KIND_NONE);
+    CHECK(ac_set_cycles_per_frame(core, 100) == AC_STATUS_OK);
+    CHECK(ac_step_frame(core) == AC_STATUS_FAULT);
+    CHECK(ac_fault(core) == AC_FAULT_KIND_STACK_OVERFLOW);
+    CHECK(strstr(ac_last_error(core), "StackOverflow") != NULL);
+    ac_free(core);
+}
+
+static void test_quirks_and_seed(void) {
+    ACCore *a = load(RANDOM, sizeof RANDOM);
+    ACCore *b = load(RANDOM, sizeof RANDOM);
+    CHECK(ac_set_quirks(a, "cosmac") == AC_STATUS_OK);
+    CHECK(ac_set_quirks(b, "cosmac") == AC_STATUS_OK);
+    CHECK(ac_set_quirks(a, "nonsense") == AC_STATUS_INVALID_ARGUMENT);
+    CHECK(ac_set_quirks(a, NULL) == AC_STATUS_NULL_POINTER);
+    CHECK(ac_seed(a, 42) == AC_STATUS_OK);
+    CHECK(ac_seed(b, 42) == AC_STATUS_OK);
+    for (int frame = 0; frame < 10; frame++) {
+        CHECK(ac_step_frame(a) == AC_STATUS_OK);
+        CHECK(ac_step_frame(b) == AC_STATUS_OK);
+        CHECK(ac_register(a, 0) == ac_register(b, 0));
+    }
+    ac_free(a);
+    ac_free(b);
+}
+
+static void test_seed_before_load(void) {
+    ACCore *a = ac_new();
+    ACCore *b = ac_new();
+    CHECK(ac_seed(a, 42) == AC_STATUS_OK);
+    CHECK(ac_seed(b, 42) == AC_STATUS_OK);
+    CHECK(ac_load_rom(a, RANDOM, sizeof RANDOM) == AC_STATUS_OK);
+    CHECK(ac_load_rom(b, RANDOM, sizeof RANDOM) == AC_STATUS_OK);
+    for (int frame = 0; frame < 10; frame++) {
+        CHECK(ac_step_frame(a) == AC_STATUS_OK);
+        CHECK(ac_step_frame(b) == AC_STATUS_OK);
+        CHECK(ac_register(a, 0) == ac_register(b, 0));
+    }
+    ac_free(a);
+    ac_free(b);
+}
+
+static void test_null(void) {
+    CHECK(ac_step_frame(NULL) == AC_STATUS_NULL_POINTER);
+    CHECK(ac_load_rom(NULL, RANDOM, sizeof RANDOM) == AC_STATUS_NULL_POINTER);
+    CHECK(ac_save_state(NULL, NULL, 0) == 0);
+    CHECK(ac_last_error(NULL) != NULL);
+    ac_free(NULL);
+
+    ACCore *core = ac_new();
+    CHECK(ac_load_rom(core, NULL, 4) == AC_STATUS_NULL_POINTER);
+    CHECK(ac_load_rom(core, NULL, 0) == AC_STATUS_OK);
+    ac_free(core);
+}
+
+int main(void) {
+    test_version();
+    test_framebuffer();
+    test_keys();
+    test_save_state();
+    test_memory_wraps();
+    test_fault();
+    test_quirks_and_seed();
+    test_seed_before_load();
+    test_null();
+    if (failures) {
+        fprintf(stderr, "%d checks failed\n", failures);
+        return 1;
+    }
+    printf("all C API tests passed\n");
+    return 0;
+}
@@ -0,0 +1,102 @@
+//! Linux pretending to be a board: no std, no allocator, just libc for the clock and for printing the panel.
+//! Runs a rom that draws `AC` for a second, then prints the panel as text and exits
+#![no_std]
+#![no_main]
+use ate_chip::emulator::ACEmulator;
+use ate_chip::platform::{self, ACClock, ACInput, ACXorShift};
+use ate_chip_embedded::{ACPanelBus, ACPanelPages, ACSsd1306, PANEL_HEIGHT, PANEL_WIDTH};
+
+/// Draws the font's A and C in the top left corner, then loops forever
+const ROM: [u8; 24] = [
+    0x60, 0x0A, // V0 = A
+    0xF0, 0x29, // I = font(V0)
+    0x61, 0x00, // V1 = 0
+    0x62, 0x00, // V2 = 0
+    0xD1, 0x25, // draw at V1, V2
+    0x60, 0x0C, // V0 = C
+    0xF0, 0x29, // I = font(V0)
+    0x61, 0x05, // V1 = 5
+    0xD1, 0x25, // draw at V1, V2
+    0x12, 0x12, // jump here
+    0x00, 0x00, 0x00, 0x00,
+];
+const FRAMES: u32 = 60;
+
+#[repr(C)]
+struct Timespec {
+    tv_sec: i64,
+    tv_nsec: i64,
+}
+
+const CLOCK_MONOTONIC: i32 = 1;
+
+#[link(name = "c")]
+extern "C" {
+    fn clock_gettime(clock: i32, time: *mut Timespec) -> i32;
+    fn write(fd: i32, buf: *const u8, count: usize) -> isize;
+    fn abort() -> !;
+}
+
+struct ACMonotonic;
+
+impl ACClock for ACMonotonic {
+    fn micros(&self) -> u64 {
+        let mut time = Timespec { tv_sec: 0, tv_nsec: 0 };
+        unsafe { clock_gettime(CLOCK_MONOTONIC, &mut time) };
+        time.tv_sec as u64 * 1_000_000 + time.tv_nsec as u64 / 1_000
+    }
+}
+
+/// Keeps the last frame, a real board would send it over I2C here
+struct ACLastFrame(ACPanelPages);
+
+impl ACPanelBus for ACLastFrame {
+    type Error = ();
+
+    fn flush(&mut self, pages: &ACPanelPages, _tone: bool) -> Result<(), ()> {
+        self.0 = *pages;
+        Ok(())
+    }
+}
+
+fn print(bytes: &[u8]) {
+    unsafe { write(1, bytes.as_ptr(), bytes.len()) };
+}
+
+#[no_mangle]
+pub extern "C" fn main(_argc: i32, _argv: *const *const u8) -> i32 {
+    let mut emulator = ACEmulator::with_random(ACXorShift::new(1));
+    emulator.load_rom(ROM);
+    let mut display = ACSsd1306::new(ACLastFrame([0; PANEL_WIDTH * PANEL_HEIGHT / 8]));
+
+    let mut frame = 0;
+    let ran = platform::run(&mut emulator, &ACMonotonic, &mut display, 10, |_keyboard| {
+        frame += 1;
+        if frame > FRAMES { ACInput::Quit } else { ACInput::Continue(None) }
+    });
+    if ran.is_err() {
+        return 1;
+    }
+
+    // just the corner with the letters in, a line at a time
+    for y in 0..16 {
+        let mut line = [b' '; 25];
+        for (x, c) in line[..24].iter_mut().enumerate() {
+            if display.panel_pixel(x, y) {
+                *c = b'#';
+            }
+        }
+        line[24] = b'\n';
+        print(&line);
+    }
+    0
+}
+
+#[panic_handler]
+fn panic(_info: &core::panic::PanicInfo) -> ! {
+    unsafe { abort() }
+}
+
+/// The host's prebuilt libcore was built to unwind and links against this, though with `panic = "abort"` nothing calls it
+#[no_mangle]
+extern "C" fn rust_eh_personality() {}
@@ -0,0 +1,61 @@
+//! ate-chip on a microcontroller, with no std and no allocator: the interpreter from `ate_chip` with the
+//! `std` feature off, and a display for the 128x64 SSD1306 style panels boards usually have.
+//!
+//! A board brings an [`ACPanelBus`] to send the panel its pixels, an [`ACClock`](ate_chip::platform::ACClock) and
+//! somewhere to read keys from, then hands them to [`ate_chip::platform::run`]. `src/bin/linux.rs` does
+//! that with Linux standing in for the board
+#![no_std]
+use ate_chip::emulator::ACFramebuffer;
+use ate_chip::platform::ACDisplay;
+use ate_chip::{SCREEN_HEIGHT, SCREEN_WIDTH};
+
+pub const PANEL_WIDTH: usize = 128;
+pub const PANEL_HEIGHT: usize = 64;
+/// Each chip-8 pixel is this many panel pixels across and down
+const SCALE: usize = PANEL_WIDTH / SCREEN_WIDTH as usize;
+
+/// The panel's memory: 8 pages of 8 pixel tall columns, a byte per column with the top pixel in the lowest bit
+pub type ACPanelPages = [u8; PANEL_WIDTH * PANEL_HEIGHT / 8];
+
+/// How the pixels get to the panel, usually over I2C or SPI
+pub trait ACPanelBus {
+    type Error;
+
+    /// Sends the whole panel, `tone` being whether the buzzer should be on
+    fn flush(&mut self, pages: &ACPanelPages, tone: bool) -> Result<(), Self::Error>;
+}
+
+/// Shows the screen at twice the size on an SSD1306 style panel
+#[derive(Debug)]
+pub struct ACSsd1306<B> {
+    pub bus: B,
+    pages: ACPanelPages,
+}
+
+impl<B: ACPanelBus> ACSsd1306<B> {
+    pub fn new(bus: B) -> Self {
+        Self { bus, pages: [0; PANEL_WIDTH * PANEL_HEIGHT / 8] }
+    }
+
+    /// Whether the panel pixel at `x`, `y` is lit, as of the last frame
+    pub fn panel_pixel(&self, x: usize, y: usize) -> bool {
+        self.pages[y / 8 * PANEL_WIDTH + x] & 1 << (y % 8) != 0
+    }
+}
+
+impl<B: ACPanelBus> ACDisplay for ACSsd1306<B> {
+    type Error = B::Error;
+
+    fn show(&mut self, framebuffer: &ACFramebuffer, tone: bool) -> Result<(), B::Error> {
+        for (n, column) in self.pages.iter_mut().enumerate() {
+            let (x, page) = (n % PANEL_WIDTH, n / PANEL_WIDTH);
+            *column = (0..8).fold(0, |column, bit| {
+                let y = page * 8 + bit;
+                let lit = framebuffer.get_pixel(x / SCALE, y / SCALE);
+                column | (lit as u8) << bit
+            });
+        }
+        debug_assert_eq!(PANEL_HEIGHT / SCALE, SCREEN_HEIGHT as usize);
+        self.bus.flush(&self.pages, tone)
+    }
+}
@@ -0,0 +1,45 @@
+use std::path::Path;
+use std::process::Command;
+
+/// Targets with no OS at all, so nothing can sneak std in
+const BARE_METAL: [&str; 3] = ["thumbv7em-none-eabihf", "thumbv6m-none-eabi", "riscv32imc-unknown-none-elf"];
+
+#[test]
+fn runs_on_linux_without_std() {
+    // linking would fail with two panic handlers if anything in the build used std
+    let output = Command::new(env!("CARGO_BIN_EXE_linux")).output().unwrap();
+    assert!(output.status.success());
+    let panel = String::from_utf8(output.stdout).unwrap();
+    let lines: Vec<&str> = panel.lines().collect();
+    // the tops and bottoms of A and C, each chip-8 pixel twice the size
+    assert_eq!(lines[0], "########  ########      ");
+    assert_eq!(lines[1], lines[0]);
+    assert_eq!(lines[2], "##    ##  ##            ");
+    assert_eq!(lines[9], "##    ##  ########      ");
+    assert!(lines[10..].iter().all(|line| line.trim().is_empty()));
+}
+
+#[test]
+#[ignore = "needs a bare metal target, run with `cargo test -- --ignored`"]
+fn builds_for_bare_metal() {
+    let rustc = std::env::var("RUSTC").unwrap_or_else(|_| "rustc".to_string());
+    let sysroot = Command::new(rustc).args(["--print", "sysroot"]).output().unwrap();
+    let sysroot = String::from_utf8(sysroot.stdout).unwrap();
+    let installed: Vec<&str> = BARE_METAL
+        .into_iter()
+        .filter(|target| Path::new(sysroot.trim()).join("lib/rustlib").join(target).exists())
+        .collect();
+    assert!(!installed.is_empty(), "no bare metal targets installed, `rustup target add {}` to build for one", BARE_METAL[0]);
+
+    let target_dir = std::env::temp_dir().join("ate-chip-embedded-bare-metal");
+    for target in installed {
+        let status = Command::new(env!("CARGO"))
+            .args(["build", "--lib", "--target", target])
+            .arg("--target-dir")
+            .arg(&target_dir)
+            .current_dir(env!("CARGO_MANIFEST_DIR"))
+            .status()
+            .unwrap();
+        assert!(status.success(), "failed to build for {}", target);
+    }
+}
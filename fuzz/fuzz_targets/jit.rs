@@ -0,0 +1,51 @@
+//! Runs an arbitrary rom with arbitrary quirks and key presses, both interpreted and compiled.
+//!
+//! The two should never differ, however much the rom rewrites itself
+#![no_main]
+
+use arbitrary::Arbitrary;
+use libfuzzer_sys::fuzz_target;
+
+use ate_chip::emulator::ACEmulator;
+use ate_chip::keyboard::{ACKey, ACKeyboard};
+use ate_chip::quirks::ACQuirks;
+
+#[derive(Arbitrary, Debug)]
+struct Input {
+    quirks: u8,
+    seed: u64,
+    /// (key, pressed) at the start of each frame
+    frames: Vec<Option<(u8, bool)>>,
+    cycles_per_frame: u8,
+    rom: Vec<u8>,
+}
+
+fuzz_target!(|input: Input| {
+    let quirks = ACQuirks::from_bits(input.quirks & 0x3F).unwrap();
+    let mut interpreted = ACEmulator::new();
+    let mut jitted = ACEmulator::new();
+    jitted.enable_jit().unwrap();
+    for emulator in [&mut interpreted, &mut jitted] {
+        emulator.quirks = quirks;
+        emulator.seed(input.seed);
+        emulator.load_rom(&input.rom);
+    }
+
+    let mut keyboard = ACKeyboard::new();
+    for (frame, event) in input.frames.iter().take(256).enumerate() {
+        let mut new_keypress = None;
+        if let Some((key, pressed)) = event {
+            let key = ACKey::from_hex(key & 0x0F).unwrap();
+            if *pressed {
+                keyboard.press(key);
+                new_keypress = Some(key);
+            } else {
+                keyboard.release(key);
+            }
+        }
+        for emulator in [&mut interpreted, &mut jitted] {
+            emulator.run_frame(&keyboard, new_keypress, input.cycles_per_frame as usize);
+        }
+        assert!(interpreted.save_state() == jitted.save_state(), "differ after frame {}", frame);
+    }
+});
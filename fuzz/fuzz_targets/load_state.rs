@@ -0,0 +1,16 @@
+//! Feeds arbitrary bytes to `load_state`, which should reject anything
+//! that isn't a state it could have saved
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+use ate_chip::emulator::ACEmulator;
+
+fuzz_target!(|data: &[u8]| {
+    let mut emulator = ACEmulator::new();
+    let before = emulator.save_state();
+    match emulator.load_state(data) {
+        Ok(()) => assert_eq!(emulator.save_state(), data),
+        Err(_) => assert_eq!(emulator.save_state(), before),
+    }
+});
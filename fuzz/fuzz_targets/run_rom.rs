@@ -0,0 +1,61 @@
+//! Runs an arbitrary rom with arbitrary quirks and key presses.
+//!
+//! Nothing the rom does should make the emulator panic, and an emulator restored
+//! from a save state part way through should carry on exactly like the original
+#![no_main]
+
+use arbitrary::Arbitrary;
+use libfuzzer_sys::fuzz_target;
+
+use ate_chip::emulator::ACEmulator;
+use ate_chip::keyboard::{ACKey, ACKeyboard};
+use ate_chip::quirks::ACQuirks;
+
+#[derive(Arbitrary, Debug)]
+struct Input {
+    quirks: u8,
+    /// (key, pressed) at the start of each frame
+    frames: Vec<Option<(u8, bool)>>,
+    cycles_per_frame: u8,
+    rom: Vec<u8>,
+}
+
+/// Turns the input for a frame into a key press or release, returning the newly pressed key
+fn press(keyboard: &mut ACKeyboard, event: Option<(u8, bool)>) -> Option<ACKey> {
+    let (key, pressed) = event?;
+    let key = ACKey::from_hex(key & 0x0F).unwrap();
+    if pressed {
+        keyboard.press(key);
+        Some(key)
+    } else {
+        keyboard.release(key);
+        None
+    }
+}
+
+fuzz_target!(|input: Input| {
+    let mut emulator = ACEmulator::new();
+    emulator.quirks = ACQuirks::from_bits(input.quirks & 0x3F).unwrap();
+    emulator.load_rom(input.rom);
+    let cycles = input.cycles_per_frame as usize;
+    let frames = &input.frames[..input.frames.len().min(256)];
+    let (before, after) = frames.split_at(frames.len() / 2);
+
+    let mut keyboard = ACKeyboard::new();
+    for event in before {
+        let new_keypress = press(&mut keyboard, *event);
+        emulator.run_frame(&keyboard, new_keypress, cycles);
+    }
+
+    // a fresh emulator knows nothing the state doesn't tell it, including where the random numbers are up to
+    let state = emulator.save_state();
+    let mut restored = ACEmulator::new();
+    restored.load_state(&state).unwrap();
+    for event in after {
+        let new_keypress = press(&mut keyboard, *event);
+        emulator.run_frame(&keyboard, new_keypress, cycles);
+        restored.run_frame(&keyboard, new_keypress, cycles);
+        assert!(restored.framebuffer() == emulator.framebuffer());
+        assert_eq!(restored.save_state(), emulator.save_state());
+    }
+});
@@ -0,0 +1,230 @@
+//! A tiny libretro frontend for checking the core without RetroArch.
+//!
+//! ```sh
+//! cargo build -p ate-chip-libretro
+//! cargo run -p ate-chip-libretro --example frontend -- target/debug/libate_chip_libretro.so pong.ch8 \
+//!     --frames 300 --hold 60:q:10 --option ate_chip_palette=green
+//! ```
+//!
+//! Loads the core with `dlopen`, runs the rom for `--frames` frames holding keys (on the keyboard) as asked,
+//! checks that a save state taken at the end replays the same frames, then prints the last frame
+use std::collections::HashMap;
+use std::env;
+use std::ffi::{CStr, CString};
+use std::os::raw::{c_uint, c_void};
+use std::process::exit;
+use std::sync::Mutex;
+
+use ate_chip_libretro::retro::*;
+use libloading::{Library, Symbol};
+
+/// What the core has told us, and what we tell it
+#[derive(Default)]
+struct Frontend {
+    /// option values from `--option`, falling back to the default declared by the core
+    options: HashMap<String, CString>,
+    /// the last frame, as (width, height, XRGB8888 pixels)
+    video: (usize, usize, Vec<u32>),
+    /// stereo samples played so far
+    samples: usize,
+    /// the keys (as `RETROK_*` codes) held down this frame
+    held: Vec<c_uint>,
+}
+
+static FRONTEND: Mutex<Option<Frontend>> = Mutex::new(None);
+
+fn with_frontend<T>(f: impl FnOnce(&mut Frontend) -> T) -> T {
+    f(FRONTEND.lock().unwrap().get_or_insert_with(Frontend::default))
+}
+
+unsafe extern "C" fn environment(cmd: c_uint, data: *mut c_void) -> bool {
+    match cmd {
+        RETRO_ENVIRONMENT_SET_PIXEL_FORMAT => *(data as *const c_uint) == RETRO_PIXEL_FORMAT_XRGB8888,
+        RETRO_ENVIRONMENT_SET_VARIABLES => {
+            let mut var = data as *const retro_variable;
+            while !(*var).key.is_null() {
+                let key = CStr::from_ptr((*var).key).to_string_lossy().into_owned();
+                let value = CStr::from_ptr((*var).value).to_string_lossy().into_owned();
+                println!("option {}: {}", key, value);
+                // "Description; default|other|..."
+                let default = value.split("; ").nth(1).and_then(|v| v.split('|').next()).unwrap_or_default();
+                with_frontend(|f| {
+                    f.options.entry(key).or_insert_with(|| CString::new(default).unwrap());
+                });
+                var = var.add(1);
+            }
+            true
+        }
+        RETRO_ENVIRONMENT_GET_VARIABLE => {
+            let var = data as *mut retro_variable;
+            let key = CStr::from_ptr((*var).key).to_string_lossy().into_owned();
+            with_frontend(|f| match f.options.get(&key) {
+                // the strings live in FRONTEND until the end of the program
+                Some(value) => {
+                    (*var).value = value.as_ptr();
+                    true
+                }
+                None => false,
+            })
+        }
+        RETRO_ENVIRONMENT_GET_VARIABLE_UPDATE => {
+            *(data as *mut bool) = false;
+            true
+        }
+        _ => false,
+    }
+}
+
+unsafe extern "C" fn video_refresh(data: *const c_void, width: c_uint, height: c_uint, pitch: usize) {
+    let (width, height) = (width as usize, height as usize);
+    let mut pixels = Vec::with_capacity(width * height);
+    for y in 0..height {
+        let row = (data as *const u8).add(y * pitch) as *const u32;
+        pixels.extend_from_slice(std::slice::from_raw_parts(row, width));
+    }
+    with_frontend(|f| f.video = (width, height, pixels));
+}
+
+unsafe extern "C" fn audio_sample(_left: i16, _right: i16) {
+    with_frontend(|f| f.samples += 1);
+}
+
+unsafe extern "C" fn audio_sample_batch(_data: *const i16, frames: usize) -> usize {
+    with_frontend(|f| f.samples += frames);
+    frames
+}
+
+unsafe extern "C" fn input_poll() {}
+
+unsafe extern "C" fn input_state(port: c_uint, device: c_uint, _index: c_uint, id: c_uint) -> i16 {
+    with_frontend(|f| (port == 0 && device == RETRO_DEVICE_KEYBOARD && f.held.contains(&id)) as i16)
+}
+
+fn usage() -> ! {
+    eprintln!("usage: frontend <core> <rom> [--frames N] [--hold FRAME:KEY:FRAMES]... [--option KEY=VALUE]...");
+    exit(2)
+}
+
+fn main() {
+    let mut args = env::args().skip(1);
+    let (Some(core_path), Some(rom_path)) = (args.next(), args.next()) else { usage() };
+    let mut frames = 300;
+    // (first frame, key, frames held)
+    let mut holds: Vec<(usize, c_uint, usize)> = vec![];
+    while let Some(arg) = args.next() {
+        let value = args.next().unwrap_or_else(|| usage());
+        match arg.as_str() {
+            "--frames" => frames = value.parse().unwrap_or_else(|_| usage()),
+            "--hold" => {
+                let parts: Vec<&str> = value.split(':').collect();
+                let [frame, key, held] = parts[..] else { usage() };
+                let key = key.chars().next().unwrap_or_else(|| usage());
+                holds.push((frame.parse().unwrap_or_else(|_| usage()), key as c_uint, held.parse().unwrap_or_else(|_| usage())));
+            }
+            "--option" => {
+                let (key, value) = value.split_once('=').unwrap_or_else(|| usage());
+                with_frontend(|f| f.options.insert(key.to_string(), CString::new(value).unwrap()));
+            }
+            _ => usage(),
+        }
+    }
+    let rom = std::fs::read(&rom_path).expect("read the rom");
+
+    unsafe {
+        let lib = Library::new(&core_path).expect("load the core");
+        macro_rules! sym {
+            ($name:ident: $ty:ty) => {
+                let $name: Symbol<$ty> = lib.get(concat!(stringify!($name), "\0").as_bytes()).expect(stringify!($name));
+            };
+        }
+        sym!(retro_api_version: unsafe extern "C" fn() -> c_uint);
+        sym!(retro_get_system_info: unsafe extern "C" fn(*mut retro_system_info));
+        sym!(retro_get_system_av_info: unsafe extern "C" fn(*mut retro_system_av_info));
+        sym!(retro_set_environment: unsafe extern "C" fn(retro_environment_t));
+        sym!(retro_set_video_refresh: unsafe extern "C" fn(retro_video_refresh_t));
+        sym!(retro_set_audio_sample: unsafe extern "C" fn(retro_audio_sample_t));
+        sym!(retro_set_audio_sample_batch: unsafe extern "C" fn(retro_audio_sample_batch_t));
+        sym!(retro_set_input_poll: unsafe extern "C" fn(retro_input_poll_t));
+        sym!(retro_set_input_state: unsafe extern "C" fn(retro_input_state_t));
+        sym!(retro_init: unsafe extern "C" fn());
+        sym!(retro_deinit: unsafe extern "C" fn());
+        sym!(retro_load_game: unsafe extern "C" fn(*const retro_game_info) -> bool);
+        sym!(retro_unload_game: unsafe extern "C" fn());
+        sym!(retro_run: unsafe extern "C" fn());
+        sym!(retro_serialize_size: unsafe extern "C" fn() -> usize);
+        sym!(retro_serialize: unsafe extern "C" fn(*mut c_void, usize) -> bool);
+        sym!(retro_unserialize: unsafe extern "C" fn(*const c_void, usize) -> bool);
+
+        assert_eq!(retro_api_version(), RETRO_API_VERSION, "api version");
+        let mut info = std::mem::zeroed::<retro_system_info>();
+        retro_get_system_info(&mut info);
+        println!(
+            "core: {} {} ({})",
+            CStr::from_ptr(info.library_name).to_string_lossy(),
+            CStr::from_ptr(info.library_version).to_string_lossy(),
+            CStr::from_ptr(info.valid_extensions).to_string_lossy(),
+        );
+
+        retro_set_environment(environment);
+        retro_set_video_refresh(video_refresh);
+        retro_set_audio_sample(audio_sample);
+        retro_set_audio_sample_batch(audio_sample_batch);
+        retro_set_input_poll(input_poll);
+        retro_set_input_state(input_state);
+        retro_init();
+
+        let game = retro_game_info {
+            path: std::ptr::null(),
+            data: rom.as_ptr() as *const c_void,
+            size: rom.len(),
+            meta: std::ptr::null(),
+        };
+        assert!(retro_load_game(&game), "the core refused the rom");
+        let mut av = std::mem::zeroed::<retro_system_av_info>();
+        retro_get_system_av_info(&mut av);
+        println!(
+            "av: {}x{} at {} fps, {} hz audio",
+            av.geometry.base_width, av.geometry.base_height, av.timing.fps, av.timing.sample_rate
+        );
+
+        let run = |frame: usize| {
+            let held = holds
+                .iter()
+                .filter(|(first, _, len)| (*first..first + len).contains(&frame))
+                .map(|(_, key, _)| *key)
+                .collect();
+            with_frontend(|f| f.held = held);
+            retro_run();
+            with_frontend(|f| f.video.2.clone())
+        };
+        for frame in 0..frames {
+            run(frame);
+        }
+
+        // save, run a bit, load and run the same frames again, the way rewind and netplay do
+        let mut state = vec![0u8; retro_serialize_size()];
+        assert!(retro_serialize(state.as_mut_ptr() as *mut c_void, state.len()), "serialize");
+        let first: Vec<Vec<u32>> = (frames..frames + 30).map(run).collect();
+        assert!(retro_unserialize(state.as_ptr() as *const c_void, state.len()), "unserialize");
+        let again: Vec<Vec<u32>> = (frames..frames + 30).map(run).collect();
+        assert!(first == again, "replay after loading the save state differs");
+        println!("save state: {} bytes, replay matches", state.len());
+
+        let (width, height, pixels) = with_frontend(|f| f.video.clone());
+        let samples = with_frontend(|f| f.samples);
+        println!("{} frames, {} audio samples", frames + 60, samples);
+        // the most common colour is taken to be the background
+        let mut counts: HashMap<u32, usize> = HashMap::new();
+        for px in &pixels {
+            *counts.entry(*px).or_default() += 1;
+        }
+        let background = counts.into_iter().max_by_key(|(_, n)| *n).map_or(0, |(px, _)| px);
+        for y in 0..height {
+            let row: String = (0..width).map(|x| if pixels[y * width + x] == background { ' ' } else { '█' }).collect();
+            println!("|{}|", row);
+        }
+
+        retro_unload_game();
+        retro_deinit();
+    }
+}
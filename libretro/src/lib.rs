@@ -0,0 +1,443 @@
+//! ate-chip as a libretro core, so it can be run in RetroArch (or any other libretro frontend)
+//! with its shaders, netplay and rewind.
+//!
+//! The keypad is on the keyboard laid out as `1234`/`qwer`/`asdf`/`zxcv`, and on the first joypad as
+//! [`JOYPAD`]. Quirks, instructions per frame and the palette are core options
+pub mod retro;
+
+use std::ffi::CStr;
+use std::os::raw::{c_char, c_uint, c_void};
+use std::ptr;
+use std::sync::{Mutex, MutexGuard};
+
+use ate_chip::emulator::ACEmulator;
+use ate_chip::keyboard::{ACKey, ACKeyboard};
+use ate_chip::quirks::ACQuirks;
+use ate_chip::{SCREEN_HEIGHT, SCREEN_WIDTH};
+
+use retro::*;
+
+const SAMPLE_RATE: usize = 44100;
+const FPS: usize = 60;
+/// the pitch of the beep, the same as the SDL frontend
+const TONE_HZ: f32 = 440.0;
+const VOLUME: f32 = 0.25;
+/// `CXNN` gives the same numbers every time the game is started, so rewind and netplay agree with each other
+const SEED: u64 = 0;
+
+/// Keypad keys on the retropad, arranged so the d-pad is `2`/`4`/`6`/`8` like most games expect
+pub const JOYPAD: [(c_uint, u8); 12] = [
+    (RETRO_DEVICE_ID_JOYPAD_UP, 0x2),
+    (RETRO_DEVICE_ID_JOYPAD_DOWN, 0x8),
+    (RETRO_DEVICE_ID_JOYPAD_LEFT, 0x4),
+    (RETRO_DEVICE_ID_JOYPAD_RIGHT, 0x6),
+    (RETRO_DEVICE_ID_JOYPAD_A, 0x5),
+    (RETRO_DEVICE_ID_JOYPAD_B, 0x0),
+    (RETRO_DEVICE_ID_JOYPAD_X, 0x1),
+    (RETRO_DEVICE_ID_JOYPAD_Y, 0x3),
+    (RETRO_DEVICE_ID_JOYPAD_L, 0xA),
+    (RETRO_DEVICE_ID_JOYPAD_R, 0xB),
+    (RETRO_DEVICE_ID_JOYPAD_SELECT, 0xE),
+    (RETRO_DEVICE_ID_JOYPAD_START, 0xF),
+];
+
+/// The keys on a qwerty keyboard that make up the keypad, `RETROK_*` codes are the same as ascii for these
+const KEYBOARD: &str = "1234qwerasdfzxcv";
+
+const OPTION_QUIRKS: &CStr = c"ate_chip_quirks";
+const OPTION_CYCLES: &CStr = c"ate_chip_cycles_per_frame";
+const OPTION_PALETTE: &CStr = c"ate_chip_palette";
+
+/// (name, lit, unlit) as XRGB8888
+const PALETTES: [(&str, u32, u32); 4] = [
+    ("white", 0xFFFFFF, 0x000000),
+    ("green", 0x69ED2C, 0x000000),
+    ("amber", 0xFFB000, 0x1A0F00),
+    ("lcd", 0x0F380F, 0x9BBC0F),
+];
+
+/// What the frontend gave us to talk back to it with
+struct ACCallbacks {
+    environment: Option<retro_environment_t>,
+    video_refresh: Option<retro_video_refresh_t>,
+    audio_sample_batch: Option<retro_audio_sample_batch_t>,
+    input_poll: Option<retro_input_poll_t>,
+    input_state: Option<retro_input_state_t>,
+}
+
+static CALLBACKS: Mutex<ACCallbacks> = Mutex::new(ACCallbacks {
+    environment: None,
+    video_refresh: None,
+    audio_sample_batch: None,
+    input_poll: None,
+    input_state: None,
+});
+
+/// The loaded game, `None` between `retro_unload_game` and the next `retro_load_game`
+static CORE: Mutex<Option<ACRetroCore>> = Mutex::new(None);
+
+fn callbacks() -> MutexGuard<'static, ACCallbacks> {
+    CALLBACKS.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+fn core() -> MutexGuard<'static, Option<ACRetroCore>> {
+    CORE.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// The settings that can be changed from the frontend's core options menu
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct ACCoreOptions {
+    quirks: ACQuirks,
+    cycles_per_frame: usize,
+    /// (lit, unlit)
+    palette: (u32, u32),
+}
+
+impl Default for ACCoreOptions {
+    fn default() -> Self {
+        Self {
+            quirks: ACQuirks::default(),
+            cycles_per_frame: 10,
+            palette: (PALETTES[0].1, PALETTES[0].2),
+        }
+    }
+}
+
+impl ACCoreOptions {
+    /// Asks the frontend for the current value of each option, keeping the old value for any it doesn't know
+    fn fetch(self, environment: retro_environment_t) -> Self {
+        let get = |key: &CStr| -> Option<String> {
+            let mut var = retro_variable { key: key.as_ptr(), value: ptr::null() };
+            let ok = unsafe { environment(RETRO_ENVIRONMENT_GET_VARIABLE, &mut var as *mut _ as *mut c_void) };
+            if !ok || var.value.is_null() {
+                return None;
+            }
+            Some(unsafe { CStr::from_ptr(var.value) }.to_string_lossy().into_owned())
+        };
+        Self {
+            quirks: get(OPTION_QUIRKS).and_then(|v| ACQuirks::preset(&v)).unwrap_or(self.quirks),
+            cycles_per_frame: get(OPTION_CYCLES).and_then(|v| v.parse().ok()).unwrap_or(self.cycles_per_frame),
+            palette: get(OPTION_PALETTE)
+                .and_then(|v| PALETTES.iter().find(|(name, ..)| *name == v))
+                .map_or(self.palette, |(_, on, off)| (*on, *off)),
+        }
+    }
+}
+
+/// A running game
+struct ACRetroCore {
+    emulator: ACEmulator,
+    rom: Vec<u8>,
+    keyboard: ACKeyboard,
+    options: ACCoreOptions,
+    /// the screen as XRGB8888
+    video: Vec<u32>,
+    /// interleaved stereo samples for one frame
+    audio: Vec<i16>,
+    /// how far through a cycle of the beep's square wave we are, 0 to 1
+    phase: f32,
+}
+
+impl ACRetroCore {
+    fn new(rom: Vec<u8>, options: ACCoreOptions) -> Self {
+        let mut core = Self {
+            emulator: ACEmulator::new(),
+            rom,
+            keyboard: ACKeyboard::new(),
+            options,
+            video: vec![0; SCREEN_WIDTH as usize * SCREEN_HEIGHT as usize],
+            audio: vec![0; SAMPLE_RATE / FPS * 2],
+            phase: 0.0,
+        };
+        core.reset();
+        core
+    }
+
+    fn reset(&mut self) {
+        self.emulator = ACEmulator::new();
+        self.emulator.seed(SEED);
+        self.emulator.quirks = self.options.quirks;
+        self.emulator.load_rom(&self.rom);
+        self.keyboard = ACKeyboard::new();
+    }
+
+    /// Reads the keypad from the frontend, returning the first key that was newly pressed
+    fn read_input(&mut self, input_state: retro_input_state_t) -> Option<ACKey> {
+        let mut new_keypress = None;
+        for c in KEYBOARD.chars() {
+            let key = ACKey::from_qwerty(c).expect("every character in KEYBOARD is on the keypad");
+            let on_keyboard = unsafe { input_state(0, RETRO_DEVICE_KEYBOARD, 0, c as c_uint) } != 0;
+            let on_joypad = JOYPAD
+                .iter()
+                .filter(|(_, hex)| *hex == key.to_hex())
+                .any(|(id, _)| unsafe { input_state(0, RETRO_DEVICE_JOYPAD, 0, *id) } != 0);
+            let was_pressed = self.keyboard.is_pressed(&key);
+            if on_keyboard || on_joypad {
+                self.keyboard.press(key);
+                if !was_pressed {
+                    new_keypress = new_keypress.or(Some(key));
+                }
+            } else {
+                self.keyboard.release(key);
+            }
+        }
+        new_keypress
+    }
+
+    /// Draws the screen into `video`
+    fn draw(&mut self) {
+        let (on, off) = self.options.palette;
+        let framebuffer = self.emulator.framebuffer();
+        for (n, px) in self.video.iter_mut().enumerate() {
+            let lit = framebuffer.get_pixel(n % SCREEN_WIDTH as usize, n / SCREEN_WIDTH as usize);
+            *px = if lit { on } else { off };
+        }
+    }
+
+    /// Fills `audio` with one frame of the beep, or silence
+    fn play(&mut self) {
+        if !self.emulator.should_bleep() {
+            self.audio.fill(0);
+            return;
+        }
+        let amplitude = (VOLUME * i16::MAX as f32) as i16;
+        for frame in self.audio.chunks_mut(2) {
+            let sample = if self.phase <= 0.5 { amplitude } else { -amplitude };
+            frame.fill(sample);
+            self.phase = (self.phase + TONE_HZ / SAMPLE_RATE as f32) % 1.0;
+        }
+    }
+}
+
+/// Size of a save state, which doesn't depend on the rom
+fn state_size() -> usize {
+    ACEmulator::new().save_state().len()
+}
+
+#[no_mangle]
+pub extern "C" fn retro_api_version() -> c_uint {
+    RETRO_API_VERSION
+}
+
+#[no_mangle]
+pub extern "C" fn retro_init() {}
+
+#[no_mangle]
+pub extern "C" fn retro_deinit() {
+    *core() = None;
+}
+
+/// # Safety
+/// `info` must point to a `retro_system_info`
+#[no_mangle]
+pub unsafe extern "C" fn retro_get_system_info(info: *mut retro_system_info) {
+    const VERSION: &str = concat!(env!("CARGO_PKG_VERSION"), "\0");
+    *info = retro_system_info {
+        library_name: c"ate-chip".as_ptr(),
+        library_version: VERSION.as_ptr() as *const c_char,
+        valid_extensions: c"ch8|c8".as_ptr(),
+        need_fullpath: false,
+        block_extract: false,
+    };
+}
+
+/// # Safety
+/// `info` must point to a `retro_system_av_info`
+#[no_mangle]
+pub unsafe extern "C" fn retro_get_system_av_info(info: *mut retro_system_av_info) {
+    *info = retro_system_av_info {
+        geometry: retro_game_geometry {
+            base_width: SCREEN_WIDTH as c_uint,
+            base_height: SCREEN_HEIGHT as c_uint,
+            max_width: SCREEN_WIDTH as c_uint,
+            max_height: SCREEN_HEIGHT as c_uint,
+            aspect_ratio: SCREEN_WIDTH as f32 / SCREEN_HEIGHT as f32,
+        },
+        timing: retro_system_timing {
+            fps: FPS as f64,
+            sample_rate: SAMPLE_RATE as f64,
+        },
+    };
+}
+
+/// # Safety
+/// `cb` must be a valid libretro environment callback
+#[no_mangle]
+pub unsafe extern "C" fn retro_set_environment(cb: retro_environment_t) {
+    callbacks().environment = Some(cb);
+    // the first value of each is the default
+    let mut variables = [
+        retro_variable {
+            key: OPTION_QUIRKS.as_ptr(),
+            value: c"Quirks; default|cosmac|chip48|schip|xochip".as_ptr(),
+        },
+        retro_variable {
+            key: OPTION_CYCLES.as_ptr(),
+            value: c"Instructions per frame; 10|5|15|20|30|50|100|200|500|1000".as_ptr(),
+        },
+        retro_variable {
+            key: OPTION_PALETTE.as_ptr(),
+            value: c"Palette; white|green|amber|lcd".as_ptr(),
+        },
+        retro_variable { key: ptr::null(), value: ptr::null() },
+    ];
+    cb(RETRO_ENVIRONMENT_SET_VARIABLES, variables.as_mut_ptr() as *mut c_void);
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_video_refresh(cb: retro_video_refresh_t) {
+    callbacks().video_refresh = Some(cb);
+}
+
+/// Unused, audio is sent a frame at a time with `retro_set_audio_sample_batch`
+#[no_mangle]
+pub extern "C" fn retro_set_audio_sample(_cb: retro_audio_sample_t) {}
+
+#[no_mangle]
+pub extern "C" fn retro_set_audio_sample_batch(cb: retro_audio_sample_batch_t) {
+    callbacks().audio_sample_batch = Some(cb);
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_input_poll(cb: retro_input_poll_t) {
+    callbacks().input_poll = Some(cb);
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_input_state(cb: retro_input_state_t) {
+    callbacks().input_state = Some(cb);
+}
+
+/// Every device is read the same way, so there is nothing to do
+#[no_mangle]
+pub extern "C" fn retro_set_controller_port_device(_port: c_uint, _device: c_uint) {}
+
+#[no_mangle]
+pub extern "C" fn retro_reset() {
+    if let Some(core) = core().as_mut() {
+        core.reset();
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn retro_run() {
+    let callbacks = callbacks();
+    let mut core = core();
+    let Some(core) = core.as_mut() else { return };
+
+    if let Some(environment) = callbacks.environment {
+        let mut updated = false;
+        let ok = unsafe { environment(RETRO_ENVIRONMENT_GET_VARIABLE_UPDATE, &mut updated as *mut _ as *mut c_void) };
+        if ok && updated {
+            core.options = core.options.fetch(environment);
+            core.emulator.quirks = core.options.quirks;
+        }
+    }
+
+    let mut new_keypress = None;
+    if let (Some(input_poll), Some(input_state)) = (callbacks.input_poll, callbacks.input_state) {
+        unsafe { input_poll() };
+        new_keypress = core.read_input(input_state);
+    }
+    core.emulator.run_frame(&core.keyboard, new_keypress, core.options.cycles_per_frame);
+
+    core.draw();
+    if let Some(video_refresh) = callbacks.video_refresh {
+        let pitch = SCREEN_WIDTH as usize * 4;
+        unsafe {
+            video_refresh(core.video.as_ptr() as *const c_void, SCREEN_WIDTH as c_uint, SCREEN_HEIGHT as c_uint, pitch)
+        };
+    }
+    core.play();
+    if let Some(audio_sample_batch) = callbacks.audio_sample_batch {
+        unsafe { audio_sample_batch(core.audio.as_ptr(), core.audio.len() / 2) };
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn retro_serialize_size() -> usize {
+    state_size()
+}
+
+/// # Safety
+/// `data` must point to `size` writable bytes
+#[no_mangle]
+pub unsafe extern "C" fn retro_serialize(data: *mut c_void, size: usize) -> bool {
+    let core = core();
+    let Some(core) = core.as_ref() else { return false };
+    let state = core.emulator.save_state();
+    if size < state.len() {
+        return false;
+    }
+    ptr::copy_nonoverlapping(state.as_ptr(), data as *mut u8, state.len());
+    true
+}
+
+/// # Safety
+/// `data` must point to `size` readable bytes
+#[no_mangle]
+pub unsafe extern "C" fn retro_unserialize(data: *const c_void, size: usize) -> bool {
+    let mut core = core();
+    let Some(core) = core.as_mut() else { return false };
+    let state = std::slice::from_raw_parts(data as *const u8, size.min(state_size()));
+    match core.emulator.load_state(state) {
+        Ok(()) => true,
+        Err(e) => {
+            log::warn!("couldn't load state: {}", e);
+            false
+        }
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn retro_cheat_reset() {}
+
+#[no_mangle]
+pub extern "C" fn retro_cheat_set(_index: c_uint, _enabled: bool, _code: *const c_char) {}
+
+/// # Safety
+/// `game` must be null or point to a `retro_game_info` holding the rom
+#[no_mangle]
+pub unsafe extern "C" fn retro_load_game(game: *const retro_game_info) -> bool {
+    if game.is_null() || (*game).data.is_null() {
+        return false;
+    }
+    let rom = std::slice::from_raw_parts((*game).data as *const u8, (*game).size).to_vec();
+
+    let Some(environment) = callbacks().environment else { return false };
+    let mut format = RETRO_PIXEL_FORMAT_XRGB8888;
+    if !environment(RETRO_ENVIRONMENT_SET_PIXEL_FORMAT, &mut format as *mut _ as *mut c_void) {
+        log::warn!("the frontend doesn't support XRGB8888");
+        return false;
+    }
+    let options = ACCoreOptions::default().fetch(environment);
+    *core() = Some(ACRetroCore::new(rom, options));
+    true
+}
+
+/// There are no special game types
+#[no_mangle]
+pub extern "C" fn retro_load_game_special(_game_type: c_uint, _info: *const retro_game_info, _num_info: usize) -> bool {
+    false
+}
+
+#[no_mangle]
+pub extern "C" fn retro_unload_game() {
+    *core() = None;
+}
+
+#[no_mangle]
+pub extern "C" fn retro_get_region() -> c_uint {
+    RETRO_REGION_NTSC
+}
+
+/// Memory isn't exposed, as writes to it from the frontend couldn't be seen by the emulator
+#[no_mangle]
+pub extern "C" fn retro_get_memory_data(_id: c_uint) -> *mut c_void {
+    ptr::null_mut()
+}
+
+#[no_mangle]
+pub extern "C" fn retro_get_memory_size(_id: c_uint) -> usize {
+    0
+}
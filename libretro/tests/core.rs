@@ -0,0 +1,253 @@
+//! Drives the core through its `retro_*` functions the way a frontend would, with stub callbacks
+use std::ffi::{CStr, CString};
+use std::os::raw::{c_uint, c_void};
+use std::ptr;
+use std::sync::{Mutex, MutexGuard};
+
+use ate_chip::emulator::ACEmulator;
+use ate_chip::quirks::ACQuirks;
+use ate_chip_libretro::retro::*;
+use ate_chip_libretro::*;
+
+/// What the stub callbacks answer with, and what they were given
+#[derive(Default)]
+struct Frontend {
+    /// (key, value) of the options set, anything else isn't known to the frontend
+    options: Vec<(String, CString)>,
+    /// whether the options changed since the core last asked
+    updated: bool,
+    /// the last frame, as (width, height, XRGB8888 pixels)
+    video: (usize, usize, Vec<u32>),
+    /// the keys (as `RETROK_*` codes) held down
+    keys: Vec<c_uint>,
+    /// the joypad buttons held down
+    buttons: Vec<c_uint>,
+}
+
+static FRONTEND: Mutex<Option<Frontend>> = Mutex::new(None);
+
+/// The core is a global, so only one test can use it at a time
+static CORE: Mutex<()> = Mutex::new(());
+
+fn with_frontend<T>(f: impl FnOnce(&mut Frontend) -> T) -> T {
+    f(FRONTEND.lock().unwrap_or_else(|e| e.into_inner()).get_or_insert_with(Frontend::default))
+}
+
+unsafe extern "C" fn environment(cmd: c_uint, data: *mut c_void) -> bool {
+    match cmd {
+        RETRO_ENVIRONMENT_SET_PIXEL_FORMAT => *(data as *const c_uint) == RETRO_PIXEL_FORMAT_XRGB8888,
+        RETRO_ENVIRONMENT_SET_VARIABLES => true,
+        RETRO_ENVIRONMENT_GET_VARIABLE => {
+            let var = data as *mut retro_variable;
+            let key = CStr::from_ptr((*var).key).to_string_lossy().into_owned();
+            with_frontend(|f| match f.options.iter().find(|(k, _)| *k == key) {
+                // the strings live in FRONTEND until the options are set again
+                Some((_, value)) => {
+                    (*var).value = value.as_ptr();
+                    true
+                }
+                None => false,
+            })
+        }
+        RETRO_ENVIRONMENT_GET_VARIABLE_UPDATE => {
+            *(data as *mut bool) = with_frontend(|f| std::mem::take(&mut f.updated));
+            true
+        }
+        _ => false,
+    }
+}
+
+unsafe extern "C" fn video_refresh(data: *const c_void, width: c_uint, height: c_uint, pitch: usize) {
+    let (width, height) = (width as usize, height as usize);
+    let mut pixels = Vec::with_capacity(width * height);
+    for y in 0..height {
+        let row = (data as *const u8).add(y * pitch) as *const u32;
+        pixels.extend_from_slice(std::slice::from_raw_parts(row, width));
+    }
+    with_frontend(|f| f.video = (width, height, pixels));
+}
+
+unsafe extern "C" fn audio_sample_batch(_data: *const i16, frames: usize) -> usize {
+    frames
+}
+
+unsafe extern "C" fn input_poll() {}
+
+unsafe extern "C" fn input_state(port: c_uint, device: c_uint, _index: c_uint, id: c_uint) -> i16 {
+    with_frontend(|f| match (port, device) {
+        (0, RETRO_DEVICE_KEYBOARD) => f.keys.contains(&id) as i16,
+        (0, RETRO_DEVICE_JOYPAD) => f.buttons.contains(&id) as i16,
+        _ => 0,
+    })
+}
+
+fn options(options: &[(&str, &str)]) -> Vec<(String, CString)> {
+    options.iter().map(|(k, v)| (k.to_string(), CString::new(*v).unwrap())).collect()
+}
+
+/// Loads `rom` with the given options, holding the core until the guard is dropped
+fn start(rom: &[u8], opts: &[(&str, &str)]) -> MutexGuard<'static, ()> {
+    let guard = CORE.lock().unwrap_or_else(|e| e.into_inner());
+    with_frontend(|f| {
+        *f = Frontend::default();
+        f.options = options(opts);
+    });
+    unsafe {
+        retro_set_environment(environment);
+        retro_set_video_refresh(video_refresh);
+        retro_set_audio_sample_batch(audio_sample_batch);
+        retro_set_input_poll(input_poll);
+        retro_set_input_state(input_state);
+        retro_init();
+        let game = retro_game_info { path: ptr::null(), data: rom.as_ptr() as *const c_void, size: rom.len(), meta: ptr::null() };
+        assert!(retro_load_game(&game));
+    }
+    guard
+}
+
+/// Changes the options, as if from the frontend's menu
+fn set_options(opts: &[(&str, &str)]) {
+    with_frontend(|f| {
+        f.options = options(opts);
+        f.updated = true;
+    });
+}
+
+fn serialize() -> Vec<u8> {
+    let mut state = vec![0; retro_serialize_size()];
+    assert!(unsafe { retro_serialize(state.as_mut_ptr() as *mut c_void, state.len()) });
+    state
+}
+
+/// The emulator as the core has it, read back through a save state
+fn running() -> ACEmulator {
+    let mut emulator = ACEmulator::new();
+    emulator.load_state(&serialize()).unwrap();
+    emulator
+}
+
+fn frame() -> (usize, usize, Vec<u32>) {
+    with_frontend(|f| f.video.clone())
+}
+
+#[test]
+fn options_are_fetched() {
+    // ADD V0, 1; JP 200
+    let rom = [0x70, 0x01, 0x12, 0x00];
+    let core = start(&rom, &[("ate_chip_quirks", "schip"), ("ate_chip_cycles_per_frame", "5"), ("ate_chip_palette", "amber")]);
+    retro_run();
+    let emulator = running();
+    assert_eq!(emulator.quirks, ACQuirks::SCHIP);
+    assert_eq!(emulator.reg(0), 3);
+    assert!(frame().2.iter().all(|px| *px == 0x1A0F00));
+
+    // values the core doesn't know keep what was there
+    set_options(&[("ate_chip_quirks", "superchip"), ("ate_chip_cycles_per_frame", "lots"), ("ate_chip_palette", "purple")]);
+    retro_run();
+    let emulator = running();
+    assert_eq!(emulator.quirks, ACQuirks::SCHIP);
+    assert_eq!(emulator.reg(0), 5);
+    assert!(frame().2.iter().all(|px| *px == 0x1A0F00));
+
+    set_options(&[("ate_chip_quirks", "cosmac"), ("ate_chip_cycles_per_frame", "10"), ("ate_chip_palette", "lcd")]);
+    retro_run();
+    let emulator = running();
+    assert_eq!(emulator.quirks, ACQuirks::COSMAC);
+    assert_eq!(emulator.reg(0), 10);
+    assert!(frame().2.iter().all(|px| *px == 0x9BBC0F));
+    retro_unload_game();
+    drop(core);
+
+    // and the defaults are kept when loading
+    let _core = start(&rom, &[("ate_chip_quirks", "superchip")]);
+    retro_run();
+    let emulator = running();
+    assert_eq!(emulator.quirks, ACQuirks::default());
+    assert_eq!(emulator.reg(0), 5);
+    assert!(frame().2.iter().all(|px| *px == 0x000000));
+    retro_unload_game();
+}
+
+#[test]
+fn frames_are_drawn_in_the_palette() {
+    // LD V0, 0; LD F, V0; DRW V0, V0, 5; JP 206
+    let rom = [0x60, 0x00, 0xF0, 0x29, 0xD0, 0x05, 0x12, 0x06];
+    let _core = start(&rom, &[("ate_chip_palette", "green")]);
+    retro_run();
+    let (width, height, pixels) = frame();
+    assert_eq!((width, height, pixels.len()), (64, 32, 64 * 32));
+    // the 0 glyph, F0 90 90 90 F0
+    assert_eq!(pixels.iter().filter(|px| **px == 0x69ED2C).count(), 14);
+    assert_eq!(pixels.iter().filter(|px| **px == 0x000000).count(), 64 * 32 - 14);
+    assert_eq!((pixels[0], pixels[64 + 1], pixels[64 + 3], pixels[4 * 64 + 3]), (0x69ED2C, 0, 0x69ED2C, 0x69ED2C));
+    retro_unload_game();
+}
+
+#[test]
+fn input_reaches_the_keypad() {
+    // LD V2, 5; SKP V2; JP 202; LD V3, 1; LD V0, K; JP 208
+    let rom = [0x62, 0x05, 0xE2, 0x9E, 0x12, 0x02, 0x63, 0x01, 0xF0, 0x0A, 0x12, 0x08];
+    let _core = start(&rom, &[]);
+    retro_run();
+    assert_eq!(running().reg(3), 0);
+
+    // the keypad's 5 is A on the joypad and w on the keyboard, and the press is new so LD V0, K takes it too
+    for (hold, press, hex) in [
+        ((vec![], vec![RETRO_DEVICE_ID_JOYPAD_A]), (vec![], vec![RETRO_DEVICE_ID_JOYPAD_UP]), 0x2),
+        ((vec!['w' as c_uint], vec![]), (vec!['v' as c_uint], vec![]), 0xF),
+    ] {
+        retro_reset();
+        with_frontend(|f| (f.keys, f.buttons) = hold);
+        retro_run();
+        let emulator = running();
+        assert_eq!((emulator.reg(3), emulator.reg(0)), (1, 0x5));
+        assert!(emulator.waiting_for_key());
+
+        with_frontend(|f| (f.keys, f.buttons) = press);
+        retro_run();
+        assert_eq!(running().reg(0), hex);
+    }
+    retro_unload_game();
+}
+
+#[test]
+fn save_states_round_trip() {
+    // RND V0, FF; JP 200
+    let rom = [0xC0, 0xFF, 0x12, 0x00];
+    let _core = start(&rom, &[]);
+    retro_run();
+    let state = serialize();
+    retro_run();
+    let (after, draws) = (serialize(), running().reg(0));
+
+    for _ in 0..10 {
+        retro_run();
+    }
+    assert!(unsafe { retro_unserialize(state.as_ptr() as *const c_void, state.len()) });
+    assert_eq!(serialize(), state);
+    // including where the random numbers are up to
+    retro_run();
+    assert_eq!(running().reg(0), draws);
+    assert_eq!(serialize(), after);
+
+    assert!(!unsafe { retro_unserialize(state.as_ptr() as *const c_void, 10) });
+    assert_eq!(serialize(), after);
+    retro_unload_game();
+    assert!(!unsafe { retro_serialize(state.as_ptr() as *mut c_void, state.len()) });
+}
+
+#[test]
+fn resetting_draws_the_same_numbers() {
+    // RND V0, FF; RND V1, FF; RND V2, FF; JP 206
+    let rom = [0xC0, 0xFF, 0xC1, 0xFF, 0xC2, 0xFF, 0x12, 0x06];
+    let _core = start(&rom, &[]);
+    let draws = |emulator: ACEmulator| [emulator.reg(0), emulator.reg(1), emulator.reg(2)];
+    retro_run();
+    let first = draws(running());
+    assert_ne!(first, [0; 3]);
+
+    retro_reset();
+    retro_run();
+    assert_eq!(draws(running()), first);
+    retro_unload_game();
+}
@@ -0,0 +1,218 @@
+#[cfg(feature = "count-allocations")]
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+#[cfg(feature = "count-allocations")]
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use ate_chip::disasm;
+use ate_chip::emulator::ACEmulator;
+use ate_chip::keyboard::ACKeyboard;
+use ate_chip::quirks::ACQuirks;
+
+use crate::{ACEmError, QUIRK_PRESETS};
+
+/// Counts allocations, so the benchmark can show the core doesn't make any. Every allocation in the process pays for
+/// this, so it is only built in with the `count-allocations` feature
+#[cfg(feature = "count-allocations")]
+struct CountingAlloc;
+
+#[cfg(feature = "count-allocations")]
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+#[cfg(feature = "count-allocations")]
+static ALLOCATED_BYTES: AtomicUsize = AtomicUsize::new(0);
+
+#[cfg(feature = "count-allocations")]
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        ALLOCATED_BYTES.fetch_add(layout.size(), Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        ALLOCATED_BYTES.fetch_add(new_size, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[cfg(feature = "count-allocations")]
+#[global_allocator]
+static GLOBAL: CountingAlloc = CountingAlloc;
+
+/// (allocations, bytes) so far, `None` if they aren't being counted
+fn allocations() -> Option<(usize, usize)> {
+    #[cfg(feature = "count-allocations")]
+    return Some((ALLOCATIONS.load(Ordering::Relaxed), ALLOCATED_BYTES.load(Ordering::Relaxed)));
+    #[cfg(not(feature = "count-allocations"))]
+    return None;
+}
+
+/// Parses counts like `100M`, `250k` or `5000`
+fn parse_count(s: &str) -> Result<u64, String> {
+    let (digits, multiplier) = match s.char_indices().last() {
+        Some((i, 'k' | 'K')) => (&s[..i], 1_000),
+        Some((i, 'm' | 'M')) => (&s[..i], 1_000_000),
+        Some((i, 'g' | 'G')) => (&s[..i], 1_000_000_000),
+        _ => (s, 1),
+    };
+    digits
+        .replace('_', "")
+        .parse::<u64>()
+        .ok()
+        .and_then(|n| n.checked_mul(multiplier))
+        .ok_or_else(|| format!("{:?} is not a count, expected something like 100M", s))
+}
+
+#[derive(clap::Args, Debug)]
+pub struct BenchArgs {
+    #[clap(help = "path to the rom file")]
+    rom: PathBuf,
+    #[clap(long, default_value = "100M", parse(try_from_str = parse_count), help = "how many instructions to run, k/M/G suffixes are allowed")]
+    cycles: u64,
+    #[clap(long, default_value_t = 10, help = "instructions run between ticks of the timers")]
+    cycles_per_frame: usize,
+    #[clap(long, default_value = "default", possible_values = QUIRK_PRESETS, help = "which interpreter to behave like")]
+    quirks: String,
+    #[clap(long, help = "skip timing each opcode, which takes about as long as the benchmark itself")]
+    no_profile: bool,
+    #[clap(long, help = "compile hot code to native code, needs the jit feature. The per opcode profile is always interpreted")]
+    jit: bool,
+}
+
+fn load(rom: &[u8], quirks: ACQuirks) -> ACEmulator {
+    let mut emulator = ACEmulator::new();
+    emulator.quirks = quirks;
+    emulator.load_rom(rom);
+    emulator
+}
+
+/// Runs `cycles` instructions as fast as possible, returning how many were actually run before the rom faulted
+fn throughput(emulator: &mut ACEmulator, cycles: u64, cycles_per_frame: usize) -> u64 {
+    let keyboard = ACKeyboard::new();
+    let mut ran = 0;
+    while ran < cycles && emulator.fault().is_none() {
+        let frame = (cycles - ran).min(cycles_per_frame as u64);
+        emulator.run_frame(&keyboard, None, frame as usize);
+        ran += frame;
+    }
+    ran
+}
+
+/// Times every instruction on its own, grouped by `disasm::pattern`.
+/// `FX0A` with no key to press shows up as `FX0A (waiting)`
+fn profile(emulator: &mut ACEmulator, cycles: u64, cycles_per_frame: usize) -> BTreeMap<&'static str, (u64, Duration)> {
+    let keyboard = ACKeyboard::new();
+    let mut costs: BTreeMap<&'static str, (u64, Duration)> = BTreeMap::new();
+    let mut ran = 0;
+    while ran < cycles && emulator.fault().is_none() {
+        let pattern = if emulator.waiting_for_key() {
+            "FX0A (waiting)"
+        } else {
+            let pc = emulator.pc() as usize;
+            let memory = emulator.memory();
+            let instr = u16::from_be_bytes([memory[pc & 0xFFF], memory[(pc + 1) & 0xFFF]]);
+            disasm::pattern(instr)
+        };
+        let start = Instant::now();
+        emulator.step(&keyboard, None);
+        let elapsed = start.elapsed();
+        let cost = costs.entry(pattern).or_default();
+        cost.0 += 1;
+        cost.1 += elapsed;
+        ran += 1;
+        if ran % cycles_per_frame as u64 == 0 {
+            emulator.tick_timers();
+        }
+    }
+    costs
+}
+
+/// How long `Instant::now` + `elapsed` takes on its own, to subtract from the profile
+fn timer_overhead() -> Duration {
+    const SAMPLES: u32 = 100_000;
+    let start = Instant::now();
+    for _ in 0..SAMPLES {
+        std::hint::black_box(Instant::now().elapsed());
+    }
+    start.elapsed() / SAMPLES
+}
+
+/// `ate-chip bench`, measures how fast the interpreter runs a rom
+pub fn bench(args: BenchArgs) -> Result<(), ACEmError> {
+    let rom = fs::read(&args.rom)?;
+    let quirks = ACQuirks::preset(&args.quirks).expect("clap checked that the preset exists");
+    let cycles_per_frame = args.cycles_per_frame.max(1);
+
+    let mut emulator = load(&rom, quirks);
+    if args.jit {
+        #[cfg(feature = "jit")]
+        emulator.enable_jit()?;
+        #[cfg(not(feature = "jit"))]
+        return Err("ate-chip was built without the JIT, rebuild with --features jit".to_string().into());
+    }
+    let before = allocations();
+    let start = Instant::now();
+    let ran = throughput(&mut emulator, args.cycles, cycles_per_frame);
+    let elapsed = start.elapsed();
+    let after = allocations();
+
+    println!("{}: {} instructions in {:.3?}", args.rom.display(), ran, elapsed);
+    println!("{:.2} million instructions per second", ran as f64 / elapsed.as_secs_f64() / 1e6);
+    println!("{:.2} ns per instruction", elapsed.as_nanos() as f64 / ran.max(1) as f64);
+    match before.zip(after) {
+        Some(((allocs_before, bytes_before), (allocs_after, bytes_after))) => {
+            println!("{} allocations ({} bytes)", allocs_after - allocs_before, bytes_after - bytes_before)
+        }
+        None => println!("allocations not counted, rebuild with --features count-allocations"),
+    }
+    if let Some(fault) = emulator.fault() {
+        println!("stopped early, {:?} at {:03X}", fault, emulator.pc());
+    }
+
+    if args.no_profile {
+        return Ok(());
+    }
+    let mut emulator = load(&rom, quirks);
+    let overhead = timer_overhead();
+    let costs = profile(&mut emulator, ran, cycles_per_frame);
+    let mut costs: Vec<(&str, u64, Duration)> = costs.into_iter().map(|(pattern, (count, total))| (pattern, count, total)).collect();
+    // most expensive first
+    costs.sort_by_key(|(_, _, total)| std::cmp::Reverse(*total));
+
+    println!();
+    println!("per opcode (timer overhead of {:?} subtracted):", overhead);
+    println!("{:<16} {:>14} {:>8} {:>12}", "opcode", "count", "share", "ns each");
+    let without_overhead = |count: u64, time: Duration| time.as_nanos().saturating_sub(overhead.as_nanos() * count as u128) as f64;
+    let total: f64 = costs.iter().map(|(_, count, time)| without_overhead(*count, *time)).sum();
+    for (pattern, count, time) in costs {
+        let time = without_overhead(count, time);
+        let share = time / total.max(f64::MIN_POSITIVE) * 100.0;
+        println!("{:<16} {:>14} {:>7.1}% {:>12.2}", pattern, count, share, time / count as f64);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_parse_with_suffixes() {
+        assert_eq!(parse_count("5000"), Ok(5000));
+        assert_eq!(parse_count("250k"), Ok(250_000));
+        assert_eq!(parse_count("1_00M"), Ok(100_000_000));
+        assert_eq!(parse_count("2G"), Ok(2_000_000_000));
+        assert!(parse_count("M").is_err());
+        assert!(parse_count("ten").is_err());
+        // too big for a u64
+        assert!(parse_count("99999999999999999G").is_err());
+    }
+}
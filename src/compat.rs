@@ -0,0 +1,318 @@
+use std::collections::BTreeSet;
+use std::fs;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+
+use clap::ArgEnum;
+
+use ate_chip::coverage::ACCoverage;
+use ate_chip::emulator::ACEmulator;
+use ate_chip::image::ACBitmap;
+use ate_chip::keyboard::ACKeyboard;
+use ate_chip::quirks::ACQuirks;
+
+use crate::harness::{find_roms, panic_message, xml_escape};
+use crate::ACEmError;
+
+#[derive(ArgEnum, Clone, Debug)]
+pub enum CompatFormat {
+    Markdown,
+    Html,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct CompatArgs {
+    #[clap(help = "directory containing the roms (.ch8 or .c8) to check")]
+    dir: PathBuf,
+    #[clap(long, default_value_t = 10, help = "how many seconds (of emulated time) to run each rom for")]
+    seconds: usize,
+    #[clap(long, default_value_t = 10, help = "instructions run per frame")]
+    cycles_per_frame: usize,
+    #[clap(long, default_value_t = 0, help = "seed for the random numbers given by CXNN, so each run reports the same")]
+    seed: u64,
+    #[clap(long, help = "how many roms to run at once, defaults to the number of cpus")]
+    jobs: Option<usize>,
+    #[clap(long, arg_enum, default_value = "markdown", help = "format of the report")]
+    format: CompatFormat,
+    #[clap(long, help = "write the report to this file instead of stdout")]
+    report: Option<PathBuf>,
+}
+
+/// How a rom behaved when left running with nobody at the keyboard
+#[derive(Debug, Default)]
+struct Compat {
+    /// a fault or a panic
+    crash: Option<String>,
+    /// (address, instruction) of instructions that aren't understood
+    unknown: BTreeSet<(u16, u16)>,
+    /// addresses that were both run as code and written to
+    self_modifying: BTreeSet<u16>,
+    screen_changed: bool,
+    /// the frame the rom started waiting on `FX0A`, if it still was at the end
+    waiting_since: Option<usize>,
+}
+
+impl Compat {
+    /// One word summary, the worst thing that happened
+    fn status(&self) -> &'static str {
+        if self.crash.is_some() {
+            "crashed"
+        } else if self.waiting_since.is_some() {
+            "waiting for key"
+        } else if !self.screen_changed {
+            "blank screen"
+        } else {
+            "ok"
+        }
+    }
+}
+
+/// Runs `rom` headless for `frames` frames, without pressing any keys, with random numbers from `seed`
+fn check(rom: &[u8], quirks: ACQuirks, seed: u64, frames: usize, cycles_per_frame: usize) -> Compat {
+    let mut emulator = ACEmulator::new();
+    emulator.quirks = quirks;
+    emulator.seed(seed);
+    emulator.load_rom(rom);
+    emulator.coverage = Some(ACCoverage::new());
+    emulator.write_log = Some(vec![]);
+    emulator.unknown_log = Some(BTreeSet::new());
+    let keyboard = ACKeyboard::new();
+    let blank = ACBitmap::from_framebuffer(emulator.framebuffer());
+
+    let mut compat = Compat::default();
+    let mut written = BTreeSet::new();
+    let ran = panic::catch_unwind(AssertUnwindSafe(|| {
+        for frame in 0..frames {
+            emulator.run_frame(&keyboard, None, cycles_per_frame);
+            if let Some(log) = &mut emulator.write_log {
+                written.extend(log.drain(..));
+            }
+            if !compat.screen_changed && ACBitmap::from_framebuffer(emulator.framebuffer()) != blank {
+                compat.screen_changed = true;
+            }
+            if emulator.waiting_for_key() {
+                compat.waiting_since.get_or_insert(frame);
+            } else {
+                compat.waiting_since = None;
+            }
+            if emulator.fault().is_some() {
+                break;
+            }
+        }
+    }));
+
+    compat.crash = match ran {
+        Err(e) => Some(format!("panicked: {}", panic_message(&*e))),
+        Ok(()) => emulator.fault().map(|fault| format!("{:?} at {:03X}", fault, emulator.pc())),
+    };
+    compat.unknown = emulator.unknown_log.take().unwrap_or_default();
+    if let Some(coverage) = &emulator.coverage {
+        // an instruction is two bytes, so a write to either one changes it
+        compat.self_modifying = written
+            .into_iter()
+            .filter(|addr| coverage.hits(*addr) > 0 || coverage.hits(addr.wrapping_sub(1)) > 0)
+            .collect();
+    }
+    compat
+}
+
+fn describe_unknown(unknown: &BTreeSet<(u16, u16)>) -> String {
+    let list: Vec<String> = unknown.iter().map(|(addr, instr)| format!("{:04X}@{:03X}", instr, addr)).collect();
+    list.join(" ")
+}
+
+fn describe_writes(addrs: &BTreeSet<u16>) -> String {
+    match (addrs.iter().next(), addrs.iter().next_back()) {
+        (Some(first), Some(last)) => format!("{} bytes, {:03X}-{:03X}", addrs.len(), first, last),
+        _ => String::new(),
+    }
+}
+
+/// The columns of a row in the report
+fn row(rom: &str, preset: &str, compat: &Compat) -> [String; 8] {
+    [
+        rom.to_string(),
+        preset.to_string(),
+        compat.status().to_string(),
+        compat.crash.clone().unwrap_or_default(),
+        describe_unknown(&compat.unknown),
+        describe_writes(&compat.self_modifying),
+        if compat.screen_changed { "yes" } else { "no" }.to_string(),
+        compat.waiting_since.map(|f| format!("since frame {}", f)).unwrap_or_default(),
+    ]
+}
+
+const HEADER: [&str; 8] = ["rom", "quirks", "status", "crash", "unknown opcodes", "self-modifying", "drew", "FX0A"];
+
+fn report(rows: &[[String; 8]], format: &CompatFormat) -> String {
+    let mut out = String::new();
+    match format {
+        CompatFormat::Markdown => {
+            out += "# ate-chip compatibility report\n\n";
+            out += &format!("| {} |\n", HEADER.join(" | "));
+            out += &format!("|{}\n", "---|".repeat(HEADER.len()));
+            for row in rows {
+                let cells: Vec<String> = row.iter().map(|c| c.replace('|', "\\|")).collect();
+                out += &format!("| {} |\n", cells.join(" | "));
+            }
+        }
+        CompatFormat::Html => {
+            out += "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n";
+            out += "<title>ate-chip compatibility report</title>\n<style>\n";
+            out += "td, th { padding: 2px 8px; text-align: left; font-family: monospace; }\n";
+            out += ".ok { background: #cfc; } .crashed { background: #fcc; } .waiting, .blank { background: #ffc; }\n";
+            out += "</style>\n</head>\n<body>\n<h1>ate-chip compatibility report</h1>\n<table>\n<tr>";
+            for heading in HEADER {
+                out += &format!("<th>{}</th>", heading);
+            }
+            out += "</tr>\n";
+            for row in rows {
+                // the first word of the status
+                let class = row[2].split(' ').next().unwrap_or_default();
+                out += &format!("<tr class=\"{}\">", class);
+                for cell in row {
+                    out += &format!("<td>{}</td>", xml_escape(cell));
+                }
+                out += "</tr>\n";
+            }
+            out += "</table>\n</body>\n</html>\n";
+        }
+    }
+    out
+}
+
+/// `ate-chip compat`, runs every rom in a directory under each quirk preset and reports how they behaved
+pub fn compat(args: CompatArgs) -> Result<(), ACEmError> {
+    let presets: Vec<(&str, ACQuirks)> = std::iter::once(("default", ACQuirks::default()))
+        .chain(ACQuirks::PRESETS)
+        .collect();
+    let mut roms = vec![];
+    for path in find_roms(&args.dir)? {
+        let name = path.file_name().unwrap_or_default().to_string_lossy().to_string();
+        roms.push((name, fs::read(&path)?));
+    }
+    let jobs: Vec<(usize, usize)> = (0..roms.len())
+        .flat_map(|rom| (0..presets.len()).map(move |preset| (rom, preset)))
+        .collect();
+
+    let frames = args.seconds * 60;
+    let threads = args
+        .jobs
+        .unwrap_or_else(|| thread::available_parallelism().map_or(1, |n| n.get()))
+        .max(1);
+    let next = AtomicUsize::new(0);
+    let results = Mutex::new((0..jobs.len()).map(|_| None).collect::<Vec<Option<Compat>>>());
+    thread::scope(|s| {
+        for _ in 0..threads {
+            s.spawn(|| loop {
+                let n = next.fetch_add(1, Ordering::Relaxed);
+                let Some((rom, preset)) = jobs.get(n) else { break };
+                let compat = check(&roms[*rom].1, presets[*preset].1, args.seed, frames, args.cycles_per_frame);
+                results.lock().expect("no worker panics while holding the lock")[n] = Some(compat);
+            });
+        }
+    });
+
+    let results = results.into_inner().expect("no worker panics while holding the lock");
+    let rows: Vec<[String; 8]> = jobs
+        .iter()
+        .zip(results)
+        .map(|((rom, preset), compat)| {
+            row(&roms[*rom].0, presets[*preset].0, &compat.expect("every job was run"))
+        })
+        .collect();
+
+    let report = report(&rows, &args.format);
+    match &args.report {
+        Some(path) => fs::write(path, report)?,
+        None => print!("{}", report),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check_program(program: &[u16]) -> Compat {
+        let rom: Vec<u8> = program.iter().flat_map(|instr| instr.to_be_bytes()).collect();
+        check(&rom, ACQuirks::default(), 0, 5, 10)
+    }
+
+    #[test]
+    fn crashes_are_found() {
+        // calls itself until the stack runs out
+        let compat = check_program(&[0x2200]);
+        assert_eq!(compat.crash.as_deref(), Some("StackOverflow at 200"));
+        assert_eq!(compat.status(), "crashed");
+    }
+
+    #[test]
+    fn unknown_opcodes_are_listed() {
+        let compat = check_program(&[0x0123, 0x5121, 0x1204]);
+        assert_eq!(compat.unknown, BTreeSet::from([(0x200, 0x0123), (0x202, 0x5121)]));
+        assert_eq!(describe_unknown(&compat.unknown), "0123@200 5121@202");
+        assert_eq!(compat.crash, None);
+    }
+
+    #[test]
+    fn writes_over_code_that_ran_are_self_modifying() {
+        // stores V0 over the first instruction, and V0 and V1 into data after the program
+        let compat = check_program(&[0xA200, 0x6000, 0xF055, 0xA20C, 0xF155, 0x120A, 0x0000]);
+        assert_eq!(compat.self_modifying, BTreeSet::from([0x200]));
+        assert_eq!(describe_writes(&compat.self_modifying), "1 bytes, 200-200");
+        assert_eq!(describe_writes(&BTreeSet::new()), "");
+    }
+
+    #[test]
+    fn blank_screens_and_waits_are_found() {
+        let compat = check_program(&[0x1200]);
+        assert!(!compat.screen_changed);
+        assert_eq!(compat.status(), "blank screen");
+
+        let compat = check_program(&[0xD005, 0x1202]);
+        assert!(compat.screen_changed);
+        assert_eq!(compat.status(), "ok");
+
+        // waits for a key straight away, which nobody presses
+        let compat = check_program(&[0xF00A]);
+        assert_eq!(compat.waiting_since, Some(0));
+        assert_eq!(compat.status(), "waiting for key");
+    }
+
+    #[test]
+    fn the_worst_status_is_given() {
+        let mut compat = Compat::default();
+        assert_eq!(compat.status(), "blank screen");
+        compat.screen_changed = true;
+        assert_eq!(compat.status(), "ok");
+        compat.screen_changed = false;
+        compat.waiting_since = Some(3);
+        assert_eq!(compat.status(), "waiting for key");
+        compat.crash = Some("panicked".to_string());
+        assert_eq!(compat.status(), "crashed");
+    }
+
+    #[test]
+    fn reports() {
+        let mut crashed = Compat { crash: Some("a | <b>".to_string()), ..Default::default() };
+        crashed.waiting_since = Some(2);
+        let rows = [row("pong.ch8", "default", &crashed), row("blank.ch8", "schip", &Compat::default())];
+        assert_eq!(rows[0][7], "since frame 2");
+
+        let markdown = report(&rows, &CompatFormat::Markdown);
+        let lines: Vec<&str> = markdown.lines().collect();
+        assert_eq!(lines[2], "| rom | quirks | status | crash | unknown opcodes | self-modifying | drew | FX0A |");
+        assert_eq!(lines[3], "|---|---|---|---|---|---|---|---|");
+        assert_eq!(lines[4], "| pong.ch8 | default | crashed | a \\| <b> |  |  | no | since frame 2 |");
+        assert_eq!(lines[5], "| blank.ch8 | schip | blank screen |  |  |  | no |  |");
+
+        let html = report(&rows, &CompatFormat::Html);
+        assert!(html.contains("<tr class=\"crashed\"><td>pong.ch8</td><td>default</td><td>crashed</td><td>a | &lt;b&gt;</td>"));
+        assert!(html.contains("<tr class=\"blank\"><td>blank.ch8</td>"));
+        assert_eq!(html.matches("<tr").count(), 3);
+    }
+}
@@ -0,0 +1,644 @@
+//! `--control <socket>`, for driving a running emulator from scripts and bots in any language.
+//!
+//! Listens on a unix socket for JSON-RPC 2.0 requests, one per line, and answers each with one line.
+//! For example `{"jsonrpc": "2.0", "id": 1, "method": "step", "params": {"frames": 60}}`. The methods are
+//!
+//! | method | params | result |
+//! |---|---|---|
+//! | `pause`, `resume` | | |
+//! | `step` | `frames` (default 1) | once the frames have run, pausing first |
+//! | `press`, `release` | `key`, `0`-`15` or `"0"`-`"F"` | |
+//! | `registers` | | `v`, `i`, `pc`, `dt`, `st` and `stack` |
+//! | `set_register` | `register` (`v0`-`vF`, `i`, `pc`, `dt` or `st`), `value` | |
+//! | `read_memory` | `address`, `length` | the bytes, as an array |
+//! | `write_memory` | `address`, `data` (an array of bytes) | |
+//! | `framebuffer` | `format`, `bits` (default, a row at a time, 8 pixels to a byte) or `png` | `width`, `height`, `format` and base64 `data` |
+//! | `load_rom` | `path`, or base64 `data`, keeping the quirks, random numbers, JIT and debugging aids | |
+//! | `save_state` | | base64 `state` |
+//! | `load_state` | base64 `state` | |
+//! | `status` | | `paused`, `frame`, `pc`, `waiting_for_key` and `fault` |
+//! | `quit` | | |
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, Sender};
+
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use ate_chip::emulator::{ACEmulator, ACFramebuffer};
+use ate_chip::frontend::{self, ACFrontend, ACInput, ACRenderer};
+use ate_chip::graphics::{base64, unbase64};
+use ate_chip::image::ACBitmap;
+use ate_chip::keyboard::{ACKey, ACKeyboard};
+
+use crate::ACEmError;
+
+/// JSON-RPC error codes
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+/// something went wrong running a method, like a save state that doesn't load
+const SERVER_ERROR: i64 = -32000;
+
+#[derive(Debug)]
+struct ACRpcError {
+    code: i64,
+    message: String,
+}
+
+impl ACRpcError {
+    fn new(code: i64, message: impl Into<String>) -> Self {
+        Self { code, message: message.into() }
+    }
+}
+
+type ACRpcResult = Result<Value, ACRpcError>;
+
+/// A request from a client, waiting for the emulator to get round to it
+struct ACRequest {
+    method: String,
+    params: Value,
+    reply: Sender<ACRpcResult>,
+}
+
+/// Reads `params` into `T`, no params being the same as an empty object
+fn params<T: DeserializeOwned>(params: Value) -> Result<T, ACRpcError> {
+    let params = if params.is_null() { json!({}) } else { params };
+    serde_json::from_value(params).map_err(|e| ACRpcError::new(INVALID_PARAMS, e.to_string()))
+}
+
+fn one() -> usize {
+    1
+}
+
+#[derive(Deserialize)]
+struct StepParams {
+    #[serde(default = "one")]
+    frames: usize,
+}
+
+/// A keypad key as a number, or a hex digit
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum KeyParam {
+    Number(u8),
+    Hex(String),
+}
+
+#[derive(Deserialize)]
+struct KeyParams {
+    key: KeyParam,
+}
+
+impl KeyParams {
+    fn key(&self) -> Result<ACKey, ACRpcError> {
+        let hex = match &self.key {
+            KeyParam::Number(n) => Some(*n),
+            KeyParam::Hex(s) => u8::from_str_radix(s, 16).ok(),
+        };
+        hex.and_then(ACKey::from_hex)
+            .ok_or_else(|| ACRpcError::new(INVALID_PARAMS, "key must be 0-15, or a hex digit"))
+    }
+}
+
+#[derive(Deserialize)]
+struct SetRegisterParams {
+    register: String,
+    value: u16,
+}
+
+#[derive(Deserialize)]
+struct ReadMemoryParams {
+    address: u16,
+    length: usize,
+}
+
+#[derive(Deserialize)]
+struct WriteMemoryParams {
+    address: u16,
+    data: Vec<u8>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "lowercase")]
+enum FramebufferFormat {
+    #[default]
+    Bits,
+    Png,
+}
+
+#[derive(Deserialize)]
+struct FramebufferParams {
+    #[serde(default)]
+    format: FramebufferFormat,
+}
+
+#[derive(Deserialize)]
+struct LoadRomParams {
+    path: Option<PathBuf>,
+    data: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct StateParams {
+    state: String,
+}
+
+fn decode(data: &str) -> Result<Vec<u8>, ACRpcError> {
+    unbase64(data).map_err(|e| ACRpcError::new(INVALID_PARAMS, e))
+}
+
+/// Wraps a frontend, pausing it, stepping it and pressing keys as the control socket asks
+struct ACControlled<'a, F: ACFrontend> {
+    inner: &'a mut F,
+    requests: Receiver<ACRequest>,
+    /// removed when done with
+    socket: PathBuf,
+    paused: bool,
+    /// frames left to run before answering a `step`
+    stepping: Option<(usize, Sender<ACRpcResult>)>,
+    /// key presses (`true`) and releases, applied before the next frame
+    keys: Vec<(ACKey, bool)>,
+    /// frames run so far
+    frame: u64,
+    quit: bool,
+}
+
+impl<'a, F: ACFrontend> ACControlled<'a, F> {
+    /// `inner`, taking requests from `requests` and removing `socket` when dropped
+    fn new(inner: &'a mut F, requests: Receiver<ACRequest>, socket: PathBuf) -> Self {
+        Self {
+            inner,
+            requests,
+            socket,
+            paused: false,
+            stepping: None,
+            keys: vec![],
+            frame: 0,
+            quit: false,
+        }
+    }
+
+    /// Runs a request that doesn't need to wait for anything
+    fn handle(&mut self, emulator: &mut ACEmulator, method: &str, p: Value) -> ACRpcResult {
+        match method {
+            "pause" => self.paused = true,
+            "resume" => self.paused = false,
+            "press" | "release" => {
+                let key = params::<KeyParams>(p)?.key()?;
+                self.keys.push((key, method == "press"));
+            }
+            "registers" => {
+                return Ok(json!({
+                    "v": (0..16).map(|x| emulator.reg(x)).collect::<Vec<u8>>(),
+                    "i": emulator.index(),
+                    "pc": emulator.pc(),
+                    "dt": emulator.delay_timer(),
+                    "st": emulator.sound_timer(),
+                    "stack": emulator.stack(),
+                }))
+            }
+            "set_register" => {
+                let SetRegisterParams { register, value } = params(p)?;
+                let byte = || {
+                    u8::try_from(value).map_err(|_| ACRpcError::new(INVALID_PARAMS, format!("{} only holds a byte", register)))
+                };
+                match register.to_ascii_lowercase().as_str() {
+                    "i" => emulator.set_index(value),
+                    "pc" => emulator.set_pc(value),
+                    "dt" => emulator.set_delay_timer(byte()?),
+                    "st" => emulator.set_sound_timer(byte()?),
+                    r => match r.strip_prefix('v').and_then(|x| usize::from_str_radix(x, 16).ok()) {
+                        Some(x) if x < 16 && r.len() == 2 => emulator.set_reg(x, byte()?),
+                        _ => return Err(ACRpcError::new(INVALID_PARAMS, format!("there is no register {:?}", register))),
+                    },
+                }
+            }
+            "read_memory" => {
+                let ReadMemoryParams { address, length } = params(p)?;
+                let memory = emulator.memory();
+                let bytes: Vec<u8> = (0..length.min(memory.len())).map(|n| memory[(address as usize + n) & 0xFFF]).collect();
+                return Ok(json!(bytes));
+            }
+            "write_memory" => {
+                let WriteMemoryParams { address, data } = params(p)?;
+                emulator.poke(address, &data);
+            }
+            "framebuffer" => {
+                let FramebufferParams { format } = params(p)?;
+                let bitmap = ACBitmap::from_framebuffer(emulator.framebuffer());
+                let (format, data) = match format {
+                    FramebufferFormat::Bits => ("bits", emulator.framebuffer().packed().to_vec()),
+                    FramebufferFormat::Png => {
+                        let png = bitmap.encode_png([255, 255, 255], [0, 0, 0]).map_err(|e| ACRpcError::new(SERVER_ERROR, e))?;
+                        ("png", png)
+                    }
+                };
+                return Ok(json!({
+                    "width": bitmap.width,
+                    "height": bitmap.height,
+                    "format": format,
+                    "data": base64(&data),
+                }));
+            }
+            "load_rom" => {
+                let rom = match params(p)? {
+                    LoadRomParams { data: Some(data), .. } => decode(&data)?,
+                    LoadRomParams { path: Some(path), .. } => {
+                        std::fs::read(&path).map_err(|e| ACRpcError::new(SERVER_ERROR, format!("{}: {}", path.display(), e)))?
+                    }
+                    _ => return Err(ACRpcError::new(INVALID_PARAMS, "load_rom needs a path or data")),
+                };
+                emulator.reset();
+                emulator.load_rom(rom);
+            }
+            "save_state" => return Ok(json!({ "state": base64(&emulator.save_state()) })),
+            "load_state" => {
+                let StateParams { state } = params(p)?;
+                emulator.load_state(&decode(&state)?).map_err(|e| ACRpcError::new(SERVER_ERROR, e))?;
+            }
+            "status" => {
+                return Ok(json!({
+                    "paused": self.paused,
+                    "frame": self.frame,
+                    "pc": emulator.pc(),
+                    "waiting_for_key": emulator.waiting_for_key(),
+                    "fault": emulator.fault().map(|f| format!("{:?}", f)),
+                }))
+            }
+            "quit" => self.quit = true,
+            _ => return Err(ACRpcError::new(METHOD_NOT_FOUND, format!("there is no method {:?}", method))),
+        }
+        Ok(Value::Null)
+    }
+}
+
+impl<F: ACFrontend> ACRenderer for ACControlled<'_, F> {
+    fn render(&mut self, framebuffer: &ACFramebuffer, tone: bool) -> Result<(), String> {
+        self.inner.render(framebuffer, tone)
+    }
+}
+
+impl<F: ACFrontend> ACFrontend for ACControlled<'_, F> {
+    fn poll_input(&mut self, keyboard: &mut ACKeyboard) -> Result<ACInput, String> {
+        // the frontend is always asked, so windows keep responding while paused
+        let mut new_keypress = match self.inner.poll_input(keyboard)? {
+            ACInput::Quit => return Ok(ACInput::Quit),
+            ACInput::Continue(key) => key,
+            ACInput::Pause => None,
+        };
+        if self.quit {
+            return Ok(ACInput::Quit);
+        }
+        for (key, down) in self.keys.drain(..) {
+            if !down {
+                keyboard.release(key);
+                continue;
+            }
+            if !keyboard.is_pressed(&key) {
+                new_keypress = new_keypress.or(Some(key));
+            }
+            keyboard.press(key);
+        }
+
+        let run = match &mut self.stepping {
+            Some((frames, _)) if *frames > 0 => {
+                *frames -= 1;
+                true
+            }
+            _ => !self.paused,
+        };
+        if run {
+            self.frame += 1;
+            Ok(ACInput::Continue(new_keypress))
+        } else {
+            Ok(ACInput::Pause)
+        }
+    }
+
+    fn realtime(&self) -> bool {
+        // no need to spin while paused
+        self.inner.realtime() || (self.paused && self.stepping.is_none())
+    }
+
+    fn run_frame(&mut self, emulator: &mut ACEmulator, keyboard: &ACKeyboard, new_keypress: Option<ACKey>, cycles: usize) -> Result<(), String> {
+        self.inner.run_frame(emulator, keyboard, new_keypress, cycles)
+    }
+
+    fn inspect(&mut self, emulator: &mut ACEmulator) -> Result<(), String> {
+        self.inner.inspect(emulator)?;
+        if matches!(self.stepping, Some((0, _))) {
+            if let Some((_, reply)) = self.stepping.take() {
+                let _ = reply.send(Ok(Value::Null));
+            }
+        }
+        // a step is only answered once it is done, so nothing else is taken on meanwhile
+        while self.stepping.is_none() {
+            let Ok(ACRequest { method, params: p, reply }) = self.requests.try_recv() else { break };
+            if method == "step" {
+                match params::<StepParams>(p) {
+                    Ok(StepParams { frames: 0 }) => {
+                        self.paused = true;
+                        let _ = reply.send(Ok(Value::Null));
+                    }
+                    Ok(StepParams { frames }) => {
+                        self.paused = true;
+                        self.stepping = Some((frames, reply));
+                    }
+                    Err(e) => {
+                        let _ = reply.send(Err(e));
+                    }
+                }
+                continue;
+            }
+            let result = self.handle(emulator, &method, p);
+            // the client may have gone away, which is fine
+            let _ = reply.send(result);
+        }
+        Ok(())
+    }
+}
+
+impl<F: ACFrontend> Drop for ACControlled<'_, F> {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.socket);
+    }
+}
+
+/// The response to a request with `id`
+fn response(id: Value, result: ACRpcResult) -> Value {
+    match result {
+        Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+        Err(e) => json!({ "jsonrpc": "2.0", "id": id, "error": { "code": e.code, "message": e.message } }),
+    }
+}
+
+/// Answers one line from a client, `None` for notifications (requests without an id)
+fn answer(line: &str, requests: &Sender<ACRequest>) -> Option<Value> {
+    let request: Value = match serde_json::from_str(line) {
+        Ok(request) => request,
+        Err(e) => return Some(response(Value::Null, Err(ACRpcError::new(PARSE_ERROR, e.to_string())))),
+    };
+    let id = request.get("id").cloned();
+    let Some(method) = request.get("method").and_then(Value::as_str) else {
+        let error = ACRpcError::new(INVALID_REQUEST, "a request needs a method");
+        return Some(response(id.unwrap_or(Value::Null), Err(error)));
+    };
+    let (reply, result) = mpsc::channel();
+    let request = ACRequest {
+        method: method.to_string(),
+        params: request.get("params").cloned().unwrap_or(Value::Null),
+        reply,
+    };
+    let result = match requests.send(request) {
+        Ok(()) => result.recv().unwrap_or_else(|_| Err(ACRpcError::new(SERVER_ERROR, "the emulator has stopped"))),
+        Err(_) => Err(ACRpcError::new(SERVER_ERROR, "the emulator has stopped")),
+    };
+    id.map(|id| response(id, result))
+}
+
+/// Listens on `socket`, passing requests to the returned channel
+#[cfg(unix)]
+fn listen(socket: &Path) -> Result<Receiver<ACRequest>, ACEmError> {
+    use std::io::{BufRead, BufReader, Write};
+    use std::os::unix::fs::FileTypeExt;
+    use std::os::unix::net::UnixListener;
+
+    // left behind by an emulator that didn't exit cleanly
+    if std::fs::metadata(socket).is_ok_and(|m| m.file_type().is_socket()) {
+        std::fs::remove_file(socket)?;
+    }
+    let listener = UnixListener::bind(socket)?;
+    log::info!("listening for commands on {}", socket.display());
+    let (requests, received) = mpsc::channel();
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(e) => {
+                    log::warn!("couldn't accept a connection: {}", e);
+                    continue;
+                }
+            };
+            let requests = requests.clone();
+            std::thread::spawn(move || -> std::io::Result<()> {
+                let mut out = stream.try_clone()?;
+                for line in BufReader::new(stream).lines() {
+                    let line = line?;
+                    if line.trim().is_empty() {
+                        continue;
+                    }
+                    if let Some(response) = answer(&line, &requests) {
+                        writeln!(out, "{}", response)?;
+                    }
+                }
+                Ok(())
+            });
+        }
+    });
+    Ok(received)
+}
+
+#[cfg(not(unix))]
+fn listen(_socket: &Path) -> Result<Receiver<ACRequest>, ACEmError> {
+    Err("--control needs unix sockets, which this platform doesn't have".to_string().into())
+}
+
+/// Runs `emulator` in `frontend` like `frontend::run`, also taking commands from `socket` if there is one
+pub fn run(emulator: &mut ACEmulator, frontend: &mut impl ACFrontend, cycles_per_frame: usize, socket: Option<&Path>) -> Result<(), ACEmError> {
+    let Some(socket) = socket else {
+        frontend::run(emulator, frontend, cycles_per_frame)?;
+        return Ok(());
+    };
+    let mut controlled = ACControlled::new(frontend, listen(socket)?, socket.to_path_buf());
+    frontend::run(emulator, &mut controlled, cycles_per_frame)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ate_chip::frontend::{ACHeadless, ACNullRenderer};
+
+    type ACTestFrontend = ACHeadless<ACNullRenderer>;
+
+    fn headless() -> ACTestFrontend {
+        ACHeadless { renderer: ACNullRenderer, frames: usize::MAX }
+    }
+
+    /// Goes once round `frontend::run`'s loop, returning whether a frame was run
+    fn tick(controlled: &mut ACControlled<ACTestFrontend>, emulator: &mut ACEmulator, keyboard: &mut ACKeyboard) -> bool {
+        let ran = match controlled.poll_input(keyboard).unwrap() {
+            ACInput::Continue(new_keypress) => {
+                controlled.run_frame(emulator, keyboard, new_keypress, 10).unwrap();
+                true
+            }
+            _ => false,
+        };
+        controlled.inspect(emulator).unwrap();
+        ran
+    }
+
+    /// Queues a request for `controlled`, returning where the reply will turn up
+    fn send(requests: &Sender<ACRequest>, method: &str, params: Value) -> Receiver<ACRpcResult> {
+        let (reply, replied) = mpsc::channel();
+        requests.send(ACRequest { method: method.to_string(), params, reply }).unwrap();
+        replied
+    }
+
+    #[test]
+    fn answers_follow_json_rpc() {
+        let (requests, received) = mpsc::channel();
+        // an emulator that answers with what it was asked
+        let emulator = std::thread::spawn(move || {
+            for ACRequest { method, params, reply } in received {
+                let result = match method.as_str() {
+                    "fail" => Err(ACRpcError::new(SERVER_ERROR, "failed")),
+                    _ => Ok(json!([method, params])),
+                };
+                reply.send(result).unwrap();
+            }
+        });
+        let answer = |line| answer(line, &requests);
+
+        assert_eq!(
+            answer(r#"{"jsonrpc": "2.0", "id": 1, "method": "echo", "params": [2]}"#),
+            Some(json!({ "jsonrpc": "2.0", "id": 1, "result": ["echo", [2]] }))
+        );
+        assert_eq!(answer(r#"{"jsonrpc": "2.0", "id": "a", "method": "echo"}"#).unwrap()["result"], json!(["echo", null]));
+        assert_eq!(
+            answer(r#"{"jsonrpc": "2.0", "id": 2, "method": "fail"}"#),
+            Some(json!({ "jsonrpc": "2.0", "id": 2, "error": { "code": SERVER_ERROR, "message": "failed" } }))
+        );
+        // notifications are run but not answered
+        assert_eq!(answer(r#"{"jsonrpc": "2.0", "method": "echo"}"#), None);
+
+        let error = answer("{").unwrap();
+        assert_eq!((&error["id"], &error["error"]["code"]), (&Value::Null, &json!(PARSE_ERROR)));
+        let error = answer(r#"{"jsonrpc": "2.0", "id": 3, "params": {}}"#).unwrap();
+        assert_eq!((&error["id"], &error["error"]["code"]), (&json!(3), &json!(INVALID_REQUEST)));
+
+        drop(requests);
+        emulator.join().unwrap();
+    }
+
+    #[test]
+    fn answers_once_the_emulator_has_stopped() {
+        let (requests, received) = mpsc::channel();
+        drop(received);
+        let error = answer(r#"{"jsonrpc": "2.0", "id": 1, "method": "status"}"#, &requests).unwrap();
+        assert_eq!(error["error"]["code"], json!(SERVER_ERROR));
+    }
+
+    #[test]
+    fn bad_requests_are_refused() {
+        let mut frontend = headless();
+        let (_requests, received) = mpsc::channel();
+        let mut controlled = ACControlled::new(&mut frontend, received, PathBuf::new());
+        let mut emulator = ACEmulator::new();
+        let mut code = |method, p| controlled.handle(&mut emulator, method, p).unwrap_err().code;
+
+        assert_eq!(code("jump", Value::Null), METHOD_NOT_FOUND);
+        assert_eq!(code("press", Value::Null), INVALID_PARAMS);
+        assert_eq!(code("press", json!({ "key": 16 })), INVALID_PARAMS);
+        assert_eq!(code("release", json!({ "key": "G" })), INVALID_PARAMS);
+        assert_eq!(code("set_register", json!({ "register": "vG", "value": 1 })), INVALID_PARAMS);
+        assert_eq!(code("set_register", json!({ "register": "v10", "value": 1 })), INVALID_PARAMS);
+        assert_eq!(code("set_register", json!({ "register": "dt", "value": 256 })), INVALID_PARAMS);
+        assert_eq!(code("read_memory", json!({ "address": -1, "length": 1 })), INVALID_PARAMS);
+        assert_eq!(code("load_rom", json!({})), INVALID_PARAMS);
+        assert_eq!(code("load_rom", json!({ "data": "not base64!" })), INVALID_PARAMS);
+        assert_eq!(code("load_state", json!({ "state": "" })), SERVER_ERROR);
+        assert!(controlled.keys.is_empty());
+    }
+
+    #[test]
+    fn requests_change_the_emulator() {
+        let mut frontend = headless();
+        let (_requests, received) = mpsc::channel();
+        let mut controlled = ACControlled::new(&mut frontend, received, PathBuf::new());
+        let mut emulator = ACEmulator::new();
+
+        controlled.handle(&mut emulator, "set_register", json!({ "register": "VA", "value": 0x12 })).unwrap();
+        controlled.handle(&mut emulator, "set_register", json!({ "register": "i", "value": 0x345 })).unwrap();
+        let registers = controlled.handle(&mut emulator, "registers", Value::Null).unwrap();
+        assert_eq!((&registers["v"][10], &registers["i"], &registers["pc"]), (&json!(0x12), &json!(0x345), &json!(0x200)));
+
+        controlled.handle(&mut emulator, "write_memory", json!({ "address": 0xFFF, "data": [1, 2] })).unwrap();
+        let read = controlled.handle(&mut emulator, "read_memory", json!({ "address": 0xFFF, "length": 2 })).unwrap();
+        assert_eq!(read, json!([1, 2]));
+
+        controlled.handle(&mut emulator, "press", json!({ "key": "a" })).unwrap();
+        controlled.handle(&mut emulator, "release", json!({ "key": 3 })).unwrap();
+        assert_eq!(controlled.keys, [(ACKey::from_hex(0xA).unwrap(), true), (ACKey::from_hex(3).unwrap(), false)]);
+    }
+
+    #[test]
+    fn loading_a_rom_keeps_the_quirks_and_random_numbers() {
+        let mut frontend = headless();
+        let (_requests, received) = mpsc::channel();
+        let mut controlled = ACControlled::new(&mut frontend, received, PathBuf::new());
+        // V0 = a random number
+        let rom = [0xC0, 0xFF, 0x12, 0x02];
+        let mut emulator = ACEmulator::new();
+        emulator.quirks.shift_uses_vy = !emulator.quirks.shift_uses_vy;
+        emulator.seed(7);
+        emulator.write_log = Some(vec![]);
+        emulator.load_rom([0x12, 0x00]);
+        emulator.set_reg(3, 1);
+
+        controlled.handle(&mut emulator, "load_rom", json!({ "data": base64(&rom) })).unwrap();
+        assert_eq!((emulator.pc(), emulator.reg(3), emulator.memory()[0x200..0x204] == rom), (0x200, 0, true));
+        assert_ne!(emulator.quirks.shift_uses_vy, ACEmulator::new().quirks.shift_uses_vy);
+        assert!(emulator.write_log.is_some());
+
+        let mut fresh = ACEmulator::new();
+        fresh.seed(7);
+        fresh.load_rom(rom);
+        let keyboard = ACKeyboard::new();
+        emulator.step(&keyboard, None);
+        fresh.step(&keyboard, None);
+        assert_eq!(emulator.reg(0), fresh.reg(0));
+    }
+
+    #[test]
+    fn steps_are_answered_once_the_frames_have_run() {
+        let mut frontend = headless();
+        let (requests, received) = mpsc::channel();
+        let mut controlled = ACControlled::new(&mut frontend, received, PathBuf::new());
+        let mut emulator = ACEmulator::new();
+        let mut keyboard = ACKeyboard::new();
+        assert!(tick(&mut controlled, &mut emulator, &mut keyboard));
+
+        let stepped = send(&requests, "step", json!({ "frames": 3 }));
+        // waits behind the step
+        let status = send(&requests, "status", Value::Null);
+        // the step is taken on after the frame that was already going
+        assert!(tick(&mut controlled, &mut emulator, &mut keyboard));
+        for _ in 0..3 {
+            assert!(stepped.try_recv().is_err());
+            assert!(status.try_recv().is_err());
+            assert!(tick(&mut controlled, &mut emulator, &mut keyboard));
+        }
+        assert!(stepped.try_recv().unwrap().unwrap().is_null());
+        let status = status.try_recv().unwrap().unwrap();
+        assert_eq!((&status["frame"], &status["paused"]), (&json!(5), &json!(true)));
+        // and stays paused after
+        assert!(!tick(&mut controlled, &mut emulator, &mut keyboard));
+
+        let stepped = send(&requests, "step", json!({ "frames": 0 }));
+        assert!(!tick(&mut controlled, &mut emulator, &mut keyboard));
+        assert!(stepped.try_recv().unwrap().is_ok());
+        let stepped = send(&requests, "step", json!({ "frames": "a few" }));
+        assert!(!tick(&mut controlled, &mut emulator, &mut keyboard));
+        assert_eq!(stepped.try_recv().unwrap().unwrap_err().code, INVALID_PARAMS);
+
+        send(&requests, "resume", Value::Null);
+        assert!(!tick(&mut controlled, &mut emulator, &mut keyboard));
+        assert!(tick(&mut controlled, &mut emulator, &mut keyboard));
+        send(&requests, "quit", Value::Null);
+        tick(&mut controlled, &mut emulator, &mut keyboard);
+        assert!(matches!(controlled.poll_input(&mut keyboard), Ok(ACInput::Quit)));
+    }
+}
@@ -0,0 +1,344 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+
+use crate::disasm::{disassemble, is_skip};
+
+/// line -> (hits, (taken, not taken) for each skip on the line)
+type LcovLines = BTreeMap<u32, (u32, Vec<(u32, u32)>)>;
+
+/// A piece of a rom, as the reports walk through it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ACChunk {
+    /// two bytes, run as an instruction or not
+    Instruction(u16),
+    /// a single byte that is most likely data, code that never ran directly before an instruction that did
+    Data(u8),
+}
+
+/// Which parts of a rom were run, and which way every skip instruction went
+pub struct ACCoverage {
+    /// number of times the instruction at each address was run
+    executed: Box<[u32; 4096]>,
+    /// (taken, not taken) counts for each skip instruction
+    skips: HashMap<u16, (u32, u32)>,
+}
+
+impl ACCoverage {
+    pub fn new() -> Self {
+        Self {
+            executed: Box::new([0; 4096]),
+            skips: HashMap::new(),
+        }
+    }
+
+    pub fn record_exec(&mut self, addr: u16) {
+        let count = &mut self.executed[addr as usize & 0xFFF];
+        *count = count.saturating_add(1);
+    }
+
+    pub fn record_skip(&mut self, addr: u16, taken: bool) {
+        let (t, nt) = self.skips.entry(addr & 0xFFF).or_insert((0, 0));
+        if taken {
+            *t = t.saturating_add(1);
+        } else {
+            *nt = nt.saturating_add(1);
+        }
+    }
+
+    /// how many times the instruction at `addr` was run
+    pub fn hits(&self, addr: u16) -> u32 {
+        self.executed[addr as usize & 0xFFF]
+    }
+
+    /// (taken, not taken) counts for the skip instruction at `addr`
+    pub fn skip_outcomes(&self, addr: u16) -> Option<(u32, u32)> {
+        self.skips.get(&(addr & 0xFFF)).copied()
+    }
+
+    /// `memory[start..end]` an instruction at a time, stepping over single bytes of data so that the code after
+    /// them is read from the right address
+    fn chunks<'a>(&'a self, memory: &'a [u8; 4096], start: u16, end: u16) -> impl Iterator<Item = (u16, ACChunk)> + 'a {
+        let end = (end as usize).min(memory.len());
+        let mut addr = start as usize;
+        std::iter::from_fn(move || {
+            if addr >= end {
+                return None;
+            }
+            let at = addr as u16;
+            if self.hits(at) == 0 && self.hits(at + 1) != 0 {
+                addr += 1;
+                return Some((at, ACChunk::Data(memory[at as usize])));
+            }
+            let instr = (memory[addr] as u16) << 8 | memory.get(addr + 1).copied().unwrap_or(0) as u16;
+            addr += 2;
+            Some((at, ACChunk::Instruction(instr)))
+        })
+    }
+
+    /// Annotated disassembly of `memory[start..end]`, one line per instruction.
+    ///
+    /// Each line is `hits addr bytes mnemonic`, where hits is `-----` for code that never ran.
+    /// Skip instructions are followed by how many times the skip was taken and not taken.
+    /// If `symbols` is given, lines are prefixed with the source location of the instruction
+    pub fn annotated_disassembly(&self, memory: &[u8; 4096], start: u16, end: u16, symbols: Option<&ACSymbols>) -> String {
+        let mut out = String::new();
+        for (addr, chunk) in self.chunks(memory, start, end) {
+            if let Some(loc) = symbols.and_then(|s| s.lookup(addr)) {
+                let _ = write!(out, "{}:{}\t", loc.0, loc.1);
+            }
+            let hits = self.hits(addr);
+            if hits == 0 {
+                let _ = write!(out, "{:>10}", "-----");
+            } else {
+                let _ = write!(out, "{:>10}", hits);
+            }
+            match chunk {
+                ACChunk::Instruction(instr) => {
+                    let _ = write!(out, "  {:03X}  {:04X}  {}", addr, instr, disassemble(instr));
+                    if is_skip(instr) {
+                        let (taken, not_taken) = self.skip_outcomes(addr).unwrap_or((0, 0));
+                        let _ = write!(out, "\t; taken {}, not taken {}", taken, not_taken);
+                    }
+                }
+                ACChunk::Data(byte) => {
+                    let _ = write!(out, "  {:03X}  {:02X}    DB {:02X}", addr, byte, byte);
+                }
+            }
+            out += "\n";
+        }
+        out
+    }
+
+    /// Coverage of `memory[start..end]` in lcov tracefile format.
+    ///
+    /// With a symbol table, addresses are mapped back to source lines. Without one
+    /// every instruction is reported against `rom_name`, using its address as the line number.
+    /// Bytes of data, as found for `annotated_disassembly`, aren't lines
+    pub fn lcov(&self, memory: &[u8; 4096], start: u16, end: u16, symbols: Option<&ACSymbols>, rom_name: &str) -> String {
+        let mut files: BTreeMap<String, LcovLines> = BTreeMap::new();
+        for (addr, chunk) in self.chunks(memory, start, end) {
+            let ACChunk::Instruction(instr) = chunk else { continue };
+            let (file, line) = match symbols {
+                Some(symbols) => match symbols.lookup(addr) {
+                    Some((file, line)) => (file.to_string(), line),
+                    // not something that was assembled from source
+                    None => continue,
+                },
+                None => (rom_name.to_string(), addr as u32),
+            };
+            let entry = files.entry(file).or_default().entry(line).or_insert((0, vec![]));
+            entry.0 = entry.0.max(self.hits(addr));
+            if is_skip(instr) {
+                entry.1.push(self.skip_outcomes(addr).unwrap_or((0, 0)));
+            }
+        }
+
+        let mut out = String::new();
+        out += "TN:\n";
+        for (file, lines) in files {
+            let _ = writeln!(out, "SF:{}", file);
+            let (mut brf, mut brh) = (0, 0);
+            for (line, (hits, branches)) in &lines {
+                for (block, (taken, not_taken)) in branches.iter().enumerate() {
+                    let reached = *hits != 0;
+                    for (branch, count) in [*taken, *not_taken].iter().enumerate() {
+                        brf += 1;
+                        if *count != 0 {
+                            brh += 1;
+                        }
+                        if reached {
+                            let _ = writeln!(out, "BRDA:{},{},{},{}", line, block, branch, count);
+                        } else {
+                            let _ = writeln!(out, "BRDA:{},{},{},-", line, block, branch);
+                        }
+                    }
+                }
+            }
+            if brf != 0 {
+                let _ = writeln!(out, "BRF:{}", brf);
+                let _ = writeln!(out, "BRH:{}", brh);
+            }
+            for (line, (hits, _)) in &lines {
+                let _ = writeln!(out, "DA:{},{}", line, hits);
+            }
+            let _ = writeln!(out, "LF:{}", lines.len());
+            let _ = writeln!(out, "LH:{}", lines.values().filter(|(hits, _)| *hits != 0).count());
+            out += "end_of_record\n";
+        }
+        out
+    }
+}
+
+impl Default for ACCoverage {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Maps rom addresses back to the source lines that they were assembled from.
+///
+/// The format is one entry per line, `<hex address> <file>:<line>`, e.g.
+/// ```text
+/// 0x202 game.8o:14
+/// ```
+/// blank lines, and lines starting with `#` are ignored.
+///
+/// Octo doesn't write this file itself, but its compiler keeps the source line of every address it assembles for
+/// its debugger (`romLineMap` in `compiler.js`), so printing that map after compiling gives one. A listing from
+/// any other assembler can be cut down to the same two columns
+pub struct ACSymbols {
+    lines: BTreeMap<u16, (String, u32)>,
+}
+
+impl ACSymbols {
+    pub fn parse(src: &str) -> Result<Self, String> {
+        let mut lines = BTreeMap::new();
+        for (n, line) in src.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let err = || format!("Invalid symbol table entry on line {}: {:?}", n + 1, line);
+            let (addr, loc) = line.split_once(char::is_whitespace).ok_or_else(err)?;
+            let addr = u16::from_str_radix(addr.trim_start_matches("0x"), 16).map_err(|_| err())?;
+            let (file, src_line) = loc.trim().rsplit_once(':').ok_or_else(err)?;
+            let src_line = src_line.parse().map_err(|_| err())?;
+            lines.insert(addr, (file.to_string(), src_line));
+        }
+        Ok(Self { lines })
+    }
+
+    /// The source location of the instruction at `addr`
+    pub fn lookup(&self, addr: u16) -> Option<(&str, u32)> {
+        self.lines.get(&addr).map(|(file, line)| (file.as_str(), *line))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emulator::ACEmulator;
+    use crate::keyboard::ACKeyboard;
+
+    /// runs a rom with a skip taken, one not taken, and a byte of data in front of more code
+    fn covered() -> ACEmulator {
+        let rom = [
+            0x60, 0x05, // 200: LD V0, 05
+            0x30, 0x05, // 202: SE V0, 05 (taken)
+            0x12, 0x04, // 204: JP 204 (never run)
+            0x30, 0x06, // 206: SE V0, 06 (not taken)
+            0x12, 0x0B, // 208: JP 20B
+            0xAA, //       20A: data
+            0x00, 0xE0, // 20B: CLS
+            0x40, 0x05, // 20D: SNE V0, 05 (not taken)
+            0x12, 0x0F, // 20F: JP 20F
+        ];
+        let mut emulator = ACEmulator::new();
+        emulator.coverage = Some(ACCoverage::new());
+        emulator.load_rom(rom);
+        let keyboard = ACKeyboard::new();
+        for _ in 0..10 {
+            emulator.step(&keyboard, None);
+        }
+        emulator
+    }
+
+    #[test]
+    fn skips_are_counted() {
+        let mut coverage = ACCoverage::new();
+        coverage.record_skip(0x300, true);
+        coverage.record_skip(0x300, true);
+        coverage.record_skip(0x300, false);
+        assert_eq!(coverage.skip_outcomes(0x300), Some((2, 1)));
+        assert_eq!(coverage.skip_outcomes(0x302), None);
+
+        let emulator = covered();
+        let coverage = emulator.coverage.as_ref().unwrap();
+        assert_eq!(coverage.skip_outcomes(0x202), Some((1, 0)));
+        assert_eq!(coverage.skip_outcomes(0x206), Some((0, 1)));
+        assert_eq!(coverage.skip_outcomes(0x20D), Some((0, 1)));
+        assert_eq!(coverage.hits(0x204), 0);
+        assert_eq!(coverage.hits(0x20F), 4);
+    }
+
+    #[test]
+    fn data_bytes_keep_the_code_after_them_aligned() {
+        let emulator = covered();
+        let coverage = emulator.coverage.as_ref().unwrap();
+        let disassembly = coverage.annotated_disassembly(emulator.memory(), 0x200, 0x211, None);
+        let expected = [
+            "         1  200  6005  LD V0, 05",
+            "         1  202  3005  SE V0, 05\t; taken 1, not taken 0",
+            "     -----  204  1204  JP 204",
+            "         1  206  3006  SE V0, 06\t; taken 0, not taken 1",
+            "         1  208  120B  JP 20B",
+            "     -----  20A  AA    DB AA",
+            "         1  20B  00E0  CLS",
+            "         1  20D  4005  SNE V0, 05\t; taken 0, not taken 1",
+            "         4  20F  120F  JP 20F",
+        ];
+        assert_eq!(disassembly.lines().collect::<Vec<_>>(), expected);
+    }
+
+    #[test]
+    fn lcov_reports_every_instruction_and_skip() {
+        let emulator = covered();
+        let coverage = emulator.coverage.as_ref().unwrap();
+        let lcov = coverage.lcov(emulator.memory(), 0x200, 0x211, None, "game.ch8");
+        let lines: Vec<_> = lcov.lines().collect();
+        assert_eq!(lines[..2], ["TN:", "SF:game.ch8"]);
+        // 0x20A is data, and the skip at 0x20D is found after it
+        for line in ["DA:512,1", "DA:514,1", "DA:516,0", "DA:518,1", "DA:520,1", "DA:523,1", "DA:525,1", "DA:527,4"] {
+            assert!(lines.contains(&line), "{} missing from\n{}", line, lcov);
+        }
+        assert!(!lines.iter().any(|l| l.starts_with("DA:522,") || l.starts_with("DA:524,")), "{}", lcov);
+        for line in [
+            "BRDA:514,0,0,1",
+            "BRDA:514,0,1,0",
+            "BRDA:518,0,0,0",
+            "BRDA:518,0,1,1",
+            "BRDA:525,0,0,0",
+            "BRDA:525,0,1,1",
+            "BRF:6",
+            "BRH:3",
+            "LF:8",
+            "LH:7",
+        ] {
+            assert!(lines.contains(&line), "{} missing from\n{}", line, lcov);
+        }
+        assert_eq!(lines.last(), Some(&"end_of_record"));
+    }
+
+    #[test]
+    fn lcov_uses_the_symbol_table() {
+        let emulator = covered();
+        let coverage = emulator.coverage.as_ref().unwrap();
+        let symbols = ACSymbols::parse("0x200 game.8o:1\n0x202 game.8o:2\n0x204 game.8o:2\n0x20D lib.8o:7\n").unwrap();
+        let lcov = coverage.lcov(emulator.memory(), 0x200, 0x211, Some(&symbols), "game.ch8");
+        let lines: Vec<_> = lcov.lines().collect();
+        assert!(!lines.contains(&"SF:game.ch8"), "{}", lcov);
+        let game = lines.iter().position(|&l| l == "SF:game.8o").unwrap();
+        let lib = lines.iter().position(|&l| l == "SF:lib.8o").unwrap();
+        assert_eq!(lines[game + 1..game + 6], ["BRDA:2,0,0,1", "BRDA:2,0,1,0", "BRF:2", "BRH:1", "DA:1,1"]);
+        assert_eq!(lines[game + 6..game + 10], ["DA:2,1", "LF:2", "LH:2", "end_of_record"]);
+        assert_eq!(lines[lib + 1..lib + 9], ["BRDA:7,0,0,0", "BRDA:7,0,1,1", "BRF:2", "BRH:1", "DA:7,1", "LF:1", "LH:1", "end_of_record"]);
+    }
+
+    #[test]
+    fn symbol_tables_parse() {
+        let symbols = ACSymbols::parse("# made by hand\n\n0x202 game.8o:14\n  2a0\tC:\\src\\lib.8o:3  \n").unwrap();
+        assert_eq!(symbols.lookup(0x202), Some(("game.8o", 14)));
+        assert_eq!(symbols.lookup(0x2A0), Some(("C:\\src\\lib.8o", 3)));
+        assert_eq!(symbols.lookup(0x200), None);
+
+        for (src, err) in [
+            ("0x202", "Invalid symbol table entry on line 1: \"0x202\""),
+            ("\n0x2G2 game.8o:1", "Invalid symbol table entry on line 2: \"0x2G2 game.8o:1\""),
+            ("0x202 game.8o", "Invalid symbol table entry on line 1: \"0x202 game.8o\""),
+            ("0x202 game.8o:x", "Invalid symbol table entry on line 1: \"0x202 game.8o:x\""),
+            ("0x10000 game.8o:1", "Invalid symbol table entry on line 1: \"0x10000 game.8o:1\""),
+        ] {
+            assert_eq!(ACSymbols::parse(src).err().as_deref(), Some(err));
+        }
+    }
+}
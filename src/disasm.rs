@@ -0,0 +1,186 @@
+/// Turns a single instruction into a human readable mnemonic,
+/// using the same names as http://devernay.free.fr/hacks/chip8/C8TECH10.HTM
+pub fn disassemble(instr: u16) -> String {
+    let x = (instr & 0x0F00) >> 8;
+    let y = (instr & 0x00F0) >> 4;
+    let n = instr & 0x000F;
+    let nn = instr & 0x00FF;
+    let nnn = instr & 0x0FFF;
+
+    match instr & 0xF000 {
+        0x0000 => match instr {
+            0x00E0 => "CLS".to_string(),
+            0x00EE => "RET".to_string(),
+            _ => format!("SYS {:03X}", nnn),
+        },
+        0x1000 => format!("JP {:03X}", nnn),
+        0x2000 => format!("CALL {:03X}", nnn),
+        0x3000 => format!("SE V{:X}, {:02X}", x, nn),
+        0x4000 => format!("SNE V{:X}, {:02X}", x, nn),
+        0x5000 if n == 0 => format!("SE V{:X}, V{:X}", x, y),
+        0x6000 => format!("LD V{:X}, {:02X}", x, nn),
+        0x7000 => format!("ADD V{:X}, {:02X}", x, nn),
+        0x8000 => match n {
+            0x0 => format!("LD V{:X}, V{:X}", x, y),
+            0x1 => format!("OR V{:X}, V{:X}", x, y),
+            0x2 => format!("AND V{:X}, V{:X}", x, y),
+            0x3 => format!("XOR V{:X}, V{:X}", x, y),
+            0x4 => format!("ADD V{:X}, V{:X}", x, y),
+            0x5 => format!("SUB V{:X}, V{:X}", x, y),
+            0x6 => format!("SHR V{:X}, V{:X}", x, y),
+            0x7 => format!("SUBN V{:X}, V{:X}", x, y),
+            0xE => format!("SHL V{:X}, V{:X}", x, y),
+            _ => format!("DW {:04X}", instr),
+        },
+        0x9000 if n == 0 => format!("SNE V{:X}, V{:X}", x, y),
+        0xA000 => format!("LD I, {:03X}", nnn),
+        0xB000 => format!("JP V0, {:03X}", nnn),
+        0xC000 => format!("RND V{:X}, {:02X}", x, nn),
+        0xD000 => format!("DRW V{:X}, V{:X}, {:X}", x, y, n),
+        0xE000 => match nn {
+            0x9E => format!("SKP V{:X}", x),
+            0xA1 => format!("SKNP V{:X}", x),
+            _ => format!("DW {:04X}", instr),
+        },
+        0xF000 => match nn {
+            0x07 => format!("LD V{:X}, DT", x),
+            0x0A => format!("LD V{:X}, K", x),
+            0x15 => format!("LD DT, V{:X}", x),
+            0x18 => format!("LD ST, V{:X}", x),
+            0x1E => format!("ADD I, V{:X}", x),
+            0x29 => format!("LD F, V{:X}", x),
+            0x33 => format!("LD B, V{:X}", x),
+            0x55 => format!("LD [I], V{:X}", x),
+            0x65 => format!("LD V{:X}, [I]", x),
+            _ => format!("DW {:04X}", instr),
+        },
+        _ => format!("DW {:04X}", instr),
+    }
+}
+
+/// The generic form of `instr`, like `8XY4` or `DXYN`, or `????` if it isn't one this interpreter understands
+pub fn pattern(instr: u16) -> &'static str {
+    if !is_known(instr) {
+        return if instr & 0xF000 == 0 { "0NNN" } else { "????" };
+    }
+    match instr & 0xF000 {
+        0x0000 => if instr == 0x00E0 { "00E0" } else { "00EE" },
+        0x1000 => "1NNN",
+        0x2000 => "2NNN",
+        0x3000 => "3XNN",
+        0x4000 => "4XNN",
+        0x5000 => "5XY0",
+        0x6000 => "6XNN",
+        0x7000 => "7XNN",
+        0x8000 => ["8XY0", "8XY1", "8XY2", "8XY3", "8XY4", "8XY5", "8XY6", "8XY7"]
+            .get((instr & 0xF) as usize)
+            .copied()
+            .unwrap_or("8XYE"),
+        0x9000 => "9XY0",
+        0xA000 => "ANNN",
+        0xB000 => "BNNN",
+        0xC000 => "CXNN",
+        0xD000 => "DXYN",
+        0xE000 => if instr & 0xFF == 0x9E { "EX9E" } else { "EXA1" },
+        _ => match instr & 0xFF {
+            0x07 => "FX07",
+            0x0A => "FX0A",
+            0x15 => "FX15",
+            0x18 => "FX18",
+            0x1E => "FX1E",
+            0x29 => "FX29",
+            0x33 => "FX33",
+            0x55 => "FX55",
+            _ => "FX65",
+        },
+    }
+}
+
+/// Is `instr` an instruction this interpreter understands, `0NNN` (machine code) isn't
+pub fn is_known(instr: u16) -> bool {
+    match instr & 0xF000 {
+        0x0000 => matches!(instr, 0x00E0 | 0x00EE),
+        0x5000 | 0x9000 => instr & 0x000F == 0,
+        0x8000 => matches!(instr & 0x000F, 0x0..=0x7 | 0xE),
+        0xE000 => matches!(instr & 0x00FF, 0x9E | 0xA1),
+        0xF000 => matches!(instr & 0x00FF, 0x07 | 0x0A | 0x15 | 0x18 | 0x1E | 0x29 | 0x33 | 0x55 | 0x65),
+        _ => true,
+    }
+}
+
+/// Is `instr` one of the conditional skip instructions (`3XNN`, `4XNN`, `5XY0`, `9XY0`, `EX9E`, `EXA1`)
+pub fn is_skip(instr: u16) -> bool {
+    matches!(instr & 0xF000, 0x3000 | 0x4000)
+        || matches!(instr & 0xF00F, 0x5000 | 0x9000)
+        || matches!(instr & 0xF0FF, 0xE09E | 0xE0A1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_instruction_disassembles() {
+        for (instr, text, pat) in [
+            (0x00E0, "CLS", "00E0"),
+            (0x00EE, "RET", "00EE"),
+            (0x0123, "SYS 123", "0NNN"),
+            (0x1ABC, "JP ABC", "1NNN"),
+            (0x2ABC, "CALL ABC", "2NNN"),
+            (0x31FF, "SE V1, FF", "3XNN"),
+            (0x4A07, "SNE VA, 07", "4XNN"),
+            (0x5120, "SE V1, V2", "5XY0"),
+            (0x6B42, "LD VB, 42", "6XNN"),
+            (0x7C01, "ADD VC, 01", "7XNN"),
+            (0x8120, "LD V1, V2", "8XY0"),
+            (0x8121, "OR V1, V2", "8XY1"),
+            (0x8122, "AND V1, V2", "8XY2"),
+            (0x8123, "XOR V1, V2", "8XY3"),
+            (0x8124, "ADD V1, V2", "8XY4"),
+            (0x8125, "SUB V1, V2", "8XY5"),
+            (0x8126, "SHR V1, V2", "8XY6"),
+            (0x8127, "SUBN V1, V2", "8XY7"),
+            (0x812E, "SHL V1, V2", "8XYE"),
+            (0x9DE0, "SNE VD, VE", "9XY0"),
+            (0xA123, "LD I, 123", "ANNN"),
+            (0xB123, "JP V0, 123", "BNNN"),
+            (0xC30F, "RND V3, 0F", "CXNN"),
+            (0xD125, "DRW V1, V2, 5", "DXYN"),
+            (0xD120, "DRW V1, V2, 0", "DXYN"),
+            (0xE59E, "SKP V5", "EX9E"),
+            (0xE5A1, "SKNP V5", "EXA1"),
+            (0xF607, "LD V6, DT", "FX07"),
+            (0xF60A, "LD V6, K", "FX0A"),
+            (0xF615, "LD DT, V6", "FX15"),
+            (0xF618, "LD ST, V6", "FX18"),
+            (0xF61E, "ADD I, V6", "FX1E"),
+            (0xF629, "LD F, V6", "FX29"),
+            (0xF633, "LD B, V6", "FX33"),
+            (0xF655, "LD [I], V6", "FX55"),
+            (0xF665, "LD V6, [I]", "FX65"),
+        ] {
+            assert_eq!(disassemble(instr), text, "{:04X}", instr);
+            assert_eq!(pattern(instr), pat, "{:04X}", instr);
+            assert_eq!(is_known(instr), pat != "0NNN", "{:04X}", instr);
+        }
+    }
+
+    #[test]
+    fn unknown_instructions_are_words() {
+        for instr in [0x5121, 0x8128, 0x812F, 0x9DE1, 0xE500, 0xF600, 0xF6FF] {
+            assert_eq!(disassemble(instr), format!("DW {:04X}", instr));
+            assert_eq!(pattern(instr), "????", "{:04X}", instr);
+            assert!(!is_known(instr), "{:04X}", instr);
+        }
+    }
+
+    #[test]
+    fn skips_are_found() {
+        for instr in [0x3000, 0x4FFF, 0x5120, 0x9120, 0xE19E, 0xEFA1] {
+            assert!(is_skip(instr), "{:04X}", instr);
+        }
+        for instr in [0x5121, 0x9121, 0xE1A0, 0xE19F, 0x1300, 0xF10A, 0x00EE] {
+            assert!(!is_skip(instr), "{:04X}", instr);
+        }
+    }
+}
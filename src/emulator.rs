@@ -1,11 +1,14 @@
-use owo_colors::OwoColorize;
-use sdl2::render::Texture;
-use std::time::Instant;
 use crate::keyboard::ACKey;
-
-const PIXEL: &str = "██";
-
-const SPRITE_CHARS: [[u8; 5]; 0x10] = [
+#[cfg(feature = "std")]
+use std::collections::BTreeSet;
+#[cfg(feature = "std")]
+use crate::coverage::ACCoverage;
+use crate::platform::{ACClock, ACDefaultRandom, ACRandom};
+use crate::quirks::ACQuirks;
+#[cfg(feature = "std")]
+use crate::sprites::ACSpriteRef;
+
+pub const SPRITE_CHARS: [[u8; 5]; 0x10] = [
     [0xF0, 0x90, 0x90, 0x90, 0xF0], // 0
     [0x20, 0x60, 0x20, 0x20, 0x70], // 1
     [0xF0, 0x10, 0xF0, 0x80, 0xF0], // 2
@@ -28,40 +31,177 @@ const SPRITE_CHARS_ADDR: u16 = 0;
 
 const STACK_SIZE: usize = 0x10;
 
+/// Something the program did that no interpreter could carry on from.
+/// The emulator stops running instructions once one of these happens
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ACFault {
+    /// `2NNN` with all 16 stack slots already in use
+    StackOverflow,
+    /// the program counter went past the end of memory, pc is left on the instruction that took it there
+    PcOutOfBounds,
+}
+
+#[cfg(feature = "std")]
+impl ACFault {
+    fn to_byte(self) -> u8 {
+        match self {
+            Self::StackOverflow => 1,
+            Self::PcOutOfBounds => 2,
+        }
+    }
+
+    fn from_byte(b: u8) -> Option<Option<Self>> {
+        Some(match b {
+            0 => None,
+            1 => Some(Self::StackOverflow),
+            2 => Some(Self::PcOutOfBounds),
+            _ => return None,
+        })
+    }
+}
+
+/// What an instruction does, decoded once so running it again is a single jump.
+/// Instructions the interpreter doesn't know are a `Nop`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ACOp {
+    /// nothing has been decoded at this address yet
+    #[cfg(feature = "decode-cache")]
+    Undecoded,
+    Nop,
+    Cls,
+    Ret,
+    Jump,
+    Call,
+    SkipEqByte,
+    SkipNeByte,
+    SkipEqReg,
+    LoadByte,
+    AddByte,
+    LoadReg,
+    Or,
+    And,
+    Xor,
+    AddReg,
+    Sub,
+    ShiftRight,
+    SubN,
+    ShiftLeft,
+    SkipNeReg,
+    LoadIndex,
+    JumpOffset,
+    Random,
+    Draw,
+    SkipKey,
+    SkipNotKey,
+    LoadDelay,
+    WaitKey,
+    SetDelay,
+    SetSound,
+    AddIndex,
+    LoadFont,
+    Bcd,
+    Store,
+    Load,
+}
 
-pub struct ACRenderer {
-    pixels: [[bool; 64]; 32],
-    //for caching the last render
-    last_pixels: Option<[[bool; 64]; 32]>,
-    last_render: Option<String>,
+/// An instruction with its operands pulled out, `nnn` being `x` and `nn` together and `n` the bottom of `nn`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct ACDecoded {
+    op: ACOp,
+    x: u8,
+    y: u8,
+    nn: u8,
 }
 
-impl ACRenderer {
-    /// Creates a new ACEmulator with all pixels set to black
+impl ACDecoded {
+    #[cfg(feature = "decode-cache")]
+    const UNDECODED: Self = Self { op: ACOp::Undecoded, x: 0, y: 0, nn: 0 };
+
+    fn decode(instr: u16) -> Self {
+        let [high, nn] = instr.to_be_bytes();
+        let (x, y, n) = (high & 0x0F, nn >> 4, nn & 0x0F);
+        let op = match (high >> 4, n, nn) {
+            // every other `0NNN` calls machine code, which there isn't any of
+            _ if instr == 0x00E0 => ACOp::Cls,
+            _ if instr == 0x00EE => ACOp::Ret,
+            (0x1, _, _) => ACOp::Jump,
+            (0x2, _, _) => ACOp::Call,
+            (0x3, _, _) => ACOp::SkipEqByte,
+            (0x4, _, _) => ACOp::SkipNeByte,
+            (0x5, 0x0, _) => ACOp::SkipEqReg,
+            (0x6, _, _) => ACOp::LoadByte,
+            (0x7, _, _) => ACOp::AddByte,
+            (0x8, 0x0, _) => ACOp::LoadReg,
+            (0x8, 0x1, _) => ACOp::Or,
+            (0x8, 0x2, _) => ACOp::And,
+            (0x8, 0x3, _) => ACOp::Xor,
+            (0x8, 0x4, _) => ACOp::AddReg,
+            (0x8, 0x5, _) => ACOp::Sub,
+            (0x8, 0x6, _) => ACOp::ShiftRight,
+            (0x8, 0x7, _) => ACOp::SubN,
+            (0x8, 0xE, _) => ACOp::ShiftLeft,
+            (0x9, 0x0, _) => ACOp::SkipNeReg,
+            (0xA, _, _) => ACOp::LoadIndex,
+            (0xB, _, _) => ACOp::JumpOffset,
+            (0xC, _, _) => ACOp::Random,
+            (0xD, _, _) => ACOp::Draw,
+            (0xE, _, 0x9E) => ACOp::SkipKey,
+            (0xE, _, 0xA1) => ACOp::SkipNotKey,
+            (0xF, _, 0x07) => ACOp::LoadDelay,
+            (0xF, _, 0x0A) => ACOp::WaitKey,
+            (0xF, _, 0x15) => ACOp::SetDelay,
+            (0xF, _, 0x18) => ACOp::SetSound,
+            (0xF, _, 0x1E) => ACOp::AddIndex,
+            (0xF, _, 0x29) => ACOp::LoadFont,
+            (0xF, _, 0x33) => ACOp::Bcd,
+            (0xF, _, 0x55) => ACOp::Store,
+            (0xF, _, 0x65) => ACOp::Load,
+            _ => ACOp::Nop,
+        };
+        Self { op, x, y, nn }
+    }
+
+    fn nnn(self) -> u16 {
+        (self.x as u16) << 8 | self.nn as u16
+    }
+}
+
+/// start of save states, followed by a version number
+#[cfg(feature = "std")]
+const STATE_MAGIC: &[u8; 4] = b"ACST";
+#[cfg(feature = "std")]
+const STATE_VERSION: u8 = 2;
+
+/// What is on the screen, frontends get a read only view of this through `ACEmulator::framebuffer`
+#[derive(Clone, PartialEq, Eq)]
+pub struct ACFramebuffer {
+    /// a row per `u64`, the leftmost pixel in the highest bit
+    rows: [u64; 32],
+}
+
+// rows are drawn a whole `u64` at a time
+const _: () = assert!(crate::SCREEN_WIDTH as u32 == u64::BITS && crate::SCREEN_HEIGHT == 32);
+
+impl ACFramebuffer {
+    /// Creates a new framebuffer with all pixels set to black
     pub fn new() -> Self {
         Self {
-            pixels: [[false; 64]; 32],
-            last_pixels: None,
-            last_render: None,
+            rows: [0; 32],
         }
     }
 
-    pub fn xor_pixel(&mut self, mut x: u8, mut y: u8, p: bool) -> bool {
-        if x >= 64 {
-            x -= 64;
-        } //else if x < 0 {
-        //    x += 64;
-        // }
-
-        if y > 32 {
-            y -= 32;
-        }// else if y < 0 {
-        //     y += 32;
-        // }
-
-        self.pixels[31-y as usize][x as usize] ^= p;
+    /// Flips the pixel at x, y if `p` is set, and returns if it was erased (colision detection)
+    pub fn xor_pixel(&mut self, x: usize, y: usize, p: bool) -> bool {
+        self.xor_row(y % 32, (p as u64) << (63 - x % 64))
+    }
 
-        !self.pixels[y as usize][x as usize]//return if the value at xy was erased
+    /// Flips every pixel in row `y` that is set in `bits` (leftmost pixel in the highest bit), and returns if any
+    /// were erased
+    pub fn xor_row(&mut self, y: usize, bits: u64) -> bool {
+        let row = &mut self.rows[y];
+        let erased = *row & bits != 0;
+        *row ^= bits;
+        erased
     }
 
     #[allow(dead_code)]
@@ -72,80 +212,53 @@ impl ACRenderer {
             x += 64;
         }
 
-        if y > 32 {
+        if y >= 32 {
             y -= 32;
         } else if y < 0 {
             y += 32;
         }
 
-        self.pixels[y as usize][x as usize] = v;
+        let bit = 1 << (63 - x);
+        let row = &mut self.rows[y as usize];
+        *row = if v { *row | bit } else { *row & !bit };
     }
 
     pub fn clear(&mut self) {
-        self.pixels = [[false; 64]; 32]
+        self.rows = [0; 32]
     }
 
-    #[allow(dead_code)]
-    pub fn render_string(&mut self) -> String {
-        // caching yay
-        if let Some(last_pixels) = &self.last_pixels {
-            if *last_pixels == self.pixels {
-                return self.last_render.clone().unwrap();
-            }
-        }
-
-        let mut rendered = String::new();
+    /// Is the pixel at `x`, `y` on, with 0, 0 being the top left of the screen
+    pub fn get_pixel(&self, x: usize, y: usize) -> bool {
+        self.rows[y] << x >> 63 != 0
+    }
 
-        let row_end = "\n";
-        let px_off = &PIXEL.bg_rgb::<0, 0, 0>().fg_rgb::<0, 0, 0>().to_string();
-        let px_on = &PIXEL.fg_rgb::<105, 237, 44>().bg_rgb::<0, 0, 0>().to_string();
+    /// The screen a row at a time, the leftmost pixel in the highest bit
+    pub fn rows(&self) -> &[u64; 32] {
+        &self.rows
+    }
 
-        let mut first_row = true;
-        for row in self.pixels.iter().rev() {
-            if !first_row {
-                rendered += row_end;
-            } else {
-                first_row = false;
-            }
-            for px in row {
-                rendered += if *px {
-                    px_on
-                } else {
-                    px_off
-                };
-            }
+    /// The screen a row at a time, 8 pixels to a byte with the leftmost in the highest bit
+    pub fn packed(&self) -> [u8; 256] {
+        let mut packed = [0; 256];
+        for (bytes, row) in packed.chunks_mut(8).zip(&self.rows) {
+            bytes.copy_from_slice(&row.to_be_bytes());
         }
-
-        //caching yay
-        self.last_pixels = Some(self.pixels);
-        self.last_render = Some(rendered.clone());
-
-        rendered
+        packed
     }
+}
 
-    pub fn render_to_tex(&mut self, texture: &mut Texture) {
-        texture.with_lock(None, |buffer: &mut [u8], _pitch/* size of a row in bytes */: usize| {
-            let mut buf_ptr = 0;
-            for row in self.pixels.iter().rev() {
-                for px in row {
-                    if *px {
-                        buffer[buf_ptr] = 255;
-                        buffer[buf_ptr+1] = 255;
-                        buffer[buf_ptr+2] = 255;
-                    } else {
-                        buffer[buf_ptr] = 0;
-                        buffer[buf_ptr+1] = 0;
-                        buffer[buf_ptr+2] = 0;
-                    }
-                    buf_ptr += 3
-                }
-            }
-        }).expect("Rendered the current frame");
+impl Default for ACFramebuffer {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
-pub struct ACEmulator {
-    pub renderer: ACRenderer,
+/// The interpreter, getting its random numbers from `R`
+pub struct ACEmulator<R = ACDefaultRandom> {
+    /// what the program has drawn
+    framebuffer: ACFramebuffer,
+    /// which interpreter to behave like
+    pub quirks: ACQuirks,
     memory: [u8; 4096],
     regs: [u8; 16],
     /// index register?
@@ -157,323 +270,732 @@ pub struct ACEmulator {
     stack_ptr: u8,
     /// Enable sound
     tone: bool,
-    /// last time that the timer was decremented
-    t_last: Instant,
+    /// last time that the timer was decremented, in microseconds by the clock given to `update`
+    t_last: u64,
     /// last time that a instruction was run
-    i_last: Instant,
+    i_last: u64,
     /// paused untill a key is sent
     waiting_for_key: bool,
     waiting_for_key_reg: usize,
+    /// why the emulator stopped, if it did
+    fault: Option<ACFault>,
+    /// where `CXNN` gets its random numbers
+    rng: R,
+    /// every instruction run so far, decoded, by address. Writes to memory drop the instructions they change
+    #[cfg(feature = "decode-cache")]
+    decoded: [ACDecoded; 4096],
+    /// native code for hot parts of the program, if enabled with `enable_jit`
+    #[cfg(feature = "jit")]
+    jit: Option<Box<jit::ACJit>>,
+    /// records what code was run, if enabled
+    #[cfg(feature = "std")]
+    pub coverage: Option<ACCoverage>,
+    /// addresses written to by the program since this was last drained, if enabled
+    #[cfg(feature = "std")]
+    pub write_log: Option<Vec<u16>>,
+    /// every sprite drawn by the program, if enabled
+    #[cfg(feature = "std")]
+    pub sprite_log: Option<BTreeSet<ACSpriteRef>>,
+    /// (address, instruction) of every instruction run that isn't understood, if enabled
+    #[cfg(feature = "std")]
+    pub unknown_log: Option<BTreeSet<(u16, u16)>>,
 }
 
 impl ACEmulator {
+    /// An emulator with random numbers seeded by the OS, or always the same ones without `std`
     pub fn new() -> Self {
-        let mut mem = [0; 4096];
-        for (i, sprite) in SPRITE_CHARS.iter().enumerate() {
-            let p = SPRITE_CHARS_ADDR as usize + i * sprite.len();
-            mem[p..p + sprite.len()].copy_from_slice(sprite)
-        }
+        Self::with_random(crate::platform::default_random())
+    }
+}
+
+impl<R: ACRandom> ACEmulator<R> {
+    pub fn with_random(rng: R) -> Self {
         Self {
-            renderer: ACRenderer::new(),
-            memory: mem,
+            framebuffer: ACFramebuffer::new(),
+            quirks: ACQuirks::default(),
+            memory: Self::initial_memory(),
             regs: [0; 16],
             i: 0,
             dt: 0,
             st: 0,
-            pc: 0x200 as usize,
+            pc: 0x200,
             stack: [0; 0x10],
             stack_ptr: 0,
             tone: false,
-            t_last: Instant::now(),
-            i_last: Instant::now(),
+            t_last: 0,
+            i_last: 0,
             waiting_for_key: false,
             waiting_for_key_reg: 0,//this will always be set to something before it is needed
+            fault: None,
+            rng,
+            #[cfg(feature = "decode-cache")]
+            decoded: [ACDecoded::UNDECODED; 4096],
+            #[cfg(feature = "jit")]
+            jit: None,
+            #[cfg(feature = "std")]
+            coverage: None,
+            #[cfg(feature = "std")]
+            write_log: None,
+            #[cfg(feature = "std")]
+            sprite_log: None,
+            #[cfg(feature = "std")]
+            unknown_log: None,
         }
 
     }
 
+    /// Memory with nothing but the font in it
+    fn initial_memory() -> [u8; 4096] {
+        let mut mem = [0; 4096];
+        for (i, sprite) in SPRITE_CHARS.iter().enumerate() {
+            let p = SPRITE_CHARS_ADDR as usize + i * sprite.len();
+            mem[p..p + sprite.len()].copy_from_slice(sprite)
+        }
+        mem
+    }
+
+    /// Puts the machine back how it was when it was made, ready for `load_rom`. The quirks, where the random numbers
+    /// are up to, the JIT and the debugging aids are all kept
+    pub fn reset(&mut self) {
+        self.framebuffer.clear();
+        self.memory = Self::initial_memory();
+        self.forget_all_decoded();
+        self.regs = [0; 16];
+        self.i = 0;
+        self.dt = 0;
+        self.st = 0;
+        self.pc = 0x200;
+        self.stack = [0; STACK_SIZE];
+        self.stack_ptr = 0;
+        self.tone = false;
+        self.t_last = 0;
+        self.i_last = 0;
+        self.waiting_for_key = false;
+        self.waiting_for_key_reg = 0;
+        self.fault = None;
+    }
+
+    /// the screen, for frontends to draw
+    pub fn framebuffer(&self) -> &ACFramebuffer {
+        &self.framebuffer
+    }
+
     pub fn should_bleep(&self) -> bool {
         self.tone
     }
 
+    /// is the program paused on `FX0A`
+    pub fn waiting_for_key(&self) -> bool {
+        self.waiting_for_key
+    }
+
     /// must be called 60 times per second
-    pub fn update(&mut self, keypad: &crate::keyboard::ACKeyboard, new_keypress: Option<ACKey>) {
-        log::debug!("updating");
-        let now = Instant::now();
-        if (now - self.t_last).as_nanos()  > 16_666_666 {//60hz
-            log::debug!("updating timer");
-            if self.dt != 0 {
-                self.dt -= 1;
-            }
-            self.tone = if self.st != 0 {
-                self.st -= 1;
-                true
-            } else {
-                false
-            };
+    pub fn update(&mut self, clock: &impl ACClock, keypad: &crate::keyboard::ACKeyboard, new_keypress: Option<ACKey>) {
+        let now = clock.micros();
+        if now.saturating_sub(self.t_last) > 16_666 {//60hz
+            self.tick_timers();
             self.t_last = now;
         }
-        if (now - self.i_last).as_millis() > 10 && !self.waiting_for_key {
-            log::debug!("running instruction");
-            if self.pc + 1 == self.memory.len() {
-                panic!("Reached the end");
-            }
-            let instr: u16 = ((self.memory[self.pc] as u16) << 8) | self.memory[self.pc + 1] as u16;
-            self.pc += 2;
-            self.exec_oper(instr, keypad);
+        if now.saturating_sub(self.i_last) > 10_000 && !self.waiting_for_key {
+            self.step(keypad, None);
             self.i_last = now;
         } else if self.waiting_for_key {
+            self.step(keypad, new_keypress);
+        };
+    }
+
+    /// Decrements the delay and sound timers, this is what `update` does at 60hz
+    pub fn tick_timers(&mut self) {
+        if self.dt != 0 {
+            self.dt -= 1;
+        }
+        self.tone = if self.st != 0 {
+            self.st -= 1;
+            true
+        } else {
+            false
+        };
+    }
+
+    /// Runs the next instruction, regardless of how long ago the last one was.
+    ///
+    /// If the program is waiting on `FX0A`, this instead stores `new_keypress` (if there is one) and resumes
+    pub fn step(&mut self, keypad: &crate::keyboard::ACKeyboard, new_keypress: Option<ACKey>) {
+        if self.fault.is_some() {
+            return;
+        }
+        if self.waiting_for_key {
             if let Some(key) = new_keypress {
                 self.regs[self.waiting_for_key_reg] = key.to_hex();
                 self.waiting_for_key = false;
             }
-        };
+            return;
+        }
+        if self.pc + 1 >= self.memory.len() {
+            self.halt(ACFault::PcOutOfBounds);
+            return;
+        }
+        let op = self.fetch(self.pc);
+        #[cfg(feature = "std")]
+        if let Some(coverage) = &mut self.coverage {
+            coverage.record_exec(self.pc as u16);
+        }
+        #[cfg(feature = "std")]
+        if let Some(log) = &mut self.unknown_log {
+            let instr = u16::from_be_bytes([self.memory[self.pc], self.memory[self.pc + 1]]);
+            if !crate::disasm::is_known(instr) {
+                log.insert((self.pc as u16, instr));
+            }
+        }
+        let at = self.pc;
+        self.pc += 2;
+        self.execute(op, keypad);
+        self.check_pc(at);
+    }
+
+    /// Faults if the instruction at `at` left pc past the end of memory, moving pc back onto it, so that pc always
+    /// points into memory
+    fn check_pc(&mut self, at: usize) {
+        if self.pc > 0xFFF {
+            self.pc = at;
+            self.halt(ACFault::PcOutOfBounds);
+        }
     }
 
+    /// Runs one 60th of a second worth of emulation without looking at the clock,
+    /// `cycles` instructions followed by a tick of the timers. For running headless
+    pub fn run_frame(&mut self, keypad: &crate::keyboard::ACKeyboard, mut new_keypress: Option<ACKey>, cycles: usize) {
+        #[cfg(feature = "jit")]
+        if self.jit.is_some() {
+            return self.run_frame_jit(keypad, new_keypress, cycles);
+        }
+        for _ in 0..cycles {
+            let waiting = self.waiting_for_key;
+            self.step(keypad, new_keypress);
+            // a key press can only be used by one `FX0A`
+            if waiting {
+                new_keypress = None;
+            }
+        }
+        self.tick_timers();
+    }
+
+    /// Runs `instr` as if it were at the program counter, without fetching anything
     pub fn exec_oper(&mut self, instr: u16, keypad: &crate::keyboard::ACKeyboard) {
-        let code = instr & 0xF000;
-        let x = ((instr & 0x0F00) >> 8) as usize;
-        let y = ((instr & 0x00F0) >> 4) as usize;
-        let n = instr & 0x000F;
-        let nn = instr & 0x00FF;
-        let nnn = instr & 0x0FFF;
-        log::debug!("inst: {:4X} at {}, i:{}\nargs:\n{} {} {} {} {}", code, self.pc, self.i, x, y, n, nn, nnn);
-
-        match code {
-            0x0000 => {
-                match n {
-                    // 00E0 - CLS
-                    0 => {
-                        self.renderer.clear();
-                    }
-                    // 00EE - RET
-                    0x0E => {
-                        self.pc = self.stack[self.stack_ptr as usize] as usize;
-                        if self.stack_ptr > 0 {
-                            self.stack_ptr -= 1;
-                        }
-                    }
-                    //http://devernay.free.fr/hacks/chip8/C8TECH10.HTM#0nnn
-                    _ => (),
+        let at = self.pc;
+        self.execute(ACDecoded::decode(instr), keypad);
+        self.check_pc(at);
+    }
+
+    fn execute(&mut self, op: ACDecoded, keypad: &crate::keyboard::ACKeyboard) {
+        let x = op.x as usize;
+        let y = op.y as usize;
+        let n = op.nn as u16 & 0x000F;
+        let nn = op.nn as u16;
+        let nnn = op.nnn();
+
+        match op.op {
+            #[cfg(feature = "decode-cache")]
+            ACOp::Undecoded => (),
+            ACOp::Nop => (),
+            // 00E0 - CLS
+            ACOp::Cls => {
+                self.framebuffer.clear();
+            }
+            // 00EE - RET (does nothing if the stack is empty)
+            ACOp::Ret => {
+                if self.stack_ptr > 0 {
+                    self.stack_ptr -= 1;
+                    self.pc = self.stack[self.stack_ptr as usize] as usize;
                 }
             }
-            0x1000 => {
+            ACOp::Jump => {
                 //JMP addr
                 self.pc = nnn as usize;
             }
-            0x2000 => {
+            ACOp::Call => {
                 // call at nn
-                self.stack_ptr += 1;
+                if self.stack_ptr as usize == STACK_SIZE {
+                    // leave pc on the call that failed
+                    self.pc = self.pc.wrapping_sub(2) & 0xFFF;
+                    self.halt(ACFault::StackOverflow);
+                    return;
+                }
                 self.stack[self.stack_ptr as usize] = self.pc as u16;
+                self.stack_ptr += 1;
                 self.pc = nnn as usize;
             }
-            0x3000 => {
+            ACOp::SkipEqByte => {
                 // skip next if Vx = nn
-                if self.regs[x] as u16 == nn {
-                    self.pc += 2;
-                }
+                self.skip_if(self.regs[x] as u16 == nn);
             }
-            0x4000 => {
+            ACOp::SkipNeByte => {
                 // skip next if Vx != nn
-                if self.regs[x] as u16 != nn {
-                    self.pc += 2;
-                }
+                self.skip_if(self.regs[x] as u16 != nn);
             }
-            0x5000 => {
+            ACOp::SkipEqReg => {
                 // skip next if Vx == Vy
-                if self.regs[x] == self.regs[y] {
-                    self.pc += 2;
-                }
+                self.skip_if(self.regs[x] == self.regs[y]);
             }
-            0x6000 => {
+            ACOp::LoadByte => {
                 // put nn into Vx
                 self.regs[x] = nn as u8;
             }
-            0x7000 => {
+            ACOp::AddByte => {
                 // add Vx to nn and store in x
                 self.regs[x] = (self.regs[x] as u16 + nn) as u8;
             }
-            0x8000 => {
-                match n {
-                    // 8XY0 - LD VX, VY
-                    0x00 => self.regs[x] = self.regs[y],
-                    // 8XY1 - OR VX, VY
-                    0x01 => self.regs[x] = self.regs[x] | self.regs[y],
-                    // 8XY2 - AND VX, VY
-                    0x02 => self.regs[x] = self.regs[x] & self.regs[y],
-                    // 8XY3 - XOR VX, VY
-                    0x03 => self.regs[x] = self.regs[x] ^ self.regs[y],
-                    // 8XY4 - ADD VX, VY
-                    0x04 => {
-                        let res = self.regs[x] as usize + self.regs[y] as usize;
-                        if res > 255 {
-                            // Carry to VF
-                            self.regs[0x0F] = 1;
-                        } else {
-                            self.regs[0x0F] = 0;
-                        }
-                        self.regs[x] = res as u8;
-                    }
-                    // 8XY5 - SUB VX, VY
-                    0x05 => {
-                        self.regs[0x0F] = if self.regs[x] > self.regs[y] {
-                            // Carry to VF
-                            1
-                        } else {
-                            0
-                        };
-                        self.regs[x] =
-                            (self.regs[x] as i32 - self.regs[y] as i32) as u8;
-                    }
-                    // 8XY6 - SHR VX {, VY}
-                    0x06 => {
-                        self.regs[0x0F] = self.regs[x] & 0x01;
-                        self.regs[x] /= 2;
-                    }
-                    // 8XY7 - SUBN VX, VY
-                    0x07 => {
-                        self.regs[0x0F] = if self.regs[y] > self.regs[x] {
-                            1
-                        } else {
-                            0
-                        };
-                        self.regs[x] = self.regs[y] - self.regs[x];
-                    }
-                    // 8XYE - SHL VX {, VY}
-                    0x0E => {
-                        self.regs[0x0F] = self.regs[x] & 0x80;
-                        self.regs[x] = (self.regs[x] as u16 * 2) as u8;
-                    }
-                    // Default
-                    _ => (),
+            // 8XY0 - LD VX, VY
+            ACOp::LoadReg => self.regs[x] = self.regs[y],
+            // 8XY1 - OR VX, VY
+            ACOp::Or => {
+                self.regs[x] |= self.regs[y];
+                if self.quirks.logic_resets_vf {
+                    self.regs[0x0F] = 0;
                 }
             }
-            0x9000 => {
-                // skip next if Vx != Vy
-                if self.regs[x] != self.regs[y] {
-                    self.pc += 2;
+            // 8XY2 - AND VX, VY
+            ACOp::And => {
+                self.regs[x] &= self.regs[y];
+                if self.quirks.logic_resets_vf {
+                    self.regs[0x0F] = 0;
                 }
             }
-            0xA000 => {
+            // 8XY3 - XOR VX, VY
+            ACOp::Xor => {
+                self.regs[x] ^= self.regs[y];
+                if self.quirks.logic_resets_vf {
+                    self.regs[0x0F] = 0;
+                }
+            }
+            // 8XY4 - ADD VX, VY
+            // (here and below VF is written last, so the flag wins when X is F)
+            ACOp::AddReg => {
+                let (res, carry) = self.regs[x].overflowing_add(self.regs[y]);
+                self.regs[x] = res;
+                self.regs[0x0F] = carry as u8;
+            }
+            // 8XY5 - SUB VX, VY
+            ACOp::Sub => {
+                // VF is 1 when there is no borrow
+                let (res, borrow) = self.regs[x].overflowing_sub(self.regs[y]);
+                self.regs[x] = res;
+                self.regs[0x0F] = !borrow as u8;
+            }
+            // 8XY6 - SHR VX {, VY}
+            ACOp::ShiftRight => {
+                let src = if self.quirks.shift_uses_vy { self.regs[y] } else { self.regs[x] };
+                self.regs[x] = src >> 1;
+                self.regs[0x0F] = src & 0x01;
+            }
+            // 8XY7 - SUBN VX, VY
+            ACOp::SubN => {
+                let (res, borrow) = self.regs[y].overflowing_sub(self.regs[x]);
+                self.regs[x] = res;
+                self.regs[0x0F] = !borrow as u8;
+            }
+            // 8XYE - SHL VX {, VY}
+            ACOp::ShiftLeft => {
+                let src = if self.quirks.shift_uses_vy { self.regs[y] } else { self.regs[x] };
+                self.regs[x] = src << 1;
+                self.regs[0x0F] = src >> 7;
+            }
+            ACOp::SkipNeReg => {
+                // skip next if Vx != Vy
+                self.skip_if(self.regs[x] != self.regs[y]);
+            }
+            ACOp::LoadIndex => {
                 // set the index to nnn
                 self.i = nnn;
             }
-            0xB000 => {
-                self.pc = nnn as usize + self.regs[0] as usize;
+            ACOp::JumpOffset => {
+                // jump to nnn + V0 (or VX)
+                let offset = if self.quirks.jump_uses_vx { self.regs[x] } else { self.regs[0] };
+                self.pc = nnn as usize + offset as usize;
             }
-            0xC000 => {
+            ACOp::Random => {
                 // generate a random num from 0-255, and store that & nn in reg x
-                self.regs[x] = nn as u8 & rand::random::<u8>();
+                self.regs[x] = nn as u8 & self.rng.random_byte();
             }
-            0xD000 => {
+            ACOp::Draw => {
                 //& Draw instruction
-                self.regs[0x0F] = 0;
-                let xpos: usize = self.regs[x] as usize % crate::SCREEN_WIDTH as usize;
-                let ypos: usize = self.regs[y] as usize % crate::SCREEN_HEIGHT as usize;
-                for row in 0..n {
-                    // Fetch bits
-                    let bits: u8 = self.memory[(self.i + row) as usize];
-                    // Current Y
-                    let cy = (ypos + row as usize) % crate::SCREEN_HEIGHT as usize;
-                    // Loop over bits
-                    for col in 0..8_usize {
-                        // Current X
-                        let cx = (xpos + col) % crate::SCREEN_WIDTH as usize;
-                        let mask: u8 = 0x01 << 7 - col;
-                        let color = (bits & mask) >> 7 - col;
-
-                        self.renderer.xor_pixel(cx.try_into().unwrap(), cy.try_into().unwrap(),
-                            if color == 1 {
-                                true
-                            } else if color == 0 {
-                                false
-                            } else {
-                                panic!()
-                            }
-                        );
-
-                        if cx == crate::SCREEN_WIDTH as usize - 1 {
-                            // Reached the right edge
-                            // eprintln!("Reached right edge");
-                            break;
-                        }
+                #[cfg(feature = "std")]
+                if let Some(log) = &mut self.sprite_log {
+                    if n != 0 {
+                        log.insert(ACSpriteRef { addr: self.i, height: n as u8 });
                     }
-                    if cy == crate::SCREEN_HEIGHT as usize - 1 {
+                }
+                let width = crate::SCREEN_WIDTH as usize;
+                let height = crate::SCREEN_HEIGHT as usize;
+                // the starting position always wraps, the rest of the sprite is clipped or wrapped depending on the quirk
+                let xpos: usize = self.regs[x] as usize % width;
+                let ypos: usize = self.regs[y] as usize % height;
+                let mut collision = false;
+                for row in 0..n as usize {
+                    // Current Y
+                    let cy = ypos + row;
+                    if cy >= height && !self.quirks.wrap_sprites {
                         // Reached the bottom edge
-                        // eprintln!("Reached bottom edge");
                         break;
                     }
+                    // the sprite's row at the left of the screen, then moved across, whatever goes off the right
+                    // edge either coming back on the left or being dropped
+                    let bits = (self.read_mem(self.i as usize + row) as u64) << 56;
+                    let bits = if self.quirks.wrap_sprites { bits.rotate_right(xpos as u32) } else { bits >> xpos };
+                    collision |= self.framebuffer.xor_row(cy % height, bits);
                 }
+                self.regs[0x0F] = collision as u8;
             }
-            0xE000 => {
-                match nn {
-                    0x9E => {
-                        // skip next if a key on the keyboard with the value Vx is pressed
-                        if keypad.is_pressed(&ACKey::from_hex(self.regs[x]).unwrap()) {
-                            self.pc += 2;
-                        }
-                    }
-                    0xA1 => {
-                        // skip next if a key on the keyboard with the value Vx is NOT pressed
-                        if !keypad.is_pressed(&ACKey::from_hex(self.regs[x]).unwrap()) {
-                            self.pc += 2;
-                        }
-                    }
-                    _ => (),
+            ACOp::SkipKey => {
+                // skip next if a key on the keyboard with the value Vx is pressed (only the low nibble counts)
+                self.skip_if(keypad.is_pressed(&ACKey::from_hex(self.regs[x] & 0x0F).expect("keys go up to 0xF")));
+            }
+            ACOp::SkipNotKey => {
+                // skip next if a key on the keyboard with the value Vx is NOT pressed
+                self.skip_if(!keypad.is_pressed(&ACKey::from_hex(self.regs[x] & 0x0F).expect("keys go up to 0xF")));
+            }
+            // FX07 set Vx to delay timer
+            ACOp::LoadDelay => self.regs[x] = self.dt,
+            ACOp::WaitKey => {
+                // pause untill a kepress has occured, storing the key in Vx is handled elswhere
+                self.waiting_for_key = true;
+                self.waiting_for_key_reg = x;
+            }
+            // FX15 set delay timer to Vx
+            ACOp::SetDelay => self.dt = self.regs[x],
+            // FX18 set sound timer to Vx
+            ACOp::SetSound => self.st = self.regs[x],
+            // FX1E set the index register to itself plus Vx
+            ACOp::AddIndex => {
+                self.i = self.i.wrapping_add(self.regs[x] as u16);
+                if self.quirks.fx1e_affects_vf {
+                    self.regs[0x0F] = (self.i > 0xFFF) as u8;
                 }
             }
-            0xF000 => {
-                match nn {
-                    // FX07 set Vx to delay timer
-                    0x07 => self.regs[x] = self.dt,
-                    0x0A => {
-                        // pause untill a kepress has occured, storing the key in Vx is handled elswhere
-                        self.waiting_for_key = true;
-                        self.waiting_for_key_reg = x as usize;
-                    }
-                    // FX15 set delay timer to Vx
-                    0x15 => self.dt = self.regs[x],
-                    // FX18 set sound timer to Vx
-                    0x18 => self.st = self.regs[x],
-                    // FX1E set the index register to itself plus Vx
-                    0x1E => self.i = self.i + self.regs[x] as u16,
-                    // FX29 set I to location of sprite for digit VX
-                    0x29 => self.i = self.regs[x] as u16 * 0x05,
-                    // FX33 store BCD representation of VX in I, I+1 and I+2
-                    0x33 => {
-                        let num = self.regs[x];
-                        let h = num / 100;
-                        let t = (num - h * 100) / 10;
-                        let o = num - h * 100 - t * 10;
-                        let i = self.i as usize;
-                        self.memory[i] = h;
-                        self.memory[i + 1] = t;
-                        self.memory[i + 2] = o;
-                    }
-                    // FX55 set memory starting at I to values in V0 to VX
-                    0x55 => {
-                        let n: usize = x;
-                        for reg in 0..n + 1 {
-                            self.memory[self.i as usize + reg] = self.regs[reg];
-                        }
-                    }
-                    // FX65 set registers V0 to VX to memory starting at I
-                    0x65 => {
-                        let n: usize = x;
-                        for reg in 0..n + 1 {
-                            self.regs[reg] = self.memory[self.i as usize + reg];
-                        }
-                    }
-                    _ => (),
+            // FX29 set I to location of sprite for digit VX
+            ACOp::LoadFont => self.i = SPRITE_CHARS_ADDR + (self.regs[x] & 0x0F) as u16 * 0x05,
+            // FX33 store BCD representation of VX in I, I+1 and I+2
+            ACOp::Bcd => {
+                let num = self.regs[x];
+                let h = num / 100;
+                let t = (num - h * 100) / 10;
+                let o = num - h * 100 - t * 10;
+                let i = self.i as usize;
+                self.write_mem(i, h);
+                self.write_mem(i + 1, t);
+                self.write_mem(i + 2, o);
+            }
+            // FX55 set memory starting at I to values in V0 to VX
+            ACOp::Store => {
+                let n: usize = x;
+                for reg in 0..n + 1 {
+                    self.write_mem(self.i as usize + reg, self.regs[reg]);
+                }
+                if self.quirks.load_store_increments_i {
+                    self.i = self.i.wrapping_add(n as u16 + 1);
                 }
             }
-            _ => (),
+            // FX65 set registers V0 to VX to memory starting at I
+            ACOp::Load => {
+                let n: usize = x;
+                for reg in 0..n + 1 {
+                    self.regs[reg] = self.read_mem(self.i as usize + reg);
+                }
+                if self.quirks.load_store_increments_i {
+                    self.i = self.i.wrapping_add(n as u16 + 1);
+                }
+            }
+        }
+    }
+
+    /// skips the next instruction if `cond` is true, recording the outcome for coverage
+    fn skip_if(&mut self, cond: bool) {
+        #[cfg(feature = "std")]
+        if let Some(coverage) = &mut self.coverage {
+            coverage.record_skip((self.pc.wrapping_sub(2) & 0xFFF) as u16, cond);
+        }
+        if cond {
+            self.pc += 2;
+        }
+    }
+
+    /// Stops the emulator for good
+    fn halt(&mut self, fault: ACFault) {
+        log::error!("Emulator stopped at {:03X}: {:?}", self.pc, fault);
+        self.fault = Some(fault);
+    }
+
+    /// Makes `CXNN` give the same numbers every time for `seed`, instead of different ones each run
+    pub fn seed(&mut self, seed: u64) {
+        self.rng.reseed(seed);
+    }
+
+    /// why the emulator stopped running, if it has
+    pub fn fault(&self) -> Option<ACFault> {
+        self.fault
+    }
+
+    /// reads memory on behalf of the running program, addresses past the end wrap around
+    fn read_mem(&self, addr: usize) -> u8 {
+        self.memory[addr & 0xFFF]
+    }
+
+    /// writes to memory on behalf of the running program, addresses past the end wrap around
+    fn write_mem(&mut self, addr: usize, v: u8) {
+        let addr = addr & 0xFFF;
+        self.memory[addr] = v;
+        self.forget_decoded(addr);
+        #[cfg(feature = "std")]
+        if let Some(log) = &mut self.write_log {
+            log.push(addr as u16);
+        }
+    }
+
+    /// The instruction at `addr`, decoded the first time it is run and kept until memory under it is written
+    #[cfg(feature = "decode-cache")]
+    fn fetch(&mut self, addr: usize) -> ACDecoded {
+        let op = self.decoded[addr];
+        if op.op == ACOp::Undecoded {
+            return self.decode_at(addr);
+        }
+        op
+    }
+
+    /// The instruction at `addr`, decoded every time
+    #[cfg(not(feature = "decode-cache"))]
+    fn fetch(&mut self, addr: usize) -> ACDecoded {
+        ACDecoded::decode(u16::from_be_bytes([self.memory[addr], self.memory[addr + 1]]))
+    }
+
+    /// Decodes the instruction at `addr` and keeps it for next time, out of line so running cached instructions stays quick
+    #[cfg(feature = "decode-cache")]
+    #[cold]
+    #[inline(never)]
+    fn decode_at(&mut self, addr: usize) -> ACDecoded {
+        let op = ACDecoded::decode(u16::from_be_bytes([self.memory[addr], self.memory[addr + 1]]));
+        self.decoded[addr] = op;
+        op
+    }
+
+    /// Drops the decoded instructions that include the byte at `addr`, the one starting there and the one before it,
+    /// and any compiled code made from it
+    #[cfg_attr(not(any(feature = "decode-cache", feature = "jit")), allow(unused_variables))]
+    fn forget_decoded(&mut self, addr: usize) {
+        #[cfg(feature = "decode-cache")]
+        {
+            self.decoded[addr] = ACDecoded::UNDECODED;
+            self.decoded[addr.wrapping_sub(1) & 0xFFF] = ACDecoded::UNDECODED;
+        }
+        #[cfg(feature = "jit")]
+        if let Some(jit) = &mut self.jit {
+            jit.written(addr);
+        }
+    }
+
+    /// Drops every decoded instruction and all compiled code, for when the whole program changes
+    fn forget_all_decoded(&mut self) {
+        #[cfg(feature = "decode-cache")]
+        {
+            self.decoded = [ACDecoded::UNDECODED; 4096];
+        }
+        #[cfg(feature = "jit")]
+        if let Some(jit) = &mut self.jit {
+            jit.reset();
+        }
+    }
+
+    /// Read only view of the emulators memory
+    pub fn memory(&self) -> &[u8; 4096] {
+        &self.memory
+    }
+
+    /// the value of register `Vx`
+    pub fn reg(&self, x: usize) -> u8 {
+        self.regs[x]
+    }
+
+    pub fn sound_timer(&self) -> u8 {
+        self.st
+    }
+
+    pub fn delay_timer(&self) -> u8 {
+        self.dt
+    }
+
+    /// sets register `Vx`, for debuggers and automation
+    pub fn set_reg(&mut self, x: usize, v: u8) {
+        self.regs[x] = v;
+    }
+
+    pub fn set_index(&mut self, v: u16) {
+        self.i = v;
+    }
+
+    /// jumps to `addr`, wrapping around past the end of memory
+    pub fn set_pc(&mut self, addr: u16) {
+        self.pc = addr as usize & 0xFFF;
+    }
+
+    pub fn set_delay_timer(&mut self, v: u8) {
+        self.dt = v;
+    }
+
+    pub fn set_sound_timer(&mut self, v: u8) {
+        self.st = v;
+    }
+
+    /// Writes `data` into memory starting at `addr`, wrapping around past the end.
+    /// Unlike writes made by the program, these are not recorded in `write_log`
+    pub fn poke(&mut self, addr: u16, data: &[u8]) {
+        for (n, v) in data.iter().enumerate() {
+            let addr = (addr as usize + n) & 0xFFF;
+            self.memory[addr] = *v;
+            self.forget_decoded(addr);
+        }
+    }
+
+    /// address of the next instruction to be run
+    pub fn pc(&self) -> u16 {
+        self.pc as u16
+    }
+
+    /// the index register
+    pub fn index(&self) -> u16 {
+        self.i
+    }
+
+    /// return addresses currently on the stack, oldest first
+    pub fn stack(&self) -> &[u16] {
+        &self.stack[..self.stack_ptr as usize]
+    }
+
+    /// Copies `rom` into memory at the program counter, anything that does not fit is dropped
+    pub fn load_rom(&mut self, rom: impl AsRef<[u8]>) {
+        let rom = rom.as_ref();
+        let space = self.memory.len() - self.pc.min(self.memory.len());
+        if rom.len() > space {
+            log::warn!("Rom is {} bytes, only the first {} fit in memory", rom.len(), space);
+        }
+        for (mem, v) in self.memory.get_mut(self.pc..).into_iter().flatten().zip(rom.iter()) {
+            *mem = *v;
         }
+        self.forget_all_decoded();
     }
 
-    pub fn load_rom(&mut self, rom: Vec<u8>) {
-        for (i, v) in rom.iter().enumerate() {
-            self.memory[self.pc+i] = *v;
+    /// Everything needed to put the emulator back exactly how it is now with `load_state`, including where `CXNN` is
+    /// up to in its random numbers.
+    ///
+    /// Debugging aids (`coverage`, `write_log` and `sprite_log`) are not included
+    #[cfg(feature = "std")]
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut state = Vec::with_capacity(4096 + 128 + 32 * 64 / 8);
+        state.extend_from_slice(STATE_MAGIC);
+        state.push(STATE_VERSION);
+        state.extend_from_slice(&self.memory);
+        state.extend_from_slice(&self.regs);
+        state.extend_from_slice(&self.i.to_be_bytes());
+        state.push(self.dt);
+        state.push(self.st);
+        state.extend_from_slice(&(self.pc as u16).to_be_bytes());
+        for ret in &self.stack {
+            state.extend_from_slice(&ret.to_be_bytes());
+        }
+        state.push(self.stack_ptr);
+        state.push(self.tone as u8);
+        state.push(self.waiting_for_key as u8);
+        state.push(self.waiting_for_key_reg as u8);
+        state.push(self.fault.map_or(0, ACFault::to_byte));
+        state.push(self.quirks.to_bits());
+        state.extend_from_slice(&self.framebuffer.packed());
+        state.extend_from_slice(&self.rng.state().to_be_bytes());
+        state
+    }
+
+    /// Restores a state from `save_state`. If the state is invalid, nothing is changed
+    #[cfg(feature = "std")]
+    pub fn load_state(&mut self, state: &[u8]) -> Result<(), String> {
+        let mut rest = state;
+        let mut take = |len: usize| -> Result<&[u8], String> {
+            if rest.len() < len {
+                return Err("Save state is truncated".to_string());
+            }
+            let (taken, remaining) = rest.split_at(len);
+            rest = remaining;
+            Ok(taken)
+        };
+        let bool_byte = |b: u8| match b {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err("Save state is corrupt".to_string()),
+        };
+        let u16_at = |b: &[u8]| u16::from_be_bytes([b[0], b[1]]);
+
+        if take(4)? != STATE_MAGIC {
+            return Err("Not a save state".to_string());
+        }
+        let version = take(1)?[0];
+        if version != STATE_VERSION {
+            return Err(format!("Unsupported save state version {}", version));
+        }
+        let mut memory = [0; 4096];
+        memory.copy_from_slice(take(4096)?);
+        let mut regs = [0; 16];
+        regs.copy_from_slice(take(16)?);
+        let i = u16_at(take(2)?);
+        let dt = take(1)?[0];
+        let st = take(1)?[0];
+        let pc = u16_at(take(2)?) as usize;
+        let mut stack = [0; STACK_SIZE];
+        for ret in stack.iter_mut() {
+            *ret = u16_at(take(2)?);
+        }
+        let stack_ptr = take(1)?[0];
+        let tone = bool_byte(take(1)?[0])?;
+        let waiting_for_key = bool_byte(take(1)?[0])?;
+        let waiting_for_key_reg = take(1)?[0] as usize;
+        let fault = ACFault::from_byte(take(1)?[0]).ok_or("Save state is corrupt")?;
+        let quirks = ACQuirks::from_bits(take(1)?[0]).ok_or("Save state is corrupt")?;
+        let mut rows = [0; 32];
+        for row in rows.iter_mut() {
+            *row = u64::from_be_bytes(take(8)?.try_into().expect("took 8 bytes"));
         }
+        let rng = u64::from_be_bytes(take(8)?.try_into().expect("took 8 bytes"));
+        if !rest.is_empty() {
+            return Err("Save state has trailing data".to_string());
+        }
+        if stack_ptr as usize > STACK_SIZE || waiting_for_key_reg > 0xF || pc > 0xFFF {
+            return Err("Save state is corrupt".to_string());
+        }
+
+        self.memory = memory;
+        self.forget_all_decoded();
+        self.regs = regs;
+        self.i = i;
+        self.dt = dt;
+        self.st = st;
+        self.pc = pc;
+        self.stack = stack;
+        self.stack_ptr = stack_ptr;
+        self.tone = tone;
+        self.waiting_for_key = waiting_for_key;
+        self.waiting_for_key_reg = waiting_for_key_reg;
+        self.fault = fault;
+        self.quirks = quirks;
+        self.framebuffer.rows = rows;
+        self.rng.set_state(rng);
+        Ok(())
     }
-}
\ No newline at end of file
+}
+
+impl Default for ACEmulator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(feature = "jit")]
+mod jit;
+
+// the tests lean on std for save states, the debugging aids and the other modules
+#[cfg(all(test, feature = "std"))]
+mod tests;
@@ -0,0 +1,475 @@
+//! Compiles straight line runs of instructions to native code with cranelift, for running headless as fast as possible.
+//!
+//! Once the interpreter has been to an address often enough, everything from there up to the first instruction
+//! that skips, calls, returns, draws, waits for a key, stores to memory or isn't understood is compiled into a block,
+//! and the interpreter picks up from that instruction. `1NNN` is compiled, as it is how most loops end.
+//!
+//! Bytes the program writes to are never compiled, and writing to a compiled block throws it away,
+//! so self-modifying code is always left to the interpreter
+use core::mem::offset_of;
+
+use cranelift_codegen::ir::condcodes::IntCC;
+use cranelift_codegen::ir::{types, AbiParam, InstBuilder, MemFlags, Signature, Value};
+use cranelift_codegen::settings::{self, Configurable};
+use cranelift_frontend::{FunctionBuilder, FunctionBuilderContext, Variable};
+use cranelift_jit::{JITBuilder, JITModule};
+use cranelift_module::{default_libcall_names, Module};
+
+use super::{ACDecoded, ACEmulator, ACOp, SPRITE_CHARS_ADDR};
+use crate::keyboard::{ACKey, ACKeyboard};
+use crate::platform::ACRandom;
+use crate::quirks::ACQuirks;
+
+/// times the interpreter gets to an address before a block is compiled there
+const HOT: u8 = 16;
+/// the most instructions in one block
+const MAX_BLOCK: usize = 64;
+
+/// Runs at most `budget` instructions (always at least one), leaving pc after the last, and returns how many ran
+type ACBlock<R> = unsafe extern "C" fn(*mut ACEmulator<R>, u32) -> u32;
+
+#[derive(Debug, Clone, Copy)]
+enum ACSlot {
+    /// not compiled yet, having been run this many times
+    Cold(u8),
+    /// nothing here can be compiled, the interpreter runs it
+    Interpreted,
+    /// a compiled block starting here, made from the bytes up to `end`
+    Compiled { code: *const u8, end: usize },
+}
+
+/// Compiled blocks for one emulator, see the module docs
+pub(super) struct ACJit {
+    /// only `None` while being replaced
+    module: Option<JITModule>,
+    context: cranelift_codegen::Context,
+    builder_context: FunctionBuilderContext,
+    /// by starting address
+    slots: Vec<ACSlot>,
+    /// how many compiled blocks each byte is part of
+    covered: Vec<u8>,
+    /// bytes the program has written to, which are never compiled
+    written: Vec<bool>,
+    /// what memory held when each block was compiled
+    source: Vec<u8>,
+    /// what the blocks were compiled for
+    quirks: ACQuirks,
+    /// whether `module` has any code in it
+    used: bool,
+}
+
+// the compiled code and the pointers to it are only ever used by the emulator that owns them
+unsafe impl Send for ACJit {}
+
+impl ACJit {
+    fn new(quirks: ACQuirks) -> Result<Self, String> {
+        let mut jit = Self {
+            module: Some(new_module()?),
+            context: cranelift_codegen::Context::new(),
+            builder_context: FunctionBuilderContext::new(),
+            slots: vec![ACSlot::Cold(0); 4096],
+            covered: vec![0; 4096],
+            written: vec![false; 4096],
+            source: vec![0; 4096],
+            quirks,
+            used: false,
+        };
+        jit.context.func.signature = jit.signature();
+        Ok(jit)
+    }
+
+    /// Throws away every block, as the whole program has changed
+    pub(super) fn reset(&mut self) {
+        self.flush();
+        self.written.fill(false);
+    }
+
+    /// Throws away every block, freeing the code
+    fn flush(&mut self) {
+        self.slots.fill(ACSlot::Cold(0));
+        self.covered.fill(0);
+        if !self.used {
+            return;
+        }
+        self.used = false;
+        let old = self.module.take().expect("only taken here");
+        // nothing points at the code any more
+        unsafe { old.free_memory() };
+        self.module = Some(new_module().expect("worked the first time"));
+    }
+
+    /// Notes that the byte at `addr` has been written, throwing away any blocks made from it
+    pub(super) fn written(&mut self, addr: usize) {
+        self.written[addr] = true;
+        self.forget(addr);
+    }
+
+    /// Throws away any blocks made from the byte at `addr`
+    fn forget(&mut self, addr: usize) {
+        if self.covered[addr] == 0 {
+            return;
+        }
+        for start in addr.saturating_sub(MAX_BLOCK * 2)..=addr {
+            if let ACSlot::Compiled { end, .. } = self.slots[start] {
+                if addr < end {
+                    // the code is left where it is until the next flush, as freeing one function isn't possible
+                    self.slots[start] = ACSlot::Cold(0);
+                    for byte in &mut self.covered[start..end] {
+                        *byte -= 1;
+                    }
+                }
+            }
+        }
+    }
+
+    /// How many blocks are compiled and in use
+    #[cfg(test)]
+    pub(super) fn blocks(&self) -> usize {
+        self.slots.iter().filter(|slot| matches!(slot, ACSlot::Compiled { .. })).count()
+    }
+
+    fn module(&mut self) -> &mut JITModule {
+        self.module.as_mut().expect("only taken while flushing")
+    }
+
+    /// `(emulator, budget) -> instructions run`
+    fn signature(&mut self) -> Signature {
+        let pointer = self.module().target_config().pointer_type();
+        let mut signature = self.module().make_signature();
+        signature.params.push(AbiParam::new(pointer));
+        signature.params.push(AbiParam::new(types::I32));
+        signature.returns.push(AbiParam::new(types::I32));
+        signature
+    }
+
+    /// Compiles the block starting at `start`, if there is anything there to compile
+    fn compile<R: ACRandom>(&mut self, memory: &[u8; 4096], start: usize) -> Option<(*const u8, usize)> {
+        let mut ops = Vec::new();
+        let mut addr = start;
+        while ops.len() < MAX_BLOCK && addr + 1 < memory.len() && !self.written[addr] && !self.written[addr + 1] {
+            let op = ACDecoded::decode(u16::from_be_bytes([memory[addr], memory[addr + 1]]));
+            if !compiles(op.op) {
+                break;
+            }
+            ops.push(op);
+            addr += 2;
+            if op.op == ACOp::Jump {
+                break;
+            }
+        }
+        if ops.is_empty() {
+            return None;
+        }
+
+        let pointer = self.module().target_config().pointer_type();
+        let mut random_signature = self.module().make_signature();
+        random_signature.params.push(AbiParam::new(pointer));
+        random_signature.returns.push(AbiParam::new(types::I8));
+
+        self.context.func.signature = self.signature();
+        let mut b = FunctionBuilder::new(&mut self.context.func, &mut self.builder_context);
+        let random_signature = b.import_signature(random_signature);
+        let entry = b.create_block();
+        b.append_block_params_for_function_params(entry);
+        b.switch_to_block(entry);
+        let emulator = b.block_params(entry)[0];
+        let budget = b.block_params(entry)[1];
+        let flags = MemFlags::trusted();
+
+        // V0 to VF, then I, DT and ST, kept in cranelift variables and only written back on the way out
+        let regs = offset_of!(ACEmulator<R>, regs) as i32;
+        let fields = [(16, offset_of!(ACEmulator<R>, i), types::I16), (17, offset_of!(ACEmulator<R>, dt), types::I8), (18, offset_of!(ACEmulator<R>, st), types::I8)];
+        let var = Variable::from_u32;
+        for n in 0..16 {
+            b.declare_var(var(n), types::I8);
+            let v = b.ins().load(types::I8, flags, emulator, regs + n as i32);
+            b.def_var(var(n), v);
+        }
+        for (n, offset, ty) in fields {
+            b.declare_var(var(n), ty);
+            let v = b.ins().load(ty, flags, emulator, offset as i32);
+            b.def_var(var(n), v);
+        }
+        let mut changed = [false; 19];
+        let write_back = |b: &mut FunctionBuilder, changed: &[bool; 19], pc: Value, ran: i64| {
+            for n in (0..16).filter(|n| changed[*n]) {
+                let v = b.use_var(var(n as u32));
+                b.ins().store(flags, v, emulator, regs + n as i32);
+            }
+            for (n, offset, _) in fields.into_iter().filter(|(n, _, _)| changed[*n as usize]) {
+                let v = b.use_var(var(n));
+                b.ins().store(flags, v, emulator, offset as i32);
+            }
+            b.ins().store(flags, pc, emulator, offset_of!(ACEmulator<R>, pc) as i32);
+            let ran = b.ins().iconst(types::I32, ran);
+            b.ins().return_(&[ran]);
+        };
+
+        let quirks = self.quirks;
+        let mut jump = None;
+        for (n, op) in ops.iter().enumerate() {
+            let pc = start + n * 2;
+            if n > 0 {
+                // stop here if that's all that was asked for
+                let (more, out) = (b.create_block(), b.create_block());
+                let enough = b.ins().icmp_imm(IntCC::UnsignedLessThanOrEqual, budget, n as i64);
+                b.ins().brif(enough, out, &[], more, &[]);
+                b.switch_to_block(out);
+                let pc = b.ins().iconst(pointer, pc as i64);
+                write_back(&mut b, &changed, pc, n as i64);
+                b.switch_to_block(more);
+            }
+
+            let (x, y, nn) = (op.x as u32, op.y as u32, op.nn as i64);
+            let mut set = |b: &mut FunctionBuilder, n: u32, v: Value| {
+                b.def_var(var(n), v);
+                changed[n as usize] = true;
+            };
+            let vx = b.use_var(var(x));
+            let vy = b.use_var(var(y));
+            match op.op {
+                ACOp::LoadByte => {
+                    let v = b.ins().iconst(types::I8, nn);
+                    set(&mut b, x, v);
+                }
+                ACOp::AddByte => {
+                    let v = b.ins().iadd_imm(vx, nn);
+                    set(&mut b, x, v);
+                }
+                ACOp::LoadReg => set(&mut b, x, vy),
+                ACOp::Or | ACOp::And | ACOp::Xor => {
+                    let v = match op.op {
+                        ACOp::Or => b.ins().bor(vx, vy),
+                        ACOp::And => b.ins().band(vx, vy),
+                        _ => b.ins().bxor(vx, vy),
+                    };
+                    set(&mut b, x, v);
+                    if quirks.logic_resets_vf {
+                        let zero = b.ins().iconst(types::I8, 0);
+                        set(&mut b, 0xF, zero);
+                    }
+                }
+                // VF is set last, so the flag wins when X is F
+                ACOp::AddReg => {
+                    let v = b.ins().iadd(vx, vy);
+                    let carry = b.ins().icmp(IntCC::UnsignedLessThan, v, vx);
+                    set(&mut b, x, v);
+                    set(&mut b, 0xF, carry);
+                }
+                ACOp::Sub | ACOp::SubN => {
+                    let (from, take) = if op.op == ACOp::Sub { (vx, vy) } else { (vy, vx) };
+                    let v = b.ins().isub(from, take);
+                    let no_borrow = b.ins().icmp(IntCC::UnsignedGreaterThanOrEqual, from, take);
+                    set(&mut b, x, v);
+                    set(&mut b, 0xF, no_borrow);
+                }
+                ACOp::ShiftRight | ACOp::ShiftLeft => {
+                    let src = if quirks.shift_uses_vy { vy } else { vx };
+                    let (v, flag) = if op.op == ACOp::ShiftRight {
+                        (b.ins().ushr_imm(src, 1), b.ins().band_imm(src, 1))
+                    } else {
+                        (b.ins().ishl_imm(src, 1), b.ins().ushr_imm(src, 7))
+                    };
+                    set(&mut b, x, v);
+                    set(&mut b, 0xF, flag);
+                }
+                ACOp::LoadIndex => {
+                    let v = b.ins().iconst(types::I16, op.nnn() as i64);
+                    set(&mut b, 16, v);
+                }
+                ACOp::Random => {
+                    let random_byte: extern "C" fn(*mut ACEmulator<R>) -> u8 = random_byte::<R>;
+                    let random = b.ins().iconst(pointer, random_byte as usize as i64);
+                    let call = b.ins().call_indirect(random_signature, random, &[emulator]);
+                    let byte = b.inst_results(call)[0];
+                    let v = b.ins().band_imm(byte, nn);
+                    set(&mut b, x, v);
+                }
+                ACOp::LoadDelay => {
+                    let v = b.use_var(var(17));
+                    set(&mut b, x, v);
+                }
+                ACOp::SetDelay => set(&mut b, 17, vx),
+                ACOp::SetSound => set(&mut b, 18, vx),
+                ACOp::AddIndex => {
+                    let i = b.use_var(var(16));
+                    let add = b.ins().uextend(types::I16, vx);
+                    let v = b.ins().iadd(i, add);
+                    set(&mut b, 16, v);
+                    if quirks.fx1e_affects_vf {
+                        let flag = b.ins().icmp_imm(IntCC::UnsignedGreaterThan, v, 0xFFF);
+                        set(&mut b, 0xF, flag);
+                    }
+                }
+                ACOp::LoadFont => {
+                    let digit = b.ins().uextend(types::I16, vx);
+                    let digit = b.ins().band_imm(digit, 0x0F);
+                    let offset = b.ins().imul_imm(digit, 5);
+                    let v = b.ins().iadd_imm(offset, SPRITE_CHARS_ADDR as i64);
+                    set(&mut b, 16, v);
+                }
+                ACOp::Load => {
+                    let i = b.use_var(var(16));
+                    let memory = b.ins().iadd_imm(emulator, offset_of!(ACEmulator<R>, memory) as i64);
+                    for reg in 0..=x {
+                        // wraps around the end of memory
+                        let addr = b.ins().iadd_imm(i, reg as i64);
+                        let addr = b.ins().band_imm(addr, 0xFFF);
+                        let addr = b.ins().uextend(pointer, addr);
+                        let addr = b.ins().iadd(memory, addr);
+                        let v = b.ins().load(types::I8, flags, addr, 0);
+                        set(&mut b, reg, v);
+                    }
+                    if quirks.load_store_increments_i {
+                        let v = b.ins().iadd_imm(i, x as i64 + 1);
+                        set(&mut b, 16, v);
+                    }
+                }
+                ACOp::Jump => jump = Some(op.nnn() as usize),
+                _ => unreachable!("{:?} is not compiled", op.op),
+            }
+        }
+        let end = start + ops.len() * 2;
+        let pc = b.ins().iconst(pointer, jump.unwrap_or(end) as i64);
+        write_back(&mut b, &changed, pc, ops.len() as i64);
+        b.seal_all_blocks();
+        b.finalize();
+
+        let module = self.module.as_mut().expect("only taken while flushing");
+        let compiled = (|| {
+            let id = module.declare_anonymous_function(&self.context.func.signature).map_err(|e| e.to_string())?;
+            module.define_function(id, &mut self.context).map_err(|e| e.to_string())?;
+            module.finalize_definitions().map_err(|e| e.to_string())?;
+            Ok::<_, String>(id)
+        })();
+        module.clear_context(&mut self.context);
+        self.used = true;
+        match compiled {
+            Ok(id) => Some((module.get_finalized_function(id), end)),
+            Err(e) => {
+                log::warn!("Failed to compile the block at {:03X}, interpreting it: {}", start, e);
+                None
+            }
+        }
+    }
+}
+
+fn new_module() -> Result<JITModule, String> {
+    let mut flags = settings::builder();
+    flags.set("opt_level", "speed").map_err(|e| e.to_string())?;
+    flags.set("use_colocated_libcalls", "false").map_err(|e| e.to_string())?;
+    flags.set("is_pic", "false").map_err(|e| e.to_string())?;
+    let isa = cranelift_native::builder()
+        .map_err(|e| format!("Cranelift can't compile for this machine: {}", e))?
+        .finish(settings::Flags::new(flags))
+        .map_err(|e| e.to_string())?;
+    Ok(JITModule::new(JITBuilder::with_isa(isa, default_libcall_names())))
+}
+
+/// Whether `op` can be part of a block
+fn compiles(op: ACOp) -> bool {
+    use ACOp::*;
+    matches!(
+        op,
+        LoadByte | AddByte | LoadReg | Or | And | Xor | AddReg | Sub | ShiftRight | SubN | ShiftLeft | LoadIndex | Random
+            | LoadDelay | SetDelay | SetSound | AddIndex | LoadFont | Load | Jump
+    )
+}
+
+/// `CXNN` calls back into rust for its random number
+extern "C" fn random_byte<R: ACRandom>(emulator: *mut ACEmulator<R>) -> u8 {
+    // only ever called by a block, which is given the emulator by `run_frame_jit`
+    unsafe { (*emulator).rng.random_byte() }
+}
+
+impl<R: ACRandom> ACEmulator<R> {
+    /// From now on, compile code `run_frame` runs often to native code, which is a lot quicker.
+    /// Does nothing if it already is, and fails if cranelift can't compile for this machine
+    pub fn enable_jit(&mut self) -> Result<(), String> {
+        if self.jit.is_none() {
+            self.jit = Some(Box::new(ACJit::new(self.quirks)?));
+        }
+        Ok(())
+    }
+
+    /// Takes over the code `other` has compiled, for playing the same game again without compiling it all again.
+    /// Anything compiled from memory that is different here is thrown away
+    pub fn take_jit(&mut self, other: &mut ACEmulator<R>) {
+        self.jit = other.jit.take();
+        if let Some(jit) = &mut self.jit {
+            for addr in 0..self.memory.len() {
+                if jit.covered[addr] != 0 && jit.source[addr] != self.memory[addr] {
+                    jit.forget(addr);
+                }
+            }
+        }
+    }
+
+    /// Goes back to interpreting everything
+    pub fn disable_jit(&mut self) {
+        self.jit = None;
+    }
+
+    /// `run_frame`, running compiled blocks wherever there are some
+    pub(super) fn run_frame_jit(&mut self, keypad: &ACKeyboard, mut new_keypress: Option<ACKey>, cycles: usize) {
+        let mut left = cycles;
+        while left > 0 {
+            // the debugging aids need to see every instruction
+            if self.fault.is_none() && !self.waiting_for_key && self.coverage.is_none() && self.unknown_log.is_none() {
+                if let Some(block) = self.block_at(self.pc) {
+                    let budget = left.min(u32::MAX as usize) as u32;
+                    left -= unsafe { block(self, budget) } as usize;
+                    // only running off the end of the last block can leave memory, jumps stay inside it
+                    if self.pc > 0xFFF {
+                        self.check_pc(self.pc - 2);
+                    }
+                    continue;
+                }
+            }
+            let waiting = self.waiting_for_key;
+            self.step(keypad, new_keypress);
+            // a key press can only be used by one `FX0A`
+            if waiting {
+                new_keypress = None;
+            }
+            left -= 1;
+        }
+        self.tick_timers();
+    }
+
+    /// The block starting at `pc`, compiling it if it's got hot
+    fn block_at(&mut self, pc: usize) -> Option<ACBlock<R>> {
+        let jit = self.jit.as_mut()?;
+        if pc + 1 >= self.memory.len() {
+            return None;
+        }
+        if jit.quirks != self.quirks {
+            jit.quirks = self.quirks;
+            jit.flush();
+        }
+        let code = match jit.slots[pc] {
+            ACSlot::Compiled { code, .. } => code,
+            ACSlot::Interpreted => return None,
+            ACSlot::Cold(runs) if runs + 1 < HOT => {
+                jit.slots[pc] = ACSlot::Cold(runs + 1);
+                return None;
+            }
+            ACSlot::Cold(_) => match jit.compile::<R>(&self.memory, pc) {
+                Some((code, end)) => {
+                    jit.slots[pc] = ACSlot::Compiled { code, end };
+                    jit.source[pc..end].copy_from_slice(&self.memory[pc..end]);
+                    for byte in &mut jit.covered[pc..end] {
+                        *byte += 1;
+                    }
+                    code
+                }
+                None => {
+                    jit.slots[pc] = ACSlot::Interpreted;
+                    return None;
+                }
+            },
+        };
+        // compiled by `compile` with the signature `ACBlock<R>`
+        Some(unsafe { core::mem::transmute::<*const u8, ACBlock<R>>(code) })
+    }
+}
@@ -0,0 +1,845 @@
+//! Known-correct behaviour of every instruction, under each set of quirks.
+//!
+//! Written against http://devernay.free.fr/hacks/chip8/C8TECH10.HTM and
+//! https://github.com/Timendus/chip8-test-suite
+use super::*;
+use crate::keyboard::ACKeyboard;
+use crate::quirks::ACQuirks;
+
+/// Loads `program` at 0x200 with the given quirks, without running anything
+fn load(quirks: ACQuirks, program: &[u16]) -> ACEmulator {
+    let mut emu = ACEmulator::new();
+    emu.quirks = quirks;
+    emu.load_rom(program.iter().flat_map(|instr| instr.to_be_bytes()).collect::<Vec<u8>>());
+    emu
+}
+
+/// Runs `steps` instructions of `program`, with no keys pressed
+fn run_with(quirks: ACQuirks, program: &[u16], steps: usize) -> ACEmulator {
+    let mut emu = load(quirks, program);
+    let keyboard = ACKeyboard::new();
+    for _ in 0..steps {
+        emu.step(&keyboard, None);
+    }
+    emu
+}
+
+/// Runs all of `program` in a straight line, with no quirks
+fn run(program: &[u16]) -> ACEmulator {
+    run_with(ACQuirks::default(), program, program.len())
+}
+
+fn all_quirks() -> Vec<ACQuirks> {
+    let mut all = vec![ACQuirks::default()];
+    all.extend(ACQuirks::PRESETS.iter().map(|(_, q)| *q));
+    all
+}
+
+#[test]
+fn cls_00e0() {
+    // draw the 0 from the font, then clear
+    let emu = run(&[0xD005, 0x00E0]);
+    for y in 0..32 {
+        for x in 0..64 {
+            assert!(!emu.framebuffer().get_pixel(x, y));
+        }
+    }
+}
+
+#[test]
+fn sys_0nnn_is_ignored() {
+    let emu = run(&[0x0123]);
+    assert_eq!(emu.pc(), 0x202);
+    assert_eq!(emu.regs, [0; 16]);
+
+    // these look like CLS and RET but aren't
+    let mut emu = load(ACQuirks::default(), &[0x6001, 0xA000, 0xD015, 0x2208, 0x0120, 0x012E]);
+    for _ in 0..6 {
+        emu.step(&ACKeyboard::new(), None);
+    }
+    assert!(emu.framebuffer().get_pixel(1, 0));
+    assert_eq!(emu.pc(), 0x20C);
+    assert_eq!(emu.stack(), [0x208]);
+}
+
+#[test]
+fn only_known_instructions_do_anything() {
+    for instr in 0..=u16::MAX {
+        let op = ACDecoded::decode(instr).op;
+        assert_eq!(op != ACOp::Nop, crate::disasm::is_known(instr), "{:04X} decoded as {:?}", instr, op);
+    }
+}
+
+#[test]
+fn jp_1nnn() {
+    let emu = run(&[0x1ABC]);
+    assert_eq!(emu.pc(), 0xABC);
+}
+
+#[test]
+fn call_2nnn_uses_the_whole_stack() {
+    // call 0x204, which calls 0x206
+    let emu = run_with(ACQuirks::default(), &[0x2204, 0x0000, 0x2206, 0x0000], 2);
+    assert_eq!(emu.pc(), 0x206);
+    assert_eq!(emu.stack(), &[0x202, 0x206]);
+    // slot 0 is used
+    assert_eq!(emu.stack[0], 0x202);
+    assert_eq!(emu.stack_ptr, 2);
+}
+
+#[test]
+fn call_2nnn_sixteen_deep() {
+    // 0x200 calls itself
+    let emu = run_with(ACQuirks::default(), &[0x2200], STACK_SIZE);
+    assert_eq!(emu.stack().len(), STACK_SIZE);
+    assert!(emu.stack().iter().all(|ret| *ret == 0x202));
+}
+
+#[test]
+fn ret_00ee() {
+    // call 0x204, return, then 0x202 jumps somewhere recognisable
+    let emu = run_with(ACQuirks::default(), &[0x2204, 0x1ABC, 0x00EE], 3);
+    assert_eq!(emu.pc(), 0xABC);
+    assert!(emu.stack().is_empty());
+    assert_eq!(emu.stack_ptr, 0);
+}
+
+#[test]
+fn ret_00ee_with_empty_stack_does_nothing() {
+    let emu = run(&[0x00EE]);
+    assert_eq!(emu.pc(), 0x202);
+    assert_eq!(emu.stack_ptr, 0);
+}
+
+#[test]
+fn se_3xnn() {
+    assert_eq!(run(&[0x6312, 0x3312]).pc(), 0x206);
+    assert_eq!(run(&[0x6312, 0x3313]).pc(), 0x204);
+}
+
+#[test]
+fn sne_4xnn() {
+    assert_eq!(run(&[0x6312, 0x4312]).pc(), 0x204);
+    assert_eq!(run(&[0x6312, 0x4313]).pc(), 0x206);
+}
+
+#[test]
+fn se_5xy0() {
+    assert_eq!(run(&[0x6312, 0x6412, 0x5340]).pc(), 0x208);
+    assert_eq!(run(&[0x6312, 0x6413, 0x5340]).pc(), 0x206);
+}
+
+#[test]
+fn sne_9xy0() {
+    assert_eq!(run(&[0x6312, 0x6412, 0x9340]).pc(), 0x206);
+    assert_eq!(run(&[0x6312, 0x6413, 0x9340]).pc(), 0x208);
+}
+
+#[test]
+fn ld_6xnn() {
+    let emu = run(&[0x6A42]);
+    assert_eq!(emu.reg(0xA), 0x42);
+}
+
+#[test]
+fn add_7xnn_wraps_without_touching_vf() {
+    let emu = run(&[0x6F05, 0x63FF, 0x7302]);
+    assert_eq!(emu.reg(3), 0x01);
+    assert_eq!(emu.reg(0xF), 0x05);
+}
+
+#[test]
+fn ld_8xy0() {
+    let emu = run(&[0x6442, 0x8340]);
+    assert_eq!(emu.reg(3), 0x42);
+}
+
+#[test]
+fn logic_8xy1_8xy2_8xy3() {
+    for quirks in all_quirks() {
+        for (op, expected) in [(0x8341, 0b1110), (0x8342, 0b1000), (0x8343, 0b0110)] {
+            let emu = run_with(quirks, &[0x630C, 0x640A, 0x6F07, op], 4);
+            assert_eq!(emu.reg(3), expected, "{:04X} with {:?}", op, quirks);
+            let vf = if quirks.logic_resets_vf { 0 } else { 7 };
+            assert_eq!(emu.reg(0xF), vf, "{:04X} with {:?}", op, quirks);
+        }
+    }
+}
+
+#[test]
+fn add_8xy4() {
+    let emu = run(&[0x63F0, 0x6410, 0x8344]);
+    assert_eq!(emu.reg(3), 0x00);
+    assert_eq!(emu.reg(0xF), 1);
+
+    let emu = run(&[0x63F0, 0x640F, 0x6F01, 0x8344]);
+    assert_eq!(emu.reg(3), 0xFF);
+    assert_eq!(emu.reg(0xF), 0);
+}
+
+#[test]
+fn sub_8xy5() {
+    // no borrow
+    let emu = run(&[0x6310, 0x6401, 0x8345]);
+    assert_eq!(emu.reg(3), 0x0F);
+    assert_eq!(emu.reg(0xF), 1);
+
+    // equal is not a borrow either
+    let emu = run(&[0x6310, 0x6410, 0x8345]);
+    assert_eq!(emu.reg(3), 0x00);
+    assert_eq!(emu.reg(0xF), 1);
+
+    // borrow
+    let emu = run(&[0x6301, 0x6410, 0x6F01, 0x8345]);
+    assert_eq!(emu.reg(3), 0xF1);
+    assert_eq!(emu.reg(0xF), 0);
+}
+
+#[test]
+fn shr_8xy6() {
+    for quirks in all_quirks() {
+        let emu = run_with(quirks, &[0x6305, 0x6408, 0x8346], 3);
+        if quirks.shift_uses_vy {
+            assert_eq!(emu.reg(3), 0x04);
+            assert_eq!(emu.reg(0xF), 0);
+        } else {
+            assert_eq!(emu.reg(3), 0x02);
+            assert_eq!(emu.reg(0xF), 1);
+        }
+        // VY is never changed
+        assert_eq!(emu.reg(4), 0x08);
+    }
+}
+
+#[test]
+fn subn_8xy7() {
+    // no borrow
+    let emu = run(&[0x6301, 0x6410, 0x8347]);
+    assert_eq!(emu.reg(3), 0x0F);
+    assert_eq!(emu.reg(0xF), 1);
+
+    // equal is not a borrow
+    let emu = run(&[0x6310, 0x6410, 0x8347]);
+    assert_eq!(emu.reg(3), 0x00);
+    assert_eq!(emu.reg(0xF), 1);
+
+    // borrow
+    let emu = run(&[0x6310, 0x6401, 0x6F01, 0x8347]);
+    assert_eq!(emu.reg(3), 0xF1);
+    assert_eq!(emu.reg(0xF), 0);
+}
+
+#[test]
+fn shl_8xye() {
+    for quirks in all_quirks() {
+        let emu = run_with(quirks, &[0x6381, 0x6441, 0x834E], 3);
+        if quirks.shift_uses_vy {
+            assert_eq!(emu.reg(3), 0x82);
+            assert_eq!(emu.reg(0xF), 0);
+        } else {
+            assert_eq!(emu.reg(3), 0x02);
+            // the flag is 1, not the bit that was shifted out (0x80)
+            assert_eq!(emu.reg(0xF), 1);
+        }
+        assert_eq!(emu.reg(4), 0x41);
+    }
+}
+
+#[test]
+fn vf_as_vx_gets_the_flag() {
+    // 8FY4, carry
+    let emu = run(&[0x6FF0, 0x6420, 0x8F44]);
+    assert_eq!(emu.reg(0xF), 1);
+    // 8FY5, no borrow
+    let emu = run(&[0x6F20, 0x6410, 0x8F45]);
+    assert_eq!(emu.reg(0xF), 1);
+    // 8FY6, shifted out a 0
+    let emu = run(&[0x6F02, 0x8F46]);
+    assert_eq!(emu.reg(0xF), 0);
+    // 8FY7, borrow
+    let emu = run(&[0x6F20, 0x6410, 0x8F47]);
+    assert_eq!(emu.reg(0xF), 0);
+    // 8FYE, shifted out a 1
+    let emu = run(&[0x6F80, 0x8F4E]);
+    assert_eq!(emu.reg(0xF), 1);
+}
+
+#[test]
+fn ld_annn() {
+    let emu = run(&[0xA123]);
+    assert_eq!(emu.index(), 0x123);
+}
+
+#[test]
+fn jp_bnnn() {
+    for quirks in all_quirks() {
+        let emu = run_with(quirks, &[0x6010, 0x6320, 0xB300], 3);
+        if quirks.jump_uses_vx {
+            assert_eq!(emu.pc(), 0x320);
+        } else {
+            assert_eq!(emu.pc(), 0x310);
+        }
+    }
+}
+
+#[test]
+fn rnd_cxnn_is_masked() {
+    for _ in 0..32 {
+        let emu = run(&[0x63FF, 0xC300]);
+        assert_eq!(emu.reg(3), 0);
+        let emu = run(&[0xC30F]);
+        assert_eq!(emu.reg(3) & 0xF0, 0);
+    }
+}
+
+#[test]
+fn drw_dxyn_draws_sprites() {
+    // the 1 from the font, at 2, 3
+    let emu = run(&[0x6302, 0x6403, 0xA005, 0xD345]);
+    let one = [0x20, 0x60, 0x20, 0x20, 0x70];
+    for (row, bits) in one.iter().enumerate() {
+        for col in 0..8 {
+            let lit = bits & (0x80 >> col) != 0;
+            assert_eq!(emu.framebuffer().get_pixel(2 + col, 3 + row), lit, "{}, {}", col, row);
+        }
+    }
+}
+
+#[test]
+fn drw_dxyn_collisions() {
+    // drawing the same sprite twice erases it, and collides
+    let emu = run(&[0xA000, 0xD005, 0xD005]);
+    assert_eq!(emu.reg(0xF), 1);
+    assert!(!emu.framebuffer().get_pixel(0, 0));
+
+    // no collision when nothing is erased, even if pixels overlap with unlit parts of the sprite
+    let emu = run(&[0xA000, 0xD005, 0x6F01, 0x6308, 0xD305]);
+    assert_eq!(emu.reg(0xF), 0);
+
+    // a single pixel of overlap is enough
+    let emu = run(&[0xA000, 0xD005, 0x6303, 0xD305]);
+    assert_eq!(emu.reg(0xF), 1);
+    // 0xF0 and 0xF0 shifted over by 3 share a pixel at x 3
+    assert!(!emu.framebuffer().get_pixel(3, 0));
+    assert!(emu.framebuffer().get_pixel(4, 0));
+}
+
+#[test]
+fn drw_dxyn_clips_or_wraps() {
+    for quirks in all_quirks() {
+        // the 0 from the font (0xF0 on its first row) at 62, 30
+        let emu = run_with(quirks, &[0x633E, 0x641E, 0xA000, 0xD345], 4);
+        assert!(emu.framebuffer().get_pixel(62, 30));
+        assert!(emu.framebuffer().get_pixel(63, 30));
+        assert_eq!(emu.framebuffer().get_pixel(0, 30), quirks.wrap_sprites, "{:?}", quirks);
+        assert_eq!(emu.framebuffer().get_pixel(1, 30), quirks.wrap_sprites, "{:?}", quirks);
+        // the bottom rows
+        assert_eq!(emu.framebuffer().get_pixel(62, 0), quirks.wrap_sprites, "{:?}", quirks);
+    }
+}
+
+#[test]
+fn framebuffer_rows_are_packed_pixels() {
+    let mut framebuffer = ACFramebuffer::new();
+    assert!(!framebuffer.xor_pixel(0, 0, true));
+    assert!(!framebuffer.xor_pixel(63, 31, true));
+    assert!(!framebuffer.xor_pixel(9, 1, false));
+    assert_eq!(framebuffer.rows()[0], 1 << 63);
+    assert_eq!(framebuffer.rows()[31], 1);
+    assert!(framebuffer.get_pixel(63, 31) && !framebuffer.get_pixel(62, 31) && !framebuffer.get_pixel(9, 1));
+
+    // collides only if a lit pixel is flipped
+    assert!(!framebuffer.xor_row(0, 0x0F));
+    assert!(framebuffer.xor_row(0, 1 << 63 | 1));
+    assert_eq!(framebuffer.rows()[0], 0x0E);
+    let packed = framebuffer.packed();
+    assert_eq!(packed[..8], [0, 0, 0, 0, 0, 0, 0, 0x0E]);
+    assert_eq!(packed[255], 1);
+}
+
+#[test]
+fn drw_dxyn_start_position_always_wraps() {
+    for quirks in all_quirks() {
+        // 64 + 2, 32 + 3 is drawn at 2, 3
+        let emu = run_with(quirks, &[0x6342, 0x6423, 0xA000, 0xD341], 4);
+        assert!(emu.framebuffer().get_pixel(2, 3));
+        assert!(emu.framebuffer().get_pixel(5, 3));
+        assert!(!emu.framebuffer().get_pixel(6, 3));
+    }
+}
+
+#[test]
+fn skp_ex9e_and_sknp_exa1() {
+    for (op, pressed, skips) in [(0xE39E, true, true), (0xE39E, false, false), (0xE3A1, true, false), (0xE3A1, false, true)] {
+        let mut emu = load(ACQuirks::default(), &[0x630A, op]);
+        let keys = if pressed { keyboard_with(ACKey::KA) } else { ACKeyboard::new() };
+        emu.step(&keys, None);
+        emu.step(&keys, None);
+        assert_eq!(emu.pc(), if skips { 0x206 } else { 0x204 }, "{:04X}", op);
+    }
+}
+
+fn keyboard_with(key: ACKey) -> ACKeyboard {
+    let mut keyboard = ACKeyboard::new();
+    keyboard.press(key);
+    keyboard
+}
+
+#[test]
+fn timers_fx07_fx15_fx18() {
+    let mut emu = run(&[0x6305, 0xF315, 0xF318]);
+    assert_eq!(emu.dt, 5);
+    assert_eq!(emu.sound_timer(), 5);
+    emu.tick_timers();
+    emu.tick_timers();
+    assert!(emu.should_bleep());
+    let keyboard = ACKeyboard::new();
+    // FX07
+    emu.exec_oper(0xF407, &keyboard);
+    assert_eq!(emu.reg(4), 3);
+    for _ in 0..3 {
+        emu.tick_timers();
+    }
+    assert_eq!(emu.dt, 0);
+    assert_eq!(emu.sound_timer(), 0);
+    // timers stop at 0
+    emu.tick_timers();
+    assert_eq!(emu.dt, 0);
+    assert!(!emu.should_bleep());
+}
+
+#[test]
+fn ld_fx0a_waits_for_a_key() {
+    let mut emu = load(ACQuirks::default(), &[0xF30A, 0x1ABC]);
+    let keyboard = ACKeyboard::new();
+    emu.step(&keyboard, None);
+    // nothing runs until a key is pressed
+    for _ in 0..10 {
+        emu.step(&keyboard, None);
+    }
+    assert_eq!(emu.pc(), 0x202);
+    let keyboard = keyboard_with(ACKey::K7);
+    emu.step(&keyboard, Some(ACKey::K7));
+    assert_eq!(emu.reg(3), 7);
+    emu.step(&keyboard, None);
+    assert_eq!(emu.pc(), 0xABC);
+}
+
+#[test]
+fn run_frame_delivers_a_key_press_to_fx0a() {
+    // wait for a key part way through the frame, then loop forever
+    let mut emu = load(ACQuirks::default(), &[0x6000, 0x6000, 0xF30A, 0x1206]);
+    emu.run_frame(&keyboard_with(ACKey::K9), Some(ACKey::K9), 10);
+    assert_eq!(emu.reg(3), 9);
+    assert_eq!(emu.pc(), 0x206);
+}
+
+#[test]
+fn add_fx1e() {
+    for quirks in all_quirks() {
+        let emu = run_with(quirks, &[0x6F05, 0x6310, 0xA100, 0xF31E], 4);
+        assert_eq!(emu.index(), 0x110);
+        assert_eq!(emu.reg(0xF), if quirks.fx1e_affects_vf { 0 } else { 5 });
+    }
+
+    let quirks = ACQuirks { fx1e_affects_vf: true, ..ACQuirks::default() };
+    let emu = run_with(quirks, &[0x6310, 0xAFF8, 0xF31E], 3);
+    assert_eq!(emu.index(), 0x1008);
+    assert_eq!(emu.reg(0xF), 1);
+}
+
+#[test]
+fn ld_fx29_points_at_the_font() {
+    for digit in 0..0x10u16 {
+        let emu = run(&[0x6300 | digit, 0xF329]);
+        assert_eq!(emu.index(), SPRITE_CHARS_ADDR + digit * 5);
+        let start = emu.index() as usize;
+        assert_eq!(&emu.memory()[start..start + 5], &SPRITE_CHARS[digit as usize]);
+    }
+    // only the low nibble is used
+    let emu = run(&[0x63FA, 0xF329]);
+    assert_eq!(emu.index(), SPRITE_CHARS_ADDR + 0xA * 5);
+}
+
+#[test]
+fn ld_fx33_bcd() {
+    for (value, digits) in [(0u16, [0, 0, 0]), (7, [0, 0, 7]), (42, [0, 4, 2]), (255, [2, 5, 5]), (109, [1, 0, 9])] {
+        let emu = run(&[0x6300 | value, 0xA300, 0xF333]);
+        assert_eq!(&emu.memory()[0x300..0x303], &digits);
+        assert_eq!(emu.index(), 0x300);
+    }
+}
+
+#[test]
+fn ld_fx55_and_fx65() {
+    for quirks in all_quirks() {
+        // store V0 to V2
+        let emu = run_with(quirks, &[0x6011, 0x6122, 0x6233, 0x6344, 0xA300, 0xF255], 6);
+        assert_eq!(&emu.memory()[0x300..0x304], &[0x11, 0x22, 0x33, 0]);
+        assert_eq!(emu.index(), if quirks.load_store_increments_i { 0x303 } else { 0x300 });
+
+        // load them back into fresh registers
+        let mut emu = emu;
+        emu.regs = [0; 16];
+        emu.i = 0x300;
+        emu.exec_oper(0xF165, &ACKeyboard::new());
+        assert_eq!(&emu.regs[..3], &[0x11, 0x22, 0]);
+        assert_eq!(emu.index(), if quirks.load_store_increments_i { 0x302 } else { 0x300 });
+    }
+}
+
+#[test]
+fn fx33_and_fx55_wrap_at_the_end_of_memory() {
+    let emu = run(&[0x60FF, 0xAFFF, 0xF033]);
+    assert_eq!(emu.memory()[0xFFF], 2);
+    assert_eq!(&emu.memory()[..2], &[5, 5]);
+
+    let emu = run_with(ACQuirks::COSMAC, &[0x6011, 0x6122, 0xAFFF, 0xF155, 0xF165], 5);
+    assert_eq!(emu.memory()[0xFFF], 0x11);
+    assert_eq!(emu.memory()[0], 0x22);
+}
+
+#[test]
+fn fx1e_wraps_i() {
+    let mut emu = run(&[0x60FF]);
+    emu.i = 0xFFFF;
+    emu.exec_oper(0xF01E, &ACKeyboard::new());
+    assert_eq!(emu.index(), 0xFE);
+}
+
+#[test]
+fn deep_recursion_faults() {
+    // calls itself forever
+    let emu = run_with(ACQuirks::default(), &[0x2200], 20);
+    assert_eq!(emu.fault(), Some(ACFault::StackOverflow));
+    assert_eq!(emu.stack().len(), STACK_SIZE);
+    assert_eq!(emu.pc(), 0x200);
+}
+
+#[test]
+fn running_off_the_end_faults() {
+    let emu = run_with(ACQuirks::default(), &[0x1FFE], 3);
+    assert_eq!(emu.fault(), Some(ACFault::PcOutOfBounds));
+    // left on the last instruction in memory, which took it off the end
+    assert_eq!(emu.pc(), 0xFFE);
+}
+
+#[test]
+fn jumping_past_the_end_faults_on_the_jump() {
+    let quirks = ACQuirks { jump_uses_vx: true, ..ACQuirks::default() };
+    let mut emu = run_with(quirks, &[0x6FFF, 0xBFFF], 3);
+    assert_eq!(emu.fault(), Some(ACFault::PcOutOfBounds));
+    assert_eq!(emu.pc(), 0x202);
+    // so the state can be loaded again, and roms loaded on top of it
+    let state = emu.save_state();
+    let mut restored = ACEmulator::new();
+    restored.load_state(&state).unwrap();
+    assert_eq!(restored.pc(), 0x202);
+    emu.load_rom([0xAB; 8]);
+
+    // skipping off the end as well
+    let mut emu = load(ACQuirks::default(), &[0x1FFE]);
+    emu.poke(0xFFE, &[0x30, 0x00]);
+    for _ in 0..3 {
+        emu.step(&ACKeyboard::new(), None);
+    }
+    assert_eq!((emu.fault(), emu.pc()), (Some(ACFault::PcOutOfBounds), 0xFFE));
+}
+
+#[test]
+fn save_states_with_pc_past_the_end_are_corrupt() {
+    let mut state = ACEmulator::new().save_state();
+    // after the magic, version, memory, registers, I and the timers
+    let pc = 4 + 1 + 4096 + 16 + 2 + 1 + 1;
+    assert_eq!(state[pc..pc + 2], [0x02, 0x00]);
+    state[pc..pc + 2].copy_from_slice(&0x1000u16.to_be_bytes());
+    let mut emu = ACEmulator::new();
+    assert_eq!(emu.load_state(&state), Err("Save state is corrupt".to_string()));
+    assert_eq!(emu.pc(), 0x200);
+}
+
+#[test]
+fn loading_a_rom_at_the_last_byte_keeps_what_fits() {
+    let mut emu = ACEmulator::new();
+    emu.set_pc(0xFFF);
+    emu.load_rom([1, 2, 3]);
+    assert_eq!(emu.memory()[0xFFF], 1);
+    assert_eq!(emu.memory()[0], 0xF0);
+}
+
+#[test]
+fn exec_oper_at_the_start_of_memory_does_not_underflow() {
+    let keyboard = ACKeyboard::new();
+    let mut emu = ACEmulator::new();
+    emu.coverage = Some(crate::coverage::ACCoverage::new());
+    emu.set_pc(0);
+    // V0 is 0, so this skips, as if it were at 0xFFE
+    emu.exec_oper(0x3000, &keyboard);
+    assert_eq!(emu.pc(), 2);
+    assert_eq!(emu.coverage.as_ref().unwrap().skip_outcomes(0xFFE), Some((1, 0)));
+
+    // calls to 0 until the stack is full
+    emu.set_pc(0);
+    for _ in 0..STACK_SIZE {
+        emu.exec_oper(0x2000, &keyboard);
+    }
+    assert_eq!(emu.fault(), None);
+    emu.exec_oper(0x2000, &keyboard);
+    assert_eq!((emu.fault(), emu.pc()), (Some(ACFault::StackOverflow), 0xFFE));
+}
+
+#[test]
+fn skp_ex9e_uses_the_low_nibble() {
+    let keyboard = keyboard_with(ACKey::K5);
+    let mut emu = load(ACQuirks::default(), &[0x63F5, 0xE39E]);
+    emu.step(&keyboard, None);
+    emu.step(&keyboard, None);
+    assert_eq!(emu.pc(), 0x206);
+}
+
+#[test]
+fn oversized_roms_are_truncated() {
+    let mut emu = ACEmulator::new();
+    emu.load_rom(vec![0xAB; 8000]);
+    assert_eq!(emu.memory()[0xFFF], 0xAB);
+}
+
+#[test]
+fn save_state_round_trips() {
+    let mut emu = run_with(ACQuirks::XOCHIP, &[0x6A42, 0xA000, 0xD015, 0x2208, 0x00EE], 4);
+    emu.dt = 9;
+    let state = emu.save_state();
+
+    let mut restored = ACEmulator::new();
+    restored.load_state(&state).unwrap();
+    assert_eq!(restored.save_state(), state);
+    assert_eq!(restored.reg(0xA), 0x42);
+    assert_eq!(restored.stack(), emu.stack());
+    assert_eq!(restored.quirks, ACQuirks::XOCHIP);
+    assert!(restored.framebuffer().get_pixel(0, 0));
+
+    // broken states leave the emulator alone
+    assert!(restored.load_state(&state[..state.len() - 1]).is_err());
+    assert!(restored.load_state(b"nonsense").is_err());
+    assert_eq!(restored.save_state(), state);
+}
+
+#[test]
+fn save_states_carry_on_with_the_same_random_numbers() {
+    let keyboard = ACKeyboard::new();
+    let mut emu = load(ACQuirks::default(), &[0xC0FF, 0x1200]);
+    emu.seed(3);
+    emu.run_frame(&keyboard, None, 10);
+    let state = emu.save_state();
+
+    // a different seed, so only the state can make them agree
+    let mut restored = ACEmulator::new();
+    restored.seed(4);
+    restored.load_state(&state).unwrap();
+    for _ in 0..20 {
+        emu.step(&keyboard, None);
+        restored.step(&keyboard, None);
+        assert_eq!(restored.reg(0), emu.reg(0));
+    }
+}
+
+#[test]
+fn resetting_carries_on_with_the_same_random_numbers() {
+    let keyboard = ACKeyboard::new();
+    let program = [0xC0FF, 0xD015, 0x1200];
+    let mut emu = load(ACQuirks::default(), &program);
+    let mut other = load(ACQuirks::default(), &program);
+    emu.seed(3);
+    other.seed(3);
+    for _ in 0..9 {
+        emu.step(&keyboard, None);
+        other.step(&keyboard, None);
+    }
+    emu.reset();
+    assert_eq!((emu.pc(), emu.reg(0), emu.memory()[0x200]), (0x200, 0, 0));
+    assert!(*emu.framebuffer() == ACFramebuffer::new());
+
+    emu.load_rom(program.iter().flat_map(|instr| instr.to_be_bytes()).collect::<Vec<u8>>());
+    // back at the start of the loop, on the next random number
+    assert_eq!(other.pc(), 0x200);
+    emu.step(&keyboard, None);
+    other.step(&keyboard, None);
+    assert_eq!(emu.reg(0), other.reg(0));
+}
+
+#[test]
+fn self_modifying_code_is_decoded_again() {
+    // the first pass stores 0x6007 over the 0x6001 at 0x200, the second runs it and stops
+    let program = [0x6001, 0x7501, 0x3502, 0x120A, 0x1208, 0x6060, 0x6107, 0xA200, 0xF155, 0x1200];
+    let emu = run_with(ACQuirks::default(), &program, 20);
+    assert_eq!(emu.reg(0), 7);
+    assert_eq!(emu.pc(), 0x208);
+
+    // a write to 0x302 changes the instruction starting at 0x301 (V1 = 5, then V1 = 0)
+    let mut emu = load(ACQuirks::default(), &[0x2301, 0x6409, 0xA302, 0xF433, 0x2301, 0x120A]);
+    emu.poke(0x301, &[0x61, 0x05, 0x00, 0x00, 0x00, 0xEE]);
+    let keyboard = ACKeyboard::new();
+    for (stop, v1) in [(0x202, 5), (0x20A, 0)] {
+        while emu.pc() != stop {
+            emu.step(&keyboard, None);
+        }
+        assert_eq!(emu.reg(1), v1);
+    }
+
+    // as do pokes
+    emu.poke(0x301, &[0x61, 0x09]);
+    emu.set_pc(0x208);
+    emu.step(&keyboard, None);
+    emu.step(&keyboard, None);
+    assert_eq!(emu.reg(1), 9);
+}
+
+#[test]
+fn self_modifying_traces_match_without_the_cache() {
+    // each pass stores `72 V1` over 0x214 with FX55 (V2 += V1, V1 going up by 3 a pass) and the BCD of V2 over
+    // 0x219..0x21B with FX33, turning 0x218 into `63 hundreds` and 0x21A into `0 tens 0 ones`
+    let program = [
+        0xA214, 0x6072, 0x7103, 0xF155, 0xA219, 0xF233, 0x1214, 0x0000, 0x0000, 0x0000, 0x7200, 0x1218, 0x6300, 0x0000,
+        0x1200,
+    ];
+    let keyboard = ACKeyboard::new();
+    let mut cached = load(ACQuirks::default(), &program);
+    let mut uncached = load(ACQuirks::default(), &program);
+    cached.seed(1);
+    uncached.seed(1);
+    for step in 0..1000 {
+        cached.step(&keyboard, None);
+        uncached.decoded = [ACDecoded::UNDECODED; 4096];
+        uncached.step(&keyboard, None);
+        assert!(cached.save_state() == uncached.save_state(), "step {} at {:03X}", step, uncached.pc());
+    }
+    // the patched instructions were run, with different operands each time
+    assert_ne!(cached.reg(2), 0);
+    assert_ne!(cached.reg(3), 0);
+}
+
+#[test]
+fn decoded_instructions_run_the_same_as_decoding_every_time() {
+    let keyboard = ACKeyboard::new();
+    for (seed, quirks) in (0..64).zip(all_quirks().into_iter().cycle()) {
+        // random instructions in a loop, a lot of them pointing I at the loop and storing over it
+        let mut random = crate::platform::ACXorShift::new(seed);
+        let mut program: Vec<u16> = (0..31)
+            .map(|_| {
+                let bits = u16::from_be_bytes([random.random_byte(), random.random_byte()]);
+                match bits % 4 {
+                    0 => 0xA200 | bits >> 2 & 0x3F,
+                    1 => [0xF033, 0xF055][bits as usize >> 2 & 1] | bits & 0x0F00,
+                    _ => bits,
+                }
+            })
+            .collect();
+        program.push(0x1200);
+        let mut cached = load(quirks, &program);
+        let mut uncached = load(quirks, &program);
+        cached.seed(seed);
+        uncached.seed(seed);
+        for _ in 0..2000 {
+            cached.step(&keyboard, Some(ACKey::K1));
+            uncached.decoded = [ACDecoded::UNDECODED; 4096];
+            uncached.step(&keyboard, Some(ACKey::K1));
+            assert_eq!((cached.pc(), cached.index(), cached.regs), (uncached.pc(), uncached.index(), uncached.regs), "seed {}", seed);
+        }
+        assert!(cached.save_state() == uncached.save_state(), "seed {}", seed);
+    }
+}
+
+#[cfg(feature = "jit")]
+#[test]
+fn jit_matches_the_interpreter() {
+    // every rom in the directory $ATE_CHIP_ROMS, if it is set, and random programs looping over a bit of everything
+    let mut roms: Vec<(String, Vec<u8>)> = match std::env::var_os("ATE_CHIP_ROMS") {
+        Some(dir) => {
+            let roms: Vec<_> = std::fs::read_dir(&dir)
+                .unwrap_or_else(|e| panic!("can't read ATE_CHIP_ROMS {:?}: {}", dir, e))
+                .flatten()
+                .filter(|entry| entry.path().extension().is_some_and(|ext| ext == "ch8"))
+                .map(|entry| (entry.path().display().to_string(), std::fs::read(entry.path()).unwrap()))
+                .collect();
+            assert!(!roms.is_empty(), "there are no .ch8 roms in ATE_CHIP_ROMS {:?}", dir);
+            roms
+        }
+        None => vec![],
+    };
+    let real_roms = roms.len();
+    const TEMPLATES: [u16; 32] = [
+        0x6000, 0x7000, 0x8000, 0x8001, 0x8002, 0x8003, 0x8004, 0x8005, 0x8006, 0x8007, 0x800E, 0xA200, 0xC000, 0xF007,
+        0xF015, 0xF018, 0xF01E, 0xF029, 0xF065, 0xF055, 0xF033, 0x3000, 0x4000, 0x5000, 0x9000, 0xD000, 0xE09E, 0xE0A1,
+        0xF00A, 0x00E0, 0x6000, 0x7000,
+    ];
+    for seed in 0..32 {
+        let mut random = crate::platform::ACXorShift::new(seed);
+        let mut program: Vec<u8> = (0..40)
+            .flat_map(|_| {
+                let template = TEMPLATES[random.random_byte() as usize % TEMPLATES.len()];
+                let operands = u16::from_be_bytes([random.random_byte(), random.random_byte()]);
+                let instr = match template {
+                    // I somewhere in the program, so stores land on code
+                    0xA200 => 0xA200 | operands & 0x4F,
+                    0xF007..=0xF065 | 0xE09E | 0xE0A1 => template | operands & 0x0F00,
+                    0x5000 | 0x9000 | 0x8000..=0x800E => template | operands & 0x0FF0,
+                    _ => template | operands & 0x0FFF,
+                };
+                instr.to_be_bytes()
+            })
+            .collect();
+        program.extend([0x12, 0x00]);
+        roms.push((format!("random program {}", seed), program));
+    }
+
+    // runs both with the same key presses, checking they stay the same
+    let race = |interpreted: &mut ACEmulator, jitted: &mut ACEmulator, name: &str, quirks: ACQuirks| {
+        for frame in 0..300 {
+            // a different key held every 10 frames
+            let key = ACKey::from_hex((frame / 10 % 16) as u8).unwrap();
+            let new_keypress = (frame % 10 == 0).then_some(key);
+            let mut keyboard = ACKeyboard::new();
+            keyboard.press(key);
+            for emu in [&mut *interpreted, &mut *jitted] {
+                emu.run_frame(&keyboard, new_keypress, 5 + frame % 11);
+            }
+            assert!(interpreted.save_state() == jitted.save_state(), "{} with {:?}, frame {}", name, quirks, frame);
+        }
+    };
+    let start = |quirks: ACQuirks, rom: &[u8]| {
+        let mut emu = load(quirks, &[]);
+        emu.load_rom(rom);
+        emu.seed(7);
+        emu
+    };
+
+    let mut blocks = 0;
+    for (n, (name, rom)) in roms.iter().enumerate() {
+        for quirks in all_quirks() {
+            let mut jitted = start(quirks, rom);
+            jitted.enable_jit().unwrap();
+            race(&mut start(quirks, rom), &mut jitted, name, quirks);
+            blocks += jitted.jit.as_ref().unwrap().blocks();
+
+            // handing the compiled code on to the next rom keeps only what is the same in both
+            let (next, next_rom) = &roms[(n + 1) % roms.len()];
+            let mut again = start(quirks, next_rom);
+            again.take_jit(&mut jitted);
+            race(&mut start(quirks, next_rom), &mut again, &format!("{} after {}", next, name), quirks);
+        }
+    }
+    assert!(blocks > 100, "only {} blocks were compiled", blocks);
+    println!("compared {} roms from ATE_CHIP_ROMS and {} random programs", real_roms, roms.len() - real_roms);
+}
+
+#[test]
+fn unknown_instructions_are_logged() {
+    let mut emu = load(ACQuirks::default(), &[0x00E0, 0x0123, 0x8AB9, 0xF0FF, 0x5121]);
+    emu.unknown_log = Some(BTreeSet::new());
+    let keyboard = ACKeyboard::new();
+    for _ in 0..5 {
+        emu.step(&keyboard, None);
+    }
+    let log: Vec<(u16, u16)> = emu.unknown_log.unwrap().into_iter().collect();
+    assert_eq!(log, [(0x202, 0x0123), (0x204, 0x8AB9), (0x206, 0xF0FF), (0x208, 0x5121)]);
+}
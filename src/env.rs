@@ -0,0 +1,261 @@
+//! A gym style environment for training agents on chip-8 games.
+//!
+//! An action is the set of keys held down, as a mask with bit `n` for key `n`. The observation is the screen,
+//! packed as by [`ACFramebuffer::packed`](crate::emulator::ACFramebuffer::packed). Games don't say what the score is,
+//! so reward and done come from rules reading the rom's memory, which are different for every game
+use crate::emulator::ACEmulator;
+use crate::keyboard::{ACKey, ACKeyboard};
+use crate::quirks::ACQuirks;
+
+/// A number the game keeps in memory
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ACReadout {
+    /// the byte at an address
+    Byte(u16),
+    /// two bytes at an address, high byte first
+    Word(u16),
+    /// a number stored a decimal digit per byte, as `FX33` does, at an address with this many digits
+    Bcd(u16, u8),
+    /// register `VX`
+    Register(u8),
+}
+
+impl ACReadout {
+    pub fn read(self, emulator: &ACEmulator) -> i64 {
+        let mem = |addr: u16| emulator.memory()[addr as usize & 0xFFF] as i64;
+        match self {
+            Self::Byte(addr) => mem(addr),
+            Self::Word(addr) => mem(addr) << 8 | mem(addr.wrapping_add(1)),
+            Self::Bcd(addr, digits) => (0..digits as u16).fold(0, |acc, n| acc * 10 + mem(addr.wrapping_add(n))),
+            Self::Register(x) => emulator.reg(x as usize & 0xF) as i64,
+        }
+    }
+}
+
+/// The reward for a step is how much the readout went up, times `scale`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ACRewardRule {
+    pub value: ACReadout,
+    pub scale: f32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ACCompare {
+    Equal,
+    NotEqual,
+    Less,
+    Greater,
+}
+
+/// The episode is over once the readout compares to `than` like this, lives reaching 0 for example
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ACDoneRule {
+    pub value: ACReadout,
+    pub compare: ACCompare,
+    pub than: i64,
+}
+
+impl ACDoneRule {
+    pub fn is_done(&self, emulator: &ACEmulator) -> bool {
+        let value = self.value.read(emulator);
+        match self.compare {
+            ACCompare::Equal => value == self.than,
+            ACCompare::NotEqual => value != self.than,
+            ACCompare::Less => value < self.than,
+            ACCompare::Greater => value > self.than,
+        }
+    }
+}
+
+/// Everything needed to set up an environment for a game
+#[derive(Debug, Clone)]
+pub struct ACEnvConfig {
+    pub rom: Vec<u8>,
+    pub quirks: ACQuirks,
+    pub cycles_per_frame: usize,
+    pub rewards: Vec<ACRewardRule>,
+    /// an episode ends when any of these are met, or the emulator faults
+    pub done: Vec<ACDoneRule>,
+    /// an episode ends after this many frames, if it hasn't already
+    pub max_frames: Option<usize>,
+    /// compile the game to native code as it runs, which needs the `jit` feature
+    pub jit: bool,
+}
+
+/// The result of a step
+#[derive(Debug, Clone, PartialEq)]
+pub struct ACStep {
+    pub observation: [u8; 256],
+    pub reward: f32,
+    /// a done rule was met or the emulator faulted, the episode is over
+    pub done: bool,
+    /// `max_frames` ran out, which isn't the agent's fault
+    pub truncated: bool,
+}
+
+/// A game an agent can play, see the module docs
+pub struct ACEnv {
+    config: ACEnvConfig,
+    emulator: ACEmulator,
+    keyboard: ACKeyboard,
+    /// the last action, to tell which keys are newly pressed
+    action: u16,
+    /// frames since the last reset
+    frame: usize,
+    /// the value of each reward rule at the end of the last step
+    values: Vec<i64>,
+}
+
+impl ACEnv {
+    /// Sets up the environment, ready to step as if just reset with seed 0
+    pub fn new(config: ACEnvConfig) -> Self {
+        #[cfg(not(feature = "jit"))]
+        if config.jit {
+            log::warn!("ate-chip was built without the JIT, rebuild with --features jit");
+        }
+        let mut env = Self {
+            config,
+            emulator: ACEmulator::new(),
+            keyboard: ACKeyboard::new(),
+            action: 0,
+            frame: 0,
+            values: vec![],
+        };
+        env.reset(0);
+        env
+    }
+
+    /// Starts a new episode, with random numbers coming from `seed`
+    pub fn reset(&mut self, seed: u64) -> [u8; 256] {
+        // the last episode's emulator is kept until the end, for its compiled code
+        let mut previous = ACEmulator::new();
+        std::mem::swap(&mut self.emulator, &mut previous);
+        self.emulator.quirks = self.config.quirks;
+        self.emulator.seed(seed);
+        self.emulator.load_rom(&self.config.rom);
+        // the same game every episode, so whatever was compiled last time is still good
+        #[cfg(feature = "jit")]
+        if self.config.jit {
+            self.emulator.take_jit(&mut previous);
+            if let Err(e) = self.emulator.enable_jit() {
+                log::warn!("Not compiling the game: {}", e);
+            }
+        }
+        self.keyboard = ACKeyboard::new();
+        self.action = 0;
+        self.frame = 0;
+        self.values = self.config.rewards.iter().map(|rule| rule.value.read(&self.emulator)).collect();
+        self.observation()
+    }
+
+    /// Holds down the keys in `action` for `frames` frames, stopping early if the episode ends
+    pub fn step(&mut self, action: u16, frames: usize) -> ACStep {
+        // the lowest numbered key that wasn't held last step, for `FX0A`
+        let pressed = action & !self.action;
+        let mut new_keypress = (pressed != 0).then(|| pressed.trailing_zeros() as u8).and_then(ACKey::from_hex);
+        for n in 0..16 {
+            let key = ACKey::from_hex(n).expect("0-15 are all keys");
+            if action & 1 << n != 0 {
+                self.keyboard.press(key);
+            } else {
+                self.keyboard.release(key);
+            }
+        }
+        self.action = action;
+
+        let (mut done, mut truncated) = (false, false);
+        for _ in 0..frames {
+            self.emulator.run_frame(&self.keyboard, new_keypress.take(), self.config.cycles_per_frame);
+            self.frame += 1;
+            done = self.emulator.fault().is_some() || self.config.done.iter().any(|rule| rule.is_done(&self.emulator));
+            truncated = self.config.max_frames.is_some_and(|max| self.frame >= max);
+            if done || truncated {
+                break;
+            }
+        }
+
+        let mut reward = 0.0;
+        for (rule, last) in self.config.rewards.iter().zip(self.values.iter_mut()) {
+            let value = rule.value.read(&self.emulator);
+            reward += (value - *last) as f32 * rule.scale;
+            *last = value;
+        }
+        ACStep {
+            observation: self.observation(),
+            reward,
+            done,
+            truncated,
+        }
+    }
+
+    /// The screen, a row at a time, 8 pixels to a byte
+    pub fn observation(&self) -> [u8; 256] {
+        self.emulator.framebuffer().packed()
+    }
+
+    /// The emulator underneath, for looking at more than the screen
+    pub fn emulator(&self) -> &ACEmulator {
+        &self.emulator
+    }
+
+    /// frames since the last reset
+    pub fn frame(&self) -> usize {
+        self.frame
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gym_env() {
+        // counts up a 3 digit score at 0x300 while key 5 is held, picking a random number each time round, until 5
+        let program: [u16; 10] = [
+            0x6505, 0x6A00, 0xA300, 0xE5A1, 0x7A01, 0xFA33, 0xC0FF, 0x3A05, 0x1206, 0x1212,
+        ];
+        let config = ACEnvConfig {
+            rom: program.iter().flat_map(|instr| instr.to_be_bytes()).collect(),
+            quirks: ACQuirks::default(),
+            cycles_per_frame: 6,
+            rewards: vec![ACRewardRule { value: ACReadout::Bcd(0x300, 3), scale: 0.5 }],
+            done: vec![ACDoneRule { value: ACReadout::Register(0xA), compare: ACCompare::Equal, than: 5 }],
+            max_frames: Some(20),
+            jit: cfg!(feature = "jit"),
+        };
+        let mut env = ACEnv::new(config.clone());
+        let step = env.step(0, 3);
+        assert_eq!((step.reward, step.done, step.truncated), (0.0, false, false));
+        assert_eq!(step.observation, env.emulator().framebuffer().packed());
+
+        // rewards add up over the episode, until the done rule is met
+        let mut total = 0.0;
+        loop {
+            let step = env.step(1 << 5, 1);
+            total += step.reward;
+            if step.done {
+                break;
+            }
+        }
+        assert_eq!(total, 2.5);
+        assert_eq!(env.emulator().memory()[0x300..0x303], [0, 0, 5]);
+        assert!(env.frame() < 20);
+
+        // the same seed gives the same random numbers
+        let randoms = |env: &mut ACEnv, seed| {
+            env.reset(seed);
+            let mut randoms = vec![];
+            for _ in 0..4 {
+                env.step(1 << 5, 1);
+                randoms.push(env.emulator().reg(0));
+            }
+            randoms
+        };
+        assert_eq!(randoms(&mut env, 7), randoms(&mut env, 7));
+
+        // an episode is cut short at max_frames
+        let mut env = ACEnv::new(ACEnvConfig { max_frames: Some(4), ..config });
+        let step = env.step(0, 10);
+        assert_eq!((step.done, step.truncated, env.frame()), (false, true, 4));
+    }
+}
@@ -0,0 +1,135 @@
+//! What the emulator runs inside of: something to show the screen on ([`ACRenderer`]),
+//! and somewhere for key presses to come from ([`ACFrontend`]).
+//!
+//! Frontends only ever see the emulator through [`ACFramebuffer`], so new ones can be added without changing the core
+
+use std::path::PathBuf;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::emulator::{ACEmulator, ACFramebuffer};
+use crate::image::ACBitmap;
+use crate::keyboard::{ACKey, ACKeyboard};
+pub use crate::platform::ACInput;
+
+/// Something that can show the screen
+pub trait ACRenderer {
+    /// Shows `framebuffer`, `tone` being whether the sound timer is running. Called once per frame
+    fn render(&mut self, framebuffer: &ACFramebuffer, tone: bool) -> Result<(), String>;
+}
+
+/// A renderer that is also where input comes from, everything needed to play a game
+pub trait ACFrontend: ACRenderer {
+    /// Updates `keyboard` with what happened since the last frame
+    fn poll_input(&mut self, keyboard: &mut ACKeyboard) -> Result<ACInput, String>;
+
+    /// Whether frames should be shown at 60hz, rather than as fast as they can be run
+    fn realtime(&self) -> bool {
+        true
+    }
+
+    /// Runs a frame of `emulator`, for frontends that need to see what happens between instructions
+    fn run_frame(&mut self, emulator: &mut ACEmulator, keyboard: &ACKeyboard, new_keypress: Option<ACKey>, cycles: usize) -> Result<(), String> {
+        emulator.run_frame(keyboard, new_keypress, cycles);
+        Ok(())
+    }
+
+    /// Called after each frame is rendered, for frontends that show more than just the screen
+    fn inspect(&mut self, _emulator: &mut ACEmulator) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+/// Runs `emulator` in `frontend` until it asks to quit, `cycles_per_frame` instructions at a time
+pub fn run(emulator: &mut ACEmulator, frontend: &mut impl ACFrontend, cycles_per_frame: usize) -> Result<(), String> {
+    let mut keyboard = ACKeyboard::new();
+    let frame_duration = Duration::from_secs(1) / 60;
+    let mut next_frame = Instant::now() + frame_duration;
+    loop {
+        match frontend.poll_input(&mut keyboard)? {
+            ACInput::Continue(new_keypress) => frontend.run_frame(emulator, &keyboard, new_keypress, cycles_per_frame)?,
+            ACInput::Pause => {}
+            ACInput::Quit => return Ok(()),
+        }
+        frontend.render(emulator.framebuffer(), emulator.should_bleep())?;
+        frontend.inspect(emulator)?;
+
+        if frontend.realtime() {
+            let now = Instant::now();
+            thread::sleep(next_frame.saturating_duration_since(now));
+            // don't try to catch up if a frame took too long, just carry on from now
+            next_frame = (next_frame + frame_duration).max(now);
+        }
+    }
+}
+
+/// Shows nothing, for running without a screen
+#[derive(Debug, Default)]
+pub struct ACNullRenderer;
+
+impl ACRenderer for ACNullRenderer {
+    fn render(&mut self, _framebuffer: &ACFramebuffer, _tone: bool) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+/// Saves each frame that differs from the one before as a numbered image, `frame-00042.png` for example.
+/// The format (`png` or `pbm`) is picked by `extension`
+#[derive(Debug)]
+pub struct ACImageRenderer {
+    dir: PathBuf,
+    extension: String,
+    frame: usize,
+    last: Option<ACBitmap>,
+}
+
+impl ACImageRenderer {
+    pub fn new(dir: PathBuf, extension: &str) -> Self {
+        Self {
+            dir,
+            extension: extension.to_string(),
+            frame: 0,
+            last: None,
+        }
+    }
+}
+
+impl ACRenderer for ACImageRenderer {
+    fn render(&mut self, framebuffer: &ACFramebuffer, _tone: bool) -> Result<(), String> {
+        let bitmap = ACBitmap::from_framebuffer(framebuffer);
+        if self.last.as_ref() != Some(&bitmap) {
+            bitmap.save(&self.dir.join(format!("frame-{:05}.{}", self.frame, self.extension)))?;
+            self.last = Some(bitmap);
+        }
+        self.frame += 1;
+        Ok(())
+    }
+}
+
+/// Runs for a fixed number of frames as fast as possible with nobody at the keyboard, showing them with any renderer
+#[derive(Debug)]
+pub struct ACHeadless<R: ACRenderer> {
+    pub renderer: R,
+    /// frames left to run
+    pub frames: usize,
+}
+
+impl<R: ACRenderer> ACRenderer for ACHeadless<R> {
+    fn render(&mut self, framebuffer: &ACFramebuffer, tone: bool) -> Result<(), String> {
+        self.renderer.render(framebuffer, tone)
+    }
+}
+
+impl<R: ACRenderer> ACFrontend for ACHeadless<R> {
+    fn poll_input(&mut self, _keyboard: &mut ACKeyboard) -> Result<ACInput, String> {
+        if self.frames == 0 {
+            return Ok(ACInput::Quit);
+        }
+        self.frames -= 1;
+        Ok(ACInput::Continue(None))
+    }
+
+    fn realtime(&self) -> bool {
+        false
+    }
+}
@@ -0,0 +1,160 @@
+//! Inline images for terminals that can show them, so the terminal frontend can look as good as a window
+
+use crate::image::ACBitmap;
+
+/// Ways of getting pixels onto a terminal
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ACGraphicsProtocol {
+    /// DEC sixel graphics, supported by xterm (`-ti vt340`), foot, mlterm, WezTerm and others
+    Sixel,
+    /// https://sw.kovidgoyal.net/kitty/graphics-protocol/, supported by kitty, WezTerm, Ghostty and Konsole
+    Kitty,
+}
+
+/// kitty only accepts this many bytes of image data per escape sequence
+const KITTY_CHUNK: usize = 4096;
+
+/// `bitmap` as a sixel image, with lit pixels coloured `on` and the rest `off`
+pub fn sixel(bitmap: &ACBitmap, on: [u8; 3], off: [u8; 3]) -> String {
+    // sixel colours are percentages
+    let pct = |c: u8| c as u32 * 100 / 255;
+    let mut out = format!("\x1bPq\"1;1;{};{}", bitmap.width, bitmap.height);
+    for (n, [r, g, b]) in [off, on].iter().enumerate() {
+        out += &format!("#{};2;{};{};{}", n, pct(*r), pct(*g), pct(*b));
+    }
+
+    // each character is a column of 6 pixels, the image is drawn a band of 6 rows at a time
+    for band in 0..bitmap.height.div_ceil(6) {
+        for (n, lit) in [false, true].iter().enumerate() {
+            out += &format!("#{}", n);
+            let mut run: Option<(char, usize)> = None;
+            for x in 0..bitmap.width {
+                let mut bits = 0;
+                for dy in 0..6 {
+                    let y = band * 6 + dy;
+                    if y < bitmap.height && bitmap.pixels[y * bitmap.width + x] == *lit {
+                        bits |= 1 << dy;
+                    }
+                }
+                let c = char::from(63 + bits);
+                run = match run {
+                    Some((prev, len)) if prev == c => Some((c, len + 1)),
+                    Some((prev, len)) => {
+                        push_run(&mut out, prev, len);
+                        Some((c, 1))
+                    }
+                    None => Some((c, 1)),
+                };
+            }
+            if let Some((c, len)) = run {
+                push_run(&mut out, c, len);
+            }
+            // back to the start of the band, for the other colour
+            out.push('$');
+        }
+        out.push('-');
+    }
+    out += "\x1b\\";
+    out
+}
+
+/// Adds `len` copies of `c` to a sixel image, run length encoded when that is shorter
+fn push_run(out: &mut String, c: char, len: usize) {
+    if len > 3 {
+        *out += &format!("!{}{}", len, c);
+    } else {
+        out.extend(std::iter::repeat_n(c, len));
+    }
+}
+
+/// `png` as a kitty graphics image with the id `id` (replacing any image already using it),
+/// drawn at the cursor without moving it
+pub fn kitty(png: &[u8], id: u32) -> String {
+    let data = base64(png);
+    let chunks: Vec<&[u8]> = data.as_bytes().chunks(KITTY_CHUNK).collect();
+    let mut out = String::new();
+    for (n, chunk) in chunks.iter().enumerate() {
+        let more = (n + 1 < chunks.len()) as u8;
+        let control = if n == 0 {
+            // q=2 stops the terminal from answering, which would show up as key presses
+            format!("a=T,f=100,i={},q=2,C=1,m={}", id, more)
+        } else {
+            format!("m={}", more)
+        };
+        out += &format!("\x1b_G{};{}\x1b\\", control, std::str::from_utf8(chunk).expect("base64 is ascii"));
+    }
+    out
+}
+
+const BASE64_ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// Standard base64, with padding
+pub fn base64(data: &[u8]) -> String {
+    let mut out = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let b = [chunk[0], *chunk.get(1).unwrap_or(&0), *chunk.get(2).unwrap_or(&0)];
+        let n = (b[0] as u32) << 16 | (b[1] as u32) << 8 | b[2] as u32;
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(BASE64_ALPHABET[(n >> (18 - i * 6)) as usize & 0x3F] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+/// The reverse of `base64`, padding is optional
+pub fn unbase64(text: &str) -> Result<Vec<u8>, String> {
+    let text = text.trim_end_matches('=');
+    let mut out = Vec::with_capacity(text.len() * 3 / 4);
+    let (mut acc, mut bits) = (0u32, 0);
+    for c in text.bytes() {
+        let Some(v) = BASE64_ALPHABET.iter().position(|a| *a == c) else {
+            return Err(format!("{:?} is not a base64 character", c as char));
+        };
+        acc = acc << 6 | v as u32;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((acc >> bits) as u8);
+        }
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn inline_graphics() {
+        assert_eq!(base64(b""), "");
+        assert_eq!(base64(b"f"), "Zg==");
+        assert_eq!(base64(b"fo"), "Zm8=");
+        assert_eq!(base64(b"foobar"), "Zm9vYmFy");
+        for data in [&b""[..], b"f", b"fo", b"foo", b"\xFF\x00\x80\x7F"] {
+            assert_eq!(unbase64(&base64(data)).unwrap(), data);
+        }
+        assert!(unbase64("Zm9v!").is_err());
+
+        // a 2x7 image, with the top left and bottom right pixels lit
+        let mut pixels = vec![false; 14];
+        pixels[0] = true;
+        pixels[13] = true;
+        let bitmap = ACBitmap { width: 2, height: 7, pixels };
+        assert_eq!(
+            sixel(&bitmap, [255, 255, 255], [0, 0, 0]),
+            "\x1bPq\"1;1;2;7#0;2;0;0;0#1;2;100;100;100#0}~$#1@?$-#0@?$#1?@$-\x1b\\"
+        );
+        assert_eq!(bitmap.scaled(3).pixels.iter().filter(|p| **p).count(), 2 * 9);
+
+        // big images are split up
+        let png = vec![0; 10_000];
+        let out = kitty(&png, 1);
+        assert!(out.starts_with("\x1b_Ga=T,f=100,i=1,q=2,C=1,m=1;"));
+        assert_eq!(out.matches("\x1b_G").count(), base64(&png).len().div_ceil(4096));
+        assert!(out.ends_with(&format!("m=0;{}\x1b\\", &base64(&png)[3 * 4096..])));
+    }
+}
@@ -0,0 +1,324 @@
+use std::fs;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+
+use clap::ArgEnum;
+
+use ate_chip::emulator::ACEmulator;
+use ate_chip::image::ACBitmap;
+use ate_chip::keyboard::{ACKey, ACKeyboard};
+use ate_chip::quirks::ACQuirks;
+
+use crate::{ACEmError, QUIRK_PRESETS};
+
+#[derive(ArgEnum, Clone, Debug)]
+pub enum ReportFormat {
+    Tap,
+    Junit,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct TestArgs {
+    #[clap(help = "directory containing the roms (.ch8 or .c8) to test")]
+    dir: PathBuf,
+    #[clap(long, default_value_t = 300, help = "how many frames to run each rom for")]
+    frames: usize,
+    #[clap(long, default_value_t = 10, help = "instructions run per frame")]
+    cycles_per_frame: usize,
+    #[clap(long, default_value = "default", possible_values = QUIRK_PRESETS, help = "which interpreter to behave like")]
+    quirks: String,
+    #[clap(long, default_value_t = 0, help = "seed for the random numbers given by CXNN, so each run draws the same")]
+    seed: u64,
+    #[clap(long, arg_enum, default_value = "tap", help = "format of the test report")]
+    format: ReportFormat,
+    #[clap(long, help = "write the report to this file instead of stdout")]
+    report: Option<PathBuf>,
+    #[clap(long, help = "overwrite the golden images with what the roms actually drew")]
+    bless: bool,
+}
+
+/// Key presses to make while running a rom headless.
+///
+/// The format is one press per line, `<frame> <key> [<frames held>]`, where key is a single hex digit, e.g.
+/// ```text
+/// # start the game
+/// 30 5 3
+/// ```
+/// keys are held for one frame if no length is given.
+/// blank lines, and lines starting with `#` are ignored
+#[derive(Debug, Default, Clone)]
+pub struct ACKeyScript {
+    /// (frame, key, frames held)
+    presses: Vec<(usize, ACKey, usize)>,
+}
+
+impl ACKeyScript {
+    pub fn parse(src: &str) -> Result<Self, String> {
+        let mut presses = vec![];
+        for (n, line) in src.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let err = || format!("Invalid key script entry on line {}: {:?}", n + 1, line);
+            let parts: Vec<&str> = line.split_whitespace().collect();
+            if parts.len() < 2 || parts.len() > 3 {
+                return Err(err());
+            }
+            let frame = parts[0].parse().map_err(|_| err())?;
+            let key = u8::from_str_radix(parts[1], 16).ok().and_then(ACKey::from_hex).ok_or_else(err)?;
+            let held = match parts.get(2) {
+                Some(held) => held.parse().map_err(|_| err())?,
+                None => 1,
+            };
+            presses.push((frame, key, held));
+        }
+        Ok(Self { presses })
+    }
+
+    /// Updates `keyboard` for the start of `frame`, returning a key that was newly pressed
+    pub fn apply(&self, frame: usize, keyboard: &mut ACKeyboard) -> Option<ACKey> {
+        let mut new_keypress = None;
+        for (start, key, held) in &self.presses {
+            if frame == start + held {
+                keyboard.release(*key);
+            }
+        }
+        for (start, key, held) in &self.presses {
+            if frame >= *start && frame < start + held {
+                if frame == *start {
+                    new_keypress = Some(*key);
+                }
+                keyboard.press(*key);
+            }
+        }
+        new_keypress
+    }
+}
+
+/// Runs `rom` for `frames` frames with no display, pressing keys according to `script`, with random numbers
+/// from `seed`
+pub fn run_headless(rom: &[u8], quirks: ACQuirks, seed: u64, frames: usize, cycles_per_frame: usize, script: &ACKeyScript) -> ACEmulator {
+    let mut emulator = ACEmulator::new();
+    emulator.quirks = quirks;
+    emulator.seed(seed);
+    emulator.load_rom(rom);
+    let mut keyboard = ACKeyboard::new();
+    for frame in 0..frames {
+        let new_keypress = script.apply(frame, &mut keyboard);
+        emulator.run_frame(&keyboard, new_keypress, cycles_per_frame);
+    }
+    emulator
+}
+
+/// The message of a caught panic
+pub fn panic_message(err: &(dyn std::any::Any + Send)) -> String {
+    if let Some(msg) = err.downcast_ref::<&str>() {
+        msg.to_string()
+    } else if let Some(msg) = err.downcast_ref::<String>() {
+        msg.clone()
+    } else {
+        "unknown panic".to_string()
+    }
+}
+
+pub(crate) enum Outcome {
+    Pass,
+    Blessed,
+    Fail(String),
+}
+
+pub(crate) struct TestResult {
+    pub name: String,
+    pub outcome: Outcome,
+}
+
+/// Golden image for `rom`, `<name>.png` if there is one, otherwise `<name>.pbm`
+fn golden_path(rom: &Path) -> PathBuf {
+    let png = rom.with_extension("png");
+    if png.exists() {
+        png
+    } else {
+        rom.with_extension("pbm")
+    }
+}
+
+fn test_rom(rom_path: &Path, args: &TestArgs) -> Result<Outcome, String> {
+    let rom = fs::read(rom_path).map_err(|e| e.to_string())?;
+    let keys_path = rom_path.with_extension("keys");
+    let script = if keys_path.exists() {
+        ACKeyScript::parse(&fs::read_to_string(&keys_path).map_err(|e| e.to_string())?)?
+    } else {
+        ACKeyScript::default()
+    };
+
+    let quirks = ACQuirks::preset(&args.quirks).expect("clap checked that the preset exists");
+    let emulator = panic::catch_unwind(AssertUnwindSafe(|| run_headless(&rom, quirks, args.seed, args.frames, args.cycles_per_frame, &script)))
+        .map_err(|e| format!("emulator panicked: {}", panic_message(&*e)))?;
+    let actual = ACBitmap::from_framebuffer(emulator.framebuffer());
+
+    let golden_path = golden_path(rom_path);
+    if args.bless {
+        actual.save(&golden_path)?;
+        return Ok(Outcome::Blessed);
+    }
+    if !golden_path.exists() {
+        return Ok(Outcome::Fail(format!("no golden image, expected {}", golden_path.display())));
+    }
+    let golden = ACBitmap::load(&golden_path)?;
+    if golden.width != actual.width || golden.height != actual.height {
+        return Ok(Outcome::Fail(format!(
+            "golden image is {}x{}, but the screen is {}x{}",
+            golden.width, golden.height, actual.width, actual.height
+        )));
+    }
+    let differing = golden.pixels.iter().zip(&actual.pixels).filter(|(a, b)| a != b).count();
+    if differing == 0 {
+        Ok(Outcome::Pass)
+    } else {
+        // keep what was drawn around, so the difference can be looked at
+        let ext = golden_path.extension().and_then(|e| e.to_str()).unwrap_or("pbm");
+        let actual_path = rom_path.with_extension(format!("actual.{}", ext));
+        actual.save(&actual_path)?;
+        Ok(Outcome::Fail(format!(
+            "{} pixels differ from {}, the screen was saved to {}",
+            differing,
+            golden_path.display(),
+            actual_path.display()
+        )))
+    }
+}
+
+pub(crate) fn xml_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+pub(crate) fn report(results: &[TestResult], format: &ReportFormat) -> String {
+    let mut out = String::new();
+    match format {
+        ReportFormat::Tap => {
+            out += "TAP version 13\n";
+            out += &format!("1..{}\n", results.len());
+            for (n, result) in results.iter().enumerate() {
+                match &result.outcome {
+                    Outcome::Pass => out += &format!("ok {} - {}\n", n + 1, result.name),
+                    Outcome::Blessed => out += &format!("ok {} - {} # SKIP blessed\n", n + 1, result.name),
+                    Outcome::Fail(msg) => {
+                        out += &format!("not ok {} - {}\n", n + 1, result.name);
+                        out += &format!("  ---\n  message: {:?}\n  ...\n", msg);
+                    }
+                }
+            }
+        }
+        ReportFormat::Junit => {
+            let failures = results.iter().filter(|r| matches!(r.outcome, Outcome::Fail(_))).count();
+            let skipped = results.iter().filter(|r| matches!(r.outcome, Outcome::Blessed)).count();
+            out += "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n";
+            out += &format!(
+                "<testsuite name=\"ate-chip\" tests=\"{}\" failures=\"{}\" skipped=\"{}\">\n",
+                results.len(),
+                failures,
+                skipped
+            );
+            for result in results {
+                let name = xml_escape(&result.name);
+                match &result.outcome {
+                    Outcome::Pass => out += &format!("  <testcase classname=\"ate-chip\" name=\"{}\"/>\n", name),
+                    Outcome::Blessed => {
+                        out += &format!("  <testcase classname=\"ate-chip\" name=\"{}\">\n", name);
+                        out += "    <skipped message=\"blessed\"/>\n  </testcase>\n";
+                    }
+                    Outcome::Fail(msg) => {
+                        out += &format!("  <testcase classname=\"ate-chip\" name=\"{}\">\n", name);
+                        out += &format!("    <failure message=\"{}\"/>\n  </testcase>\n", xml_escape(msg));
+                    }
+                }
+            }
+            out += "</testsuite>\n";
+        }
+    }
+    out
+}
+
+/// Every rom in `dir`, sorted so reports are stable
+pub fn find_roms(dir: &Path) -> Result<Vec<PathBuf>, ACEmError> {
+    let mut roms: Vec<PathBuf> = fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| matches!(path.extension().and_then(|e| e.to_str()), Some("ch8") | Some("c8")))
+        .collect();
+    roms.sort();
+    Ok(roms)
+}
+
+/// `ate-chip test`, runs every rom in a directory and compares what is on screen at the end to a golden image
+pub fn test(args: TestArgs) -> Result<(), ACEmError> {
+    let roms = find_roms(&args.dir)?;
+
+    let results: Vec<TestResult> = roms
+        .iter()
+        .map(|rom| TestResult {
+            name: rom.file_name().unwrap_or_default().to_string_lossy().to_string(),
+            outcome: test_rom(rom, &args).unwrap_or_else(Outcome::Fail),
+        })
+        .collect();
+
+    let report = report(&results, &args.format);
+    match &args.report {
+        Some(path) => fs::write(path, report)?,
+        None => print!("{}", report),
+    }
+
+    let failed = results.iter().filter(|r| matches!(r.outcome, Outcome::Fail(_))).count();
+    if failed != 0 {
+        return Err(format!("{} of {} roms failed", failed, results.len()).into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn key_scripts_parse() {
+        let script = ACKeyScript::parse("# start the game\n10 5\n\n  20 a 3  \n").unwrap();
+        assert_eq!(script.presses, [(10, ACKey::K5, 1), (20, ACKey::KA, 3)]);
+    }
+
+    #[test]
+    fn bad_key_scripts_say_where() {
+        for (src, line) in [("10", 1), ("1 2\n10 g", 2), ("x 1", 1), ("1 1 1 1", 1), ("1 1 -1", 1), ("1 10", 1)] {
+            let err = ACKeyScript::parse(src).unwrap_err();
+            assert!(err.contains(&format!("line {}", line)), "{:?}: {}", src, err);
+        }
+    }
+
+    #[test]
+    fn keys_are_held_for_their_frames() {
+        let script = ACKeyScript::parse("1 5 2").unwrap();
+        let mut keyboard = ACKeyboard::new();
+        let held: Vec<(Option<ACKey>, bool)> = (0..4)
+            .map(|frame| (script.apply(frame, &mut keyboard), keyboard.is_pressed(&ACKey::K5)))
+            .collect();
+        assert_eq!(held, [(None, false), (Some(ACKey::K5), true), (None, true), (None, false)]);
+    }
+
+    #[test]
+    fn runs_draw_the_same_for_a_seed() {
+        // draws the font digit for a random number, somewhere random, over and over
+        let rom = [0xC0, 0x0F, 0xF0, 0x29, 0xC1, 0x3F, 0xC2, 0x1F, 0xD1, 0x25, 0x12, 0x00];
+        let run = |seed| {
+            let emulator = run_headless(&rom, ACQuirks::default(), seed, 10, 10, &ACKeyScript::default());
+            ACBitmap::from_framebuffer(emulator.framebuffer())
+        };
+        assert_eq!(run(1), run(1));
+        assert_ne!(run(1), run(2));
+
+        let quirks = ACQuirks::preset("cosmac").unwrap();
+        let emulator = run_headless(&rom, quirks, 0, 1, 1, &ACKeyScript::default());
+        assert_eq!(emulator.quirks, quirks);
+    }
+}
@@ -0,0 +1,174 @@
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+
+use crate::emulator::ACFramebuffer;
+
+/// A black and white image, for saving and comparing screenshots of the display
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ACBitmap {
+    pub width: usize,
+    pub height: usize,
+    /// row major, `true` is a lit pixel
+    pub pixels: Vec<bool>,
+}
+
+impl ACBitmap {
+    /// A copy of what is currently on the screen
+    pub fn from_framebuffer(framebuffer: &ACFramebuffer) -> Self {
+        let (width, height) = (crate::SCREEN_WIDTH as usize, crate::SCREEN_HEIGHT as usize);
+        let mut pixels = Vec::with_capacity(width * height);
+        for y in 0..height {
+            for x in 0..width {
+                pixels.push(framebuffer.get_pixel(x, y));
+            }
+        }
+        Self { width, height, pixels }
+    }
+
+    /// Loads a `.pbm` or `.png` file, depending on the extension
+    pub fn load(path: &Path) -> Result<Self, String> {
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("png") => Self::read_png(path),
+            _ => Self::parse_pbm(&std::fs::read(path).map_err(|e| e.to_string())?),
+        }
+    }
+
+    /// Saves as a `.pbm` or `.png` file, depending on the extension
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("png") => self.write_png(path),
+            _ => std::fs::write(path, self.to_pbm()).map_err(|e| e.to_string()),
+        }
+    }
+
+    /// Plain (`P1`) pbm, which is readable and diffs nicely
+    pub fn to_pbm(&self) -> String {
+        let mut out = format!("P1\n{} {}\n", self.width, self.height);
+        for row in self.pixels.chunks(self.width) {
+            let row: Vec<&str> = row.iter().map(|p| if *p { "1" } else { "0" }).collect();
+            out += &row.join(" ");
+            out += "\n";
+        }
+        out
+    }
+
+    /// Reads both plain (`P1`) and raw (`P4`) pbm files
+    pub fn parse_pbm(data: &[u8]) -> Result<Self, String> {
+        let err = |msg: &str| format!("Invalid pbm file: {}", msg);
+        // the header is whitespace separated, and can have comments in it
+        let mut pos = 0;
+        let mut header = vec![];
+        while header.len() < 3 {
+            while pos < data.len() && data[pos].is_ascii_whitespace() {
+                pos += 1;
+            }
+            if pos < data.len() && data[pos] == b'#' {
+                while pos < data.len() && data[pos] != b'\n' {
+                    pos += 1;
+                }
+                continue;
+            }
+            let start = pos;
+            while pos < data.len() && !data[pos].is_ascii_whitespace() {
+                pos += 1;
+            }
+            if start == pos {
+                return Err(err("truncated header"));
+            }
+            header.push(String::from_utf8_lossy(&data[start..pos]).to_string());
+        }
+        let width: usize = header[1].parse().map_err(|_| err("bad width"))?;
+        let height: usize = header[2].parse().map_err(|_| err("bad height"))?;
+
+        let pixels = match header[0].as_str() {
+            "P1" => data[pos..]
+                .iter()
+                .filter(|b| **b == b'0' || **b == b'1')
+                .map(|b| *b == b'1')
+                .take(width * height)
+                .collect::<Vec<bool>>(),
+            "P4" => {
+                // exactly one whitespace byte after the header
+                let body = data.get(pos + 1..).unwrap_or(&[]);
+                let row_bytes = width.div_ceil(8);
+                let mut pixels = Vec::with_capacity(width * height);
+                for row in body.chunks(row_bytes).take(height) {
+                    for x in 0..width {
+                        pixels.push(row.get(x / 8).is_some_and(|b| b & (0x80 >> (x % 8)) != 0));
+                    }
+                }
+                pixels
+            }
+            _ => return Err(err("not a P1 or P4 file")),
+        };
+        if pixels.len() != width * height {
+            return Err(err("truncated image data"));
+        }
+        Ok(Self { width, height, pixels })
+    }
+
+    /// Each pixel blown up into a `factor` x `factor` square
+    pub fn scaled(&self, factor: usize) -> Self {
+        let (width, height) = (self.width * factor, self.height * factor);
+        let mut pixels = Vec::with_capacity(width * height);
+        for row in self.pixels.chunks(self.width) {
+            let row: Vec<bool> = row.iter().flat_map(|p| std::iter::repeat_n(*p, factor)).collect();
+            for _ in 0..factor {
+                pixels.extend_from_slice(&row);
+            }
+        }
+        Self { width, height, pixels }
+    }
+
+    /// A 1 bit png, with lit pixels coloured `on` and the rest `off`
+    pub fn encode_png(&self, on: [u8; 3], off: [u8; 3]) -> Result<Vec<u8>, String> {
+        let mut data = vec![];
+        let mut encoder = png::Encoder::new(&mut data, self.width as u32, self.height as u32);
+        encoder.set_color(png::ColorType::Indexed);
+        encoder.set_depth(png::BitDepth::One);
+        encoder.set_palette([off, on].concat());
+        let mut writer = encoder.write_header().map_err(|e| e.to_string())?;
+        let mut packed = Vec::with_capacity(self.width.div_ceil(8) * self.height);
+        for row in self.pixels.chunks(self.width) {
+            for byte in row.chunks(8) {
+                packed.push(byte.iter().enumerate().fold(0, |acc, (bit, p)| acc | (*p as u8) << (7 - bit)));
+            }
+        }
+        writer.write_image_data(&packed).map_err(|e| e.to_string())?;
+        writer.finish().map_err(|e| e.to_string())?;
+        Ok(data)
+    }
+
+    pub fn write_png(&self, path: &Path) -> Result<(), String> {
+        let file = File::create(path).map_err(|e| e.to_string())?;
+        let mut encoder = png::Encoder::new(BufWriter::new(file), self.width as u32, self.height as u32);
+        encoder.set_color(png::ColorType::Grayscale);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder.write_header().map_err(|e| e.to_string())?;
+        let data: Vec<u8> = self.pixels.iter().map(|p| if *p { 0xFF } else { 0 }).collect();
+        writer.write_image_data(&data).map_err(|e| e.to_string())
+    }
+
+    /// Reads any png, treating bright pixels as lit
+    pub fn read_png(path: &Path) -> Result<Self, String> {
+        let file = File::open(path).map_err(|e| e.to_string())?;
+        let mut decoder = png::Decoder::new(file);
+        decoder.set_transformations(png::Transformations::EXPAND | png::Transformations::STRIP_16);
+        let mut reader = decoder.read_info().map_err(|e| e.to_string())?;
+        let mut buf = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut buf).map_err(|e| e.to_string())?;
+        let channels = info.color_type.samples();
+        let (width, height) = (info.width as usize, info.height as usize);
+        let mut pixels = Vec::with_capacity(width * height);
+        for row in buf.chunks(info.line_size).take(height) {
+            for px in row.chunks(channels).take(width) {
+                // ignore alpha
+                let color = if channels <= 2 { &px[..1] } else { &px[..3] };
+                let brightness = color.iter().map(|c| *c as usize).sum::<usize>() / color.len();
+                pixels.push(brightness >= 0x80);
+            }
+        }
+        Ok(Self { width, height, pixels })
+    }
+}
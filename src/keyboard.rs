@@ -1,6 +1,4 @@
-use std::collections::HashSet;
-
-#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
 pub enum ACKey {
     K0,
     K1,
@@ -43,6 +41,29 @@ impl ACKey {
         })
     }
 
+    /// The key at the same place on a qwerty keyboard, the keypad being mapped to `1234`/`qwer`/`asdf`/`zxcv`
+    pub fn from_qwerty(c: char) -> Option<Self> {
+        Some(match c.to_ascii_lowercase() {
+            '1' => Self::K1,
+            '2' => Self::K2,
+            '3' => Self::K3,
+            '4' => Self::KC,
+            'q' => Self::K4,
+            'w' => Self::K5,
+            'e' => Self::K6,
+            'r' => Self::KD,
+            'a' => Self::K7,
+            's' => Self::K8,
+            'd' => Self::K9,
+            'f' => Self::KE,
+            'z' => Self::KA,
+            'x' => Self::K0,
+            'c' => Self::KB,
+            'v' => Self::KF,
+            _ => return None,
+        })
+    }
+
     pub fn to_hex(self) -> u8 {
         match self {
             Self::K0 => 0x0,
@@ -67,25 +88,32 @@ impl ACKey {
 
 #[derive(Debug)]
 pub struct ACKeyboard {
-    keys_pressed: HashSet<ACKey>,
+    /// bit `n` is set while key `n` is held
+    keys_pressed: u16,
 }
 
 impl ACKeyboard {
     pub fn new() -> Self {
         Self {
-            keys_pressed: HashSet::new()
+            keys_pressed: 0
         }
     }
 
     pub fn is_pressed(&self, key: &ACKey) -> bool {
-        self.keys_pressed.contains(key)
+        self.keys_pressed & 1 << key.to_hex() != 0
     }
 
     pub fn press(&mut self, key: ACKey) {
-        self.keys_pressed.insert(key);
+        self.keys_pressed |= 1 << key.to_hex();
     }
 
     pub fn release(&mut self, key: ACKey) {
-        self.keys_pressed.remove(&key);
+        self.keys_pressed &= !(1 << key.to_hex());
     }
-}
\ No newline at end of file
+}
+
+impl Default for ACKeyboard {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,28 @@
+//! The chip-8 interpreter, and everything that does not need a window to work.
+//!
+//! Without the `std` feature only the interpreter is left ([`emulator`], [`keyboard`], [`quirks`] and [`platform`]),
+//! which is `no_std` and never allocates, for running on microcontrollers
+#![cfg_attr(not(feature = "std"), no_std)]
+#[cfg(feature = "std")]
+pub mod coverage;
+#[cfg(feature = "std")]
+pub mod disasm;
+pub mod emulator;
+#[cfg(feature = "std")]
+pub mod env;
+#[cfg(feature = "std")]
+pub mod frontend;
+#[cfg(feature = "std")]
+pub mod graphics;
+#[cfg(feature = "std")]
+pub mod image;
+pub mod keyboard;
+pub mod platform;
+pub mod quirks;
+#[cfg(feature = "std")]
+pub mod sprites;
+#[cfg(feature = "std")]
+pub mod textmode;
+
+pub const SCREEN_WIDTH: u8 = 64;
+pub const SCREEN_HEIGHT: u8 = 32;
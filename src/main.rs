@@ -1,68 +1,43 @@
-mod emulator;
-mod keyboard;
+mod bench;
+mod compat;
+mod control;
+mod harness;
+#[cfg(feature = "sdl")]
+mod memview;
+mod scenario;
+#[cfg(feature = "scripting")]
+mod script;
+#[cfg(feature = "sdl")]
+mod sdl;
+#[cfg(feature = "sdl")]
 mod settings;
+mod telnet;
+mod terminal;
+mod vnc;
 
 
 use std::path::PathBuf;
 use std::fs;
 use std::io::Read;
-use std::time::{Duration, Instant};
 
-use sdl2::audio::{AudioCallback, AudioSpecDesired};
-use sdl2::event::Event;
-use sdl2::keyboard::Keycode;
-use sdl2::pixels::{Color, PixelFormatEnum};
-
-use log::trace;
-
-use clap::Parser;
+use clap::{ArgEnum, Parser, Subcommand};
 
 use thiserror::Error;
 
-use emulator::ACEmulator;
-use settings::ACSettings;
-use keyboard::{ACKeyboard, ACKey};
+use ate_chip::sprites;
+use ate_chip::coverage::{ACCoverage, ACSymbols};
+use ate_chip::emulator::ACEmulator;
+use ate_chip::frontend::{ACFrontend, ACHeadless, ACImageRenderer, ACNullRenderer};
+use ate_chip::quirks::ACQuirks;
+use ate_chip::textmode::ACTextMode;
 
-const SCREEN_WIDTH: u8 = 64;
-const SCREEN_HEIGHT: u8 = 32;
+const QUIRK_PRESETS: [&str; 5] = ["default", "cosmac", "chip48", "schip", "xochip"];
 
 const NAME: &str = "Ate-Chip";
 const VERSION: &str = clap::crate_version!();
 const AUTHOR: &str = clap::crate_authors!();
 const ABOUT: &str = clap::crate_description!();
 
-const SETTINGS: ACSettings = ACSettings {
-    fx1e_affects_vf: false,
-    target_fps: 200,
-    audio: AudioSpecDesired {
-        freq: Some(44100),
-        channels: Some(1), // mono
-        samples: None,     // default sample size
-    }
-};
-
-struct SquareWave {
-    phase_inc: f32,
-    phase: f32,
-    volume: f32,
-}
-
-impl AudioCallback for SquareWave {
-    type Channel = f32;
-
-    fn callback(&mut self, out: &mut [f32]) {
-        // minecraft ocean
-        for x in out.iter_mut() {
-            *x = if self.phase <= 0.5 {
-                self.volume
-            } else {
-                -self.volume
-            };
-            self.phase = (self.phase + self.phase_inc) % 1.0;
-        }
-    }
-}
-
 #[derive(Error, Debug)]
 pub enum ACEmError {
     #[error("Failed to read file: {0}")]
@@ -77,224 +52,215 @@ impl From<String> for ACEmError {
     }
 }
 
+/// the window if there is one to be had, otherwise the terminal
+const DEFAULT_FRONTEND: &str = if cfg!(feature = "sdl") { "sdl" } else { "terminal" };
+
+#[derive(ArgEnum, Clone, Debug)]
+enum Frontend {
+    /// a window, with sound
+    Sdl,
+    /// draw in the terminal, which works over ssh
+    Terminal,
+    /// run `--frames` frames as fast as possible, saving each new frame to an image in `--output`
+    Image,
+    /// run `--frames` frames as fast as possible, showing nothing
+    Null,
+}
+
+#[derive(ArgEnum, Clone, Copy, Debug)]
+enum TextMode {
+    /// two characters per pixel, needs 128x32 columns
+    Blocks,
+    /// two pixels per character, needs 64x16 columns
+    HalfBlocks,
+    /// eight pixels per character, needs 32x8 columns
+    Braille,
+}
+
+impl From<TextMode> for ACTextMode {
+    fn from(mode: TextMode) -> Self {
+        match mode {
+            TextMode::Blocks => Self::Blocks,
+            TextMode::HalfBlocks => Self::HalfBlocks,
+            TextMode::Braille => Self::Braille,
+        }
+    }
+}
+
+#[derive(ArgEnum, Clone, Copy, Debug)]
+pub enum Graphics {
+    /// ask the terminal what it supports, falling back to text
+    Auto,
+    Sixel,
+    Kitty,
+    /// always draw with text
+    None,
+}
+
+#[derive(ArgEnum, Clone, Debug)]
+enum CoverageFormat {
+    Disasm,
+    Lcov,
+}
+
 #[derive(Parser, Debug)]
 #[clap(name = NAME, author = AUTHOR, version = VERSION, about = ABOUT, long_about = None)]
 struct Args {
+    #[clap(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Play a rom
+    Run(RunArgs),
+    /// Run a directory of roms headless, and compare the screen at the end to golden images
+    Test(harness::TestArgs),
+    /// Run scripted test scenarios
+    Scenario(scenario::ScenarioArgs),
+    /// Run a directory of roms headless under every quirk preset, and report how well each one works
+    Compat(compat::CompatArgs),
+    /// Run a rom as fast as possible, and report how fast that was
+    Bench(bench::BenchArgs),
+    /// Play a rom through any VNC viewer, without needing a window
+    ServeVnc(vnc::VncArgs),
+    /// Play a rom over telnet (or nc), with the first to connect on the keypad and everyone else watching
+    ServeTelnet(telnet::TelnetArgs),
+}
+
+#[derive(clap::Args, Debug)]
+pub struct RunArgs {
     #[clap(short, long, default_value_t = 8, help = "Sets the scaling factor")]
     scale: u32,
     #[clap(short, long, help = "path to the rom file")]
     rom: PathBuf,
+    #[clap(long, arg_enum, default_value = DEFAULT_FRONTEND, help = "where to show the game")]
+    frontend: Frontend,
+    #[clap(long, default_value_t = 10, help = "instructions run per frame, there are 60 frames a second")]
+    cycles_per_frame: usize,
+    #[clap(long, default_value_t = 600, help = "how many frames the image and null frontends run for")]
+    frames: usize,
+    #[clap(long, default_value = "frames", help = "directory the image frontend saves frames to, as frame-<n>.png")]
+    output: PathBuf,
+    #[clap(long, arg_enum, default_value = "blocks", help = "how the terminal frontend draws the screen")]
+    text_mode: TextMode,
+    #[clap(long, arg_enum, default_value = "auto", help = "image protocol the terminal frontend draws the screen with, `--scale` times its size")]
+    graphics: Graphics,
+    #[clap(long, default_value = "default", possible_values = QUIRK_PRESETS, help = "which interpreter to behave like")]
+    quirks: String,
+    #[clap(long, help = "true to wrap sprites around the edges of the screen, false to clip them, overriding the quirks preset")]
+    wrap_sprites: Option<bool>,
+    #[clap(long, help = "write a coverage report to this file on exit")]
+    coverage: Option<PathBuf>,
+    #[clap(long, arg_enum, default_value = "disasm", help = "format of the coverage report")]
+    coverage_format: CoverageFormat,
+    #[clap(long, help = "symbol table (`<hex address> <file>:<line>` per line) used to map coverage back to source")]
+    symbols: Option<PathBuf>,
+    #[clap(long, help = "open a second window showing the emulators memory")]
+    memview: bool,
+    #[clap(long, help = "on exit, write every sprite the rom draws (or seems to draw) to this png")]
+    rip_sprites: Option<PathBuf>,
+    #[clap(long, help = "listen for JSON-RPC commands on this unix socket, see src/control.rs")]
+    control: Option<PathBuf>,
+    #[clap(long, help = "run this rhai script alongside the game, see src/script.rs")]
+    script: Option<PathBuf>,
 }
 
 
 pub fn main() -> Result<(), ACEmError> {
     let args = Args::parse();
 
-    env_logger::builder().filter_level(log::LevelFilter::Info).init();
+    // cranelift logs every function the jit compiles at info
+    env_logger::builder().filter_level(log::LevelFilter::Info).filter_module("cranelift", log::LevelFilter::Warn).init();
+
+    match args.command {
+        Command::Run(args) => run(args),
+        Command::Test(args) => harness::test(args),
+        Command::Scenario(args) => scenario::scenario(args),
+        Command::Compat(args) => compat::compat(args),
+        Command::Bench(args) => bench::bench(args),
+        Command::ServeVnc(args) => vnc::serve(args),
+        Command::ServeTelnet(args) => telnet::serve(args),
+    }
+}
 
+fn run(args: RunArgs) -> Result<(), ACEmError> {
     let mut rom = Vec::new();
     fs::OpenOptions::new()
         .read(true)
-        .open(args.rom)?
+        .open(&args.rom)?
         .read_to_end(&mut rom)?;
+    let rom_len = rom.len();
+    let static_sprites = sprites::find_sprites(&rom, 0x200);
 
-    let sdl_context = sdl2::init()?;
-    let video_subsystem = sdl_context.video()?;
-    let audio_subsystem = sdl_context.audio()?;
-
-    // for beep thing
-    let noise_player = audio_subsystem.open_playback(None, &SETTINGS.audio, |spec| {
-        // initialize the audio callback
-        SquareWave {
-            phase_inc: 440.0 / spec.freq as f32,
-            phase: 0.0,
-            volume: 0.25,
-        }
-    })?;
-
-    let window = video_subsystem
-        .window(
-            "Ate-Chip",
-            SCREEN_WIDTH as u32 * args.scale,
-            SCREEN_HEIGHT as u32 * args.scale,
-        )
-        .position_centered()
-        .opengl()
-        .build()
-        .map_err(|e| e.to_string())?;
-
-    let mut canvas = window.into_canvas().build().map_err(|e| e.to_string())?;
-    canvas.set_draw_color(Color::RGB(0, 0, 0));
-    canvas.clear();
-    canvas.present();
-
-    let texture_creator = canvas.texture_creator();
-    let mut tex_display = texture_creator
-        .create_texture_streaming(
-            PixelFormatEnum::RGB24,
-            SCREEN_WIDTH as u32,
-            SCREEN_HEIGHT as u32,
-        )
-        .map_err(|e| e.to_string())?;
-
-    let frame_duration = Duration::new(0, 1_000_000_000u32 / SETTINGS.target_fps as u32);
+    let symbols = match &args.symbols {
+        Some(path) => Some(ACSymbols::parse(&fs::read_to_string(path)?)?),
+        None => None,
+    };
 
     let mut emulator = ACEmulator::new();
+    emulator.quirks = ACQuirks::preset(&args.quirks).expect("clap checked that the preset exists");
+    if let Some(wrap_sprites) = args.wrap_sprites {
+        emulator.quirks.wrap_sprites = wrap_sprites;
+    }
     emulator.load_rom(rom);
+    if args.coverage.is_some() {
+        emulator.coverage = Some(ACCoverage::new());
+    }
+    if args.rip_sprites.is_some() {
+        emulator.sprite_log = Some(static_sprites);
+    }
 
-    let mut event_pump = sdl_context.event_pump()?;
-    let mut timestamp = Instant::now();
-    let mut keyboard = ACKeyboard::new();
-    'running: loop {
-        canvas.clear();
-        trace!("frame");
-        let mut key_pressed: Option<ACKey> = None;
-
-        if let Some(event) = event_pump.poll_event() {
-            match event {
-                Event::Quit {..} => {
-                    break 'running
-                }
-                Event::KeyDown { keycode, .. } => {
-                    if let Some(key) = keycode {
-                        let key = match key {
-                            Keycode::Num1 => {
-                                ACKey::K1
-                            }
-                            Keycode::Num2 => {
-                                ACKey::K2
-                            }
-                            Keycode::Num3 => {
-                                ACKey::K3
-                            }
-                            Keycode::Num4 => {
-                                ACKey::KC
-                            }
-                            Keycode::Q => {
-                                ACKey::K4
-                            }
-                            Keycode::W => {
-                                ACKey::K5
-                            }
-                            Keycode::E => {
-                                ACKey::K6
-                            }
-                            Keycode::R => {
-                                ACKey::KD
-                            }
-                            Keycode::A => {
-                                ACKey::K7
-                            }
-                            Keycode::S => {
-                                ACKey::K8
-                            }
-                            Keycode::D => {
-                                ACKey::K9
-                            }
-                            Keycode::F => {
-                                ACKey::KE
-                            }
-                            Keycode::Z => {
-                                ACKey::KA
-                            }
-                            Keycode::X => {
-                                ACKey::K0
-                            }
-                            Keycode::C => {
-                                ACKey::KB
-                            }
-                            Keycode::V => {
-                                ACKey::KF
-                            }
-                            _ => {continue;}
-                        };
-                        keyboard.press(key.clone());
-                        key_pressed = Some(key);
-                    }
-                },
-                Event::KeyUp { keycode, .. } => {
-                    if let Some(key) = keycode {
-                        match key {
-                            Keycode::Num1 => {
-                                keyboard.release(ACKey::K1)
-                            }
-                            Keycode::Num2 => {
-                                keyboard.release(ACKey::K2)
-                            }
-                            Keycode::Num3 => {
-                                keyboard.release(ACKey::K3)
-                            }
-                            Keycode::Num4 => {
-                                keyboard.release(ACKey::KC)
-                            }
-                            Keycode::Q => {
-                                keyboard.release(ACKey::K4)
-                            }
-                            Keycode::W => {
-                                keyboard.release(ACKey::K5)
-                            }
-                            Keycode::E => {
-                                keyboard.release(ACKey::K6)
-                            }
-                            Keycode::R => {
-                                keyboard.release(ACKey::KD)
-                            }
-                            Keycode::A => {
-                                keyboard.release(ACKey::K7)
-                            }
-                            Keycode::S => {
-                                keyboard.release(ACKey::K8)
-                            }
-                            Keycode::D => {
-                                keyboard.release(ACKey::K9)
-                            }
-                            Keycode::F => {
-                                keyboard.release(ACKey::KE)
-                            }
-                            Keycode::Z => {
-                                keyboard.release(ACKey::KA)
-                            }
-                            Keycode::X => {
-                                keyboard.release(ACKey::K0)
-                            }
-                            Keycode::C => {
-                                keyboard.release(ACKey::KB)
-                            }
-                            Keycode::V => {
-                                keyboard.release(ACKey::KF)
-                            }
-                            _ => {},
-                        }
-                    }
-                }
-                _ => {}
+    match args.frontend {
+        #[cfg(feature = "sdl")]
+        Frontend::Sdl => sdl::run(&args, &mut emulator)?,
+        #[cfg(not(feature = "sdl"))]
+        Frontend::Sdl => return Err("ate-chip was built without SDL, use --frontend terminal".to_string().into()),
+        Frontend::Terminal => {
+            if args.memview {
+                log::warn!("The memory view needs SDL, it is not available in the terminal");
             }
+            terminal::run(&args, &mut emulator)?
         }
-
-        // testing code
-        // if keyboard.is_pressed(&ACKey::K1) {
-        //     emulator.set_pixel(10, 10, true);
-        // } else {
-        //     emulator.set_pixel(10, 10, false);
-        // }
-        emulator.update(&keyboard, key_pressed);
-        emulator.renderer.render_to_tex(&mut tex_display);
-        if emulator.should_bleep() {
-            noise_player.resume();
-        } else {
-            noise_player.pause();
+        Frontend::Image => {
+            fs::create_dir_all(&args.output)?;
+            let renderer = ACImageRenderer::new(args.output.clone(), "png");
+            let mut frontend = ACHeadless { renderer, frames: args.frames };
+            run_frontend(&args, &mut emulator, &mut frontend)?
         }
+        Frontend::Null => {
+            let mut frontend = ACHeadless { renderer: ACNullRenderer, frames: args.frames };
+            run_frontend(&args, &mut emulator, &mut frontend)?
+        }
+    }
+
+    if let (Some(path), Some(coverage)) = (&args.coverage, &emulator.coverage) {
+        let (start, end) = (0x200, 0x200 + rom_len as u16);
+        let report = match args.coverage_format {
+            CoverageFormat::Disasm => coverage.annotated_disassembly(emulator.memory(), start, end, symbols.as_ref()),
+            CoverageFormat::Lcov => coverage.lcov(emulator.memory(), start, end, symbols.as_ref(), &args.rom.to_string_lossy()),
+        };
+        fs::write(path, report)?;
+    }
 
-        canvas.clear();
-        canvas.copy(&tex_display, None, None)?;
-        canvas.present();
-        let now = Instant::now();
-        let sleep_dur = frame_duration
-            .checked_sub(now.saturating_duration_since(timestamp))
-            .unwrap_or(Duration::new(0, 0));
-        ::std::thread::sleep(sleep_dur);
-        timestamp = now;
+    if let (Some(path), Some(sprites)) = (&args.rip_sprites, &emulator.sprite_log) {
+        sprites::write_sprite_sheet(path, emulator.memory(), sprites)?;
     }
 
     Ok(())
-}
\ No newline at end of file
+}
+
+/// Plays `emulator` in `frontend`, with the `--script` and `--control` socket hooked in if asked for
+pub fn run_frontend(args: &RunArgs, emulator: &mut ACEmulator, frontend: &mut impl ACFrontend) -> Result<(), ACEmError> {
+    let control = args.control.as_deref();
+    match &args.script {
+        #[cfg(feature = "scripting")]
+        Some(path) => {
+            let mut scripted = script::ACScripted::load(path, emulator, frontend)?;
+            control::run(emulator, &mut scripted, args.cycles_per_frame, control)
+        }
+        #[cfg(not(feature = "scripting"))]
+        Some(_) => Err("ate-chip was built without scripting, rebuild with --features scripting".to_string().into()),
+        None => control::run(emulator, frontend, args.cycles_per_frame, control),
+    }
+}
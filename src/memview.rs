@@ -0,0 +1,184 @@
+use sdl2::render::Texture;
+
+use ate_chip::emulator::{ACEmulator, SPRITE_CHARS};
+use ate_chip::sprites::{sprite_rows, ACSpriteRef};
+
+/// bytes shown per row of the bitmap, so sprites line up one above the other
+const ROW_BYTES: usize = 8;
+/// memory is split into columns of this many rows, so the window is not absurdly tall
+const COL_ROWS: usize = 128;
+const COLS: usize = 4096 / (ROW_BYTES * COL_ROWS);
+const BITMAP_WIDTH: usize = COLS * ROW_BYTES * 8;
+
+/// width of a character of the hex view, including spacing
+const CHAR_WIDTH: usize = 5;
+const CHAR_HEIGHT: usize = 6;
+/// `AAA XX XX XX XX XX XX XX XX`
+const HEX_LINE_CHARS: usize = 3 + 1 + ROW_BYTES * 3;
+const HEX_LINES: usize = 16;
+const HEX_X: usize = BITMAP_WIDTH + 4;
+/// the sprite at I is shown under the hex view
+const SPRITE_Y: usize = HEX_LINES * CHAR_HEIGHT + 1;
+const SPRITE_SCALE: usize = 2;
+
+pub const WIDTH: usize = HEX_X + HEX_LINE_CHARS * CHAR_WIDTH;
+pub const HEIGHT: usize = COL_ROWS;
+
+/// how much the highlight of a write fades each frame
+const HEAT_DECAY: u8 = 2;
+
+const COLOR_OFF: [u8; 3] = [0, 0, 0];
+const COLOR_ON: [u8; 3] = [160, 160, 160];
+const COLOR_PC: [u8; 3] = [105, 237, 44];
+const COLOR_I: [u8; 3] = [60, 120, 255];
+const COLOR_STACK: [u8; 3] = [220, 60, 220];
+const COLOR_TEXT: [u8; 3] = [255, 255, 255];
+const COLOR_SELECTED: [u8; 3] = [60, 60, 0];
+
+/// Live view of all of the emulators memory, one row per 8 bytes, plus a hex dump of the selected area.
+///
+/// Recent writes are highlighted in red, and fade out over time.
+/// The bytes at PC, I and the return addresses on the stack are tinted.
+/// The `N` bytes at I are drawn as a sprite, using `N` from the last `DXYN` that was about to run
+pub struct ACMemView {
+    /// how recently each byte was written to, 255 being this frame
+    heat: Box<[u8; 4096]>,
+    /// first address shown in the hex view
+    hex_addr: u16,
+    /// height of the sprite at I
+    sprite_height: u8,
+}
+
+impl ACMemView {
+    pub fn new() -> Self {
+        Self {
+            heat: Box::new([0; 4096]),
+            hex_addr: 0x200,
+            // height of the font
+            sprite_height: 5,
+        }
+    }
+
+    /// Fades old writes and picks up new ones from `emulator.write_log`, call once per frame
+    pub fn update(&mut self, emulator: &mut ACEmulator) {
+        for heat in self.heat.iter_mut() {
+            *heat = heat.saturating_sub(HEAT_DECAY);
+        }
+        if let Some(log) = &mut emulator.write_log {
+            for addr in log.drain(..) {
+                self.heat[addr as usize & 0xFFF] = 255;
+            }
+        }
+        let pc = emulator.pc() as usize & 0xFFF;
+        let memory = emulator.memory();
+        if memory[pc] & 0xF0 == 0xD0 && pc + 1 < memory.len() && memory[pc + 1] & 0x0F != 0 {
+            self.sprite_height = memory[pc + 1] & 0x0F;
+        }
+    }
+
+    /// Handles a click at `x`, `y` (in unscaled view pixels), moving the hex view to the clicked address
+    pub fn click(&mut self, x: usize, y: usize) {
+        if x >= BITMAP_WIDTH || y >= HEIGHT {
+            return;
+        }
+        let col = x / (ROW_BYTES * 8);
+        let byte = (x % (ROW_BYTES * 8)) / 8;
+        let addr = col * COL_ROWS * ROW_BYTES + y * ROW_BYTES + byte;
+        self.hex_addr = (addr & !(ROW_BYTES - 1)) as u16;
+    }
+
+    /// Where byte `addr` is drawn in the bitmap
+    fn bitmap_pos(addr: usize) -> (usize, usize) {
+        let col = addr / (COL_ROWS * ROW_BYTES);
+        let row = (addr / ROW_BYTES) % COL_ROWS;
+        (col * ROW_BYTES * 8 + (addr % ROW_BYTES) * 8, row)
+    }
+
+    pub fn render_to_tex(&mut self, emulator: &ACEmulator, texture: &mut Texture) {
+        let memory = emulator.memory();
+        let pc = emulator.pc() as usize;
+        let i = emulator.index() as usize;
+        let stack = emulator.stack();
+        let hex_start = self.hex_addr as usize;
+        let hex_end = hex_start + HEX_LINES * ROW_BYTES;
+        let sprite = sprite_rows(memory, ACSpriteRef { addr: i as u16, height: self.sprite_height });
+
+        texture.with_lock(None, |buffer: &mut [u8], pitch: usize| {
+            let mut put = |x: usize, y: usize, color: [u8; 3]| {
+                let p = y * pitch + x * 3;
+                buffer[p..p + 3].copy_from_slice(&color);
+            };
+
+            for (addr, byte) in memory.iter().enumerate() {
+                let tint = if addr == pc || addr == pc + 1 {
+                    Some(COLOR_PC)
+                } else if addr == i {
+                    Some(COLOR_I)
+                } else if stack.iter().any(|ret| *ret as usize == addr || *ret as usize + 1 == addr) {
+                    Some(COLOR_STACK)
+                } else {
+                    None
+                };
+                let heat = self.heat[addr];
+                let selected = (hex_start..hex_end).contains(&addr);
+
+                let (x, y) = Self::bitmap_pos(addr);
+                for bit in 0..8 {
+                    let on = byte & (0x80 >> bit) != 0;
+                    let mut color = match (on, tint) {
+                        (true, Some(tint)) => tint,
+                        (false, Some(tint)) => tint.map(|c| c / 3),
+                        (true, None) => COLOR_ON,
+                        (false, None) if selected => COLOR_SELECTED,
+                        (false, None) => COLOR_OFF,
+                    };
+                    if heat != 0 {
+                        color[0] = color[0].max(heat);
+                    }
+                    put(x + bit, y, color);
+                }
+            }
+
+            // hex view
+            for x in BITMAP_WIDTH..WIDTH {
+                for y in 0..HEIGHT {
+                    put(x, y, COLOR_OFF);
+                }
+            }
+            for line in 0..HEX_LINES {
+                let addr = hex_start + line * ROW_BYTES;
+                if addr >= memory.len() {
+                    break;
+                }
+                let mut digits = vec![(addr >> 8) as u8 & 0xF, (addr >> 4) as u8 & 0xF, addr as u8 & 0xF, 0xFF];
+                for byte in &memory[addr..addr + ROW_BYTES] {
+                    digits.extend_from_slice(&[byte >> 4, byte & 0xF, 0xFF]);
+                }
+                for (n, digit) in digits.iter().enumerate() {
+                    // 0xFF is a space
+                    if let Some(glyph) = SPRITE_CHARS.get(*digit as usize) {
+                        for (gy, glyph_row) in glyph.iter().enumerate() {
+                            for gx in 0..4 {
+                                if glyph_row & (0x80 >> gx) != 0 {
+                                    put(HEX_X + n * CHAR_WIDTH + gx, line * CHAR_HEIGHT + gy, COLOR_TEXT);
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+
+            // sprite at I
+            for (row, px) in sprite.iter().enumerate() {
+                for (col, on) in px.iter().enumerate() {
+                    let color = if *on { COLOR_I } else { COLOR_SELECTED };
+                    for sy in 0..SPRITE_SCALE {
+                        for sx in 0..SPRITE_SCALE {
+                            put(HEX_X + col * SPRITE_SCALE + sx, SPRITE_Y + row * SPRITE_SCALE + sy, color);
+                        }
+                    }
+                }
+            }
+        }).expect("Rendered the memory view");
+    }
+}
@@ -0,0 +1,164 @@
+//! What the interpreter needs from whatever it runs on, so the same core works on a desktop or a microcontroller.
+//!
+//! With `std` the operating system provides these: a seed for [`ACXorShift`], [`ACStdClock`] and any
+//! [`ACRenderer`](crate::frontend::ACRenderer). Without it, boards bring their own, or use [`ACXorShift`] as it is
+use crate::emulator::{ACEmulator, ACFramebuffer};
+use crate::keyboard::{ACKey, ACKeyboard};
+
+/// Where `CXNN` gets its random numbers
+pub trait ACRandom {
+    fn random_byte(&mut self) -> u8;
+
+    /// Starts again from `seed`, the same seed always giving the same numbers
+    fn reseed(&mut self, seed: u64);
+
+    /// Where the sequence is up to, so save states can carry on with the same numbers
+    fn state(&self) -> u64;
+
+    /// Carries on from a `state`, giving the same numbers as the generator it came from
+    fn set_state(&mut self, state: u64);
+}
+
+/// Something that knows the time, for running at the right speed
+pub trait ACClock {
+    /// Microseconds since some fixed point, never going backwards
+    fn micros(&self) -> u64;
+}
+
+/// Something that can show the screen, without needing std like [`ACRenderer`](crate::frontend::ACRenderer) does
+pub trait ACDisplay {
+    type Error;
+
+    /// Shows `framebuffer`, `tone` being whether the sound timer is running. Called once per frame
+    fn show(&mut self, framebuffer: &ACFramebuffer, tone: bool) -> Result<(), Self::Error>;
+}
+
+/// What the user did since the last frame
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ACInput {
+    /// keep going, with the key that was newly pressed, if any
+    Continue(Option<ACKey>),
+    /// don't run the emulator this frame, just show it again
+    Pause,
+    /// stop the emulator
+    Quit,
+}
+
+/// xorshift64*, small and quick, for boards with nothing better. Fine for games, not for anything else
+#[derive(Debug, Clone)]
+pub struct ACXorShift(u64);
+
+impl ACXorShift {
+    pub fn new(seed: u64) -> Self {
+        let mut rng = Self(0);
+        rng.reseed(seed);
+        rng
+    }
+}
+
+impl ACRandom for ACXorShift {
+    fn random_byte(&mut self) -> u8 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        (self.0.wrapping_mul(0x2545_F491_4F6C_DD1D) >> 56) as u8
+    }
+
+    fn reseed(&mut self, seed: u64) {
+        // splitmix64, so similar seeds don't start off giving similar numbers
+        let mut z = seed.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        self.set_state(z ^ (z >> 31));
+    }
+
+    fn state(&self) -> u64 {
+        self.0
+    }
+
+    fn set_state(&mut self, state: u64) {
+        // xorshift is stuck at 0 forever
+        self.0 = state.max(1);
+    }
+}
+
+/// Where random numbers come from unless the emulator is given something else
+pub type ACDefaultRandom = ACXorShift;
+
+/// A different sequence every run where there's an OS to ask, the same one every time otherwise
+pub(crate) fn default_random() -> ACDefaultRandom {
+    #[cfg(feature = "std")]
+    return ACXorShift::new(rand::random());
+    #[cfg(not(feature = "std"))]
+    return ACXorShift::new(0);
+}
+
+/// The time since this was made, from the OS
+#[cfg(feature = "std")]
+#[derive(Debug, Clone)]
+pub struct ACStdClock(std::time::Instant);
+
+#[cfg(feature = "std")]
+impl ACStdClock {
+    pub fn new() -> Self {
+        Self(std::time::Instant::now())
+    }
+}
+
+#[cfg(feature = "std")]
+impl Default for ACStdClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(feature = "std")]
+impl ACClock for ACStdClock {
+    fn micros(&self) -> u64 {
+        self.0.elapsed().as_micros() as u64
+    }
+}
+
+#[cfg(feature = "std")]
+impl<R: crate::frontend::ACRenderer> ACDisplay for R {
+    type Error = String;
+
+    fn show(&mut self, framebuffer: &ACFramebuffer, tone: bool) -> Result<(), String> {
+        self.render(framebuffer, tone)
+    }
+}
+
+/// A 60th of a second
+const FRAME_MICROS: u64 = 16_667;
+
+/// Runs `emulator` on `display` at 60 frames a second by `clock`, `cycles_per_frame` instructions at a time, for
+/// boards without an OS. `poll` updates the keypad before each frame and says what to do.
+///
+/// With `std`, [`frontend::run`](crate::frontend::run) does the same for desktop frontends
+pub fn run<R: ACRandom, D: ACDisplay>(
+    emulator: &mut ACEmulator<R>,
+    clock: &impl ACClock,
+    display: &mut D,
+    cycles_per_frame: usize,
+    mut poll: impl FnMut(&mut ACKeyboard) -> ACInput,
+) -> Result<(), D::Error> {
+    let mut keyboard = ACKeyboard::new();
+    let mut next_frame = clock.micros() + FRAME_MICROS;
+    loop {
+        match poll(&mut keyboard) {
+            ACInput::Continue(new_keypress) => emulator.run_frame(&keyboard, new_keypress, cycles_per_frame),
+            ACInput::Pause => {}
+            ACInput::Quit => return Ok(()),
+        }
+        display.show(emulator.framebuffer(), emulator.should_bleep())?;
+
+        // with no OS there's nothing better to do than spin
+        let mut now = clock.micros();
+        while now < next_frame {
+            core::hint::spin_loop();
+            now = clock.micros();
+        }
+        // don't try to catch up if a frame took too long, just carry on from now
+        next_frame = (next_frame + FRAME_MICROS).max(now);
+    }
+}
@@ -0,0 +1,141 @@
+/// Behaviours that differ between chip-8 interpreters, and that roms end up depending on.
+///
+/// See https://github.com/Timendus/chip8-test-suite#quirks-test for what each interpreter does
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ACQuirks {
+    /// `8XY6`/`8XYE` shift VY and store the result in VX (COSMAC VIP), rather than shifting VX in place
+    pub shift_uses_vy: bool,
+    /// `FX55`/`FX65` leave I pointing just past the last register that was stored or loaded (COSMAC VIP)
+    pub load_store_increments_i: bool,
+    /// `8XY1`/`8XY2`/`8XY3` set VF to 0 (COSMAC VIP)
+    pub logic_resets_vf: bool,
+    /// `BNNN` jumps to NNN + VX, with X being the highest nibble of NNN (CHIP-48, SCHIP), rather than NNN + V0
+    pub jump_uses_vx: bool,
+    /// `FX1E` sets VF to 1 when I goes past 0xFFF, and 0 otherwise (Amiga)
+    ///
+    /// https://en.wikipedia.org/wiki/CHIP-8#Notes
+    pub fx1e_affects_vf: bool,
+    /// `DXYN` wraps sprites that go past the edge of the screen around to the other side (XO-CHIP),
+    /// rather than clipping them
+    pub wrap_sprites: bool,
+}
+
+impl ACQuirks {
+    /// The original interpreter, on the COSMAC VIP
+    pub const COSMAC: Self = Self {
+        shift_uses_vy: true,
+        load_store_increments_i: true,
+        logic_resets_vf: true,
+        jump_uses_vx: false,
+        fx1e_affects_vf: false,
+        wrap_sprites: false,
+    };
+
+    /// CHIP-48, on the HP-48 calculators
+    pub const CHIP48: Self = Self {
+        shift_uses_vy: false,
+        load_store_increments_i: false,
+        logic_resets_vf: false,
+        jump_uses_vx: true,
+        fx1e_affects_vf: false,
+        wrap_sprites: false,
+    };
+
+    /// SUPER-CHIP 1.1, which most modern roms expect
+    pub const SCHIP: Self = Self {
+        shift_uses_vy: false,
+        load_store_increments_i: false,
+        logic_resets_vf: false,
+        jump_uses_vx: true,
+        fx1e_affects_vf: false,
+        wrap_sprites: false,
+    };
+
+    /// XO-CHIP, from Octo
+    pub const XOCHIP: Self = Self {
+        shift_uses_vy: true,
+        load_store_increments_i: true,
+        logic_resets_vf: false,
+        jump_uses_vx: false,
+        fx1e_affects_vf: false,
+        wrap_sprites: true,
+    };
+
+    /// Every preset, with its name
+    pub const PRESETS: [(&'static str, Self); 4] = [
+        ("cosmac", Self::COSMAC),
+        ("chip48", Self::CHIP48),
+        ("schip", Self::SCHIP),
+        ("xochip", Self::XOCHIP),
+    ];
+
+    /// Looks up a preset by name, `default` being no quirks at all
+    pub fn preset(name: &str) -> Option<Self> {
+        if name == "default" {
+            return Some(Self::default());
+        }
+        Self::PRESETS.iter().find(|(n, _)| *n == name).map(|(_, q)| *q)
+    }
+
+    /// Packs the quirks into a byte, one bit each in the order they are declared
+    pub fn to_bits(self) -> u8 {
+        [
+            self.shift_uses_vy,
+            self.load_store_increments_i,
+            self.logic_resets_vf,
+            self.jump_uses_vx,
+            self.fx1e_affects_vf,
+            self.wrap_sprites,
+        ]
+        .iter()
+        .enumerate()
+        .fold(0, |acc, (bit, set)| acc | (*set as u8) << bit)
+    }
+
+    /// The reverse of `to_bits`, `None` if an unknown bit is set
+    pub fn from_bits(bits: u8) -> Option<Self> {
+        if bits >> 6 != 0 {
+            return None;
+        }
+        let bit = |n: u8| bits & (1 << n) != 0;
+        Some(Self {
+            shift_uses_vy: bit(0),
+            load_store_increments_i: bit(1),
+            logic_resets_vf: bit(2),
+            jump_uses_vx: bit(3),
+            fx1e_affects_vf: bit(4),
+            wrap_sprites: bit(5),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn presets_match_the_quirks_test() {
+        // (shift_uses_vy, load_store_increments_i, logic_resets_vf, jump_uses_vx, fx1e_affects_vf, wrap_sprites)
+        let expected = [
+            ("cosmac", (true, true, true, false, false, false)),
+            ("chip48", (false, false, false, true, false, false)),
+            ("schip", (false, false, false, true, false, false)),
+            ("xochip", (true, true, false, false, false, true)),
+        ];
+        for (name, values) in expected {
+            let q = ACQuirks::preset(name).unwrap();
+            let actual = (q.shift_uses_vy, q.load_store_increments_i, q.logic_resets_vf, q.jump_uses_vx, q.fx1e_affects_vf, q.wrap_sprites);
+            assert_eq!(actual, values, "{}", name);
+        }
+        assert_eq!(ACQuirks::preset("default"), Some(ACQuirks::default()));
+        assert_eq!(ACQuirks::preset("octo"), None);
+    }
+
+    #[test]
+    fn bits_round_trip() {
+        for (_, quirks) in ACQuirks::PRESETS {
+            assert_eq!(ACQuirks::from_bits(quirks.to_bits()), Some(quirks));
+        }
+        assert_eq!(ACQuirks::from_bits(1 << 6), None);
+    }
+}
@@ -0,0 +1,326 @@
+use std::fs;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+
+use ate_chip::emulator::ACEmulator;
+use ate_chip::keyboard::{ACKey, ACKeyboard};
+use ate_chip::quirks::ACQuirks;
+
+use crate::harness::{panic_message, report, Outcome, ReportFormat, TestResult};
+use crate::ACEmError;
+
+#[derive(clap::Args, Debug)]
+pub struct ScenarioArgs {
+    #[clap(required = true, help = "scenario files (.toml) to run")]
+    files: Vec<PathBuf>,
+    #[clap(long, arg_enum, default_value = "tap", help = "format of the test report")]
+    format: ReportFormat,
+    #[clap(long, help = "write the report to this file instead of stdout")]
+    report: Option<PathBuf>,
+}
+
+/// A scripted test of a rom, e.g.
+/// ```toml
+/// rom = "pong.ch8" # relative to the scenario file
+/// quirks = "schip" # optional, "default" if not given
+/// seed = 7 # optional, for the random numbers given by CXNN, 0 if not given
+/// steps = [
+///     { wait = 60 },
+///     { press = { key = 5, frames = 3 } },
+///     { assert_reg = { reg = 3, value = 0x10 } },
+///     { assert_memory = { addr = 0x300, bytes = [1, 2, 3] } },
+///     { assert_screen = { x = 0, y = 0, width = 16, height = 8, pattern = ["#..#", "####"] } },
+///     { assert_sound = true },
+/// ]
+/// ```
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct ACScenario {
+    pub rom: PathBuf,
+    #[serde(default = "default_cycles_per_frame")]
+    pub cycles_per_frame: usize,
+    /// name of the quirks preset to run with
+    #[serde(default = "default_quirks")]
+    pub quirks: String,
+    #[serde(default)]
+    pub seed: u64,
+    pub steps: Vec<Step>,
+}
+
+fn default_cycles_per_frame() -> usize {
+    10
+}
+
+fn default_quirks() -> String {
+    "default".to_string()
+}
+
+fn default_press_frames() -> usize {
+    1
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+pub enum Step {
+    /// Run this many frames
+    Wait(usize),
+    /// Hold down a key while running `frames` frames, then release it
+    Press {
+        key: u8,
+        #[serde(default = "default_press_frames")]
+        frames: usize,
+    },
+    AssertReg { reg: u8, value: u8 },
+    AssertMemory { addr: u16, bytes: Vec<u8> },
+    /// `pattern` must appear somewhere inside the region, rows of `#` (lit) and `.` (unlit)
+    AssertScreen {
+        x: usize,
+        y: usize,
+        width: usize,
+        height: usize,
+        pattern: Vec<String>,
+    },
+    /// Is (`true`) or isn't (`false`) the sound timer running
+    AssertSound(bool),
+}
+
+impl ACScenario {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let src = fs::read_to_string(path).map_err(|e| e.to_string())?;
+        let mut scenario: Self = toml::from_str(&src).map_err(|e| e.to_string())?;
+        if let Some(dir) = path.parent() {
+            scenario.rom = dir.join(&scenario.rom);
+        }
+        Ok(scenario)
+    }
+
+    /// Runs every step, stopping at the first assertion that fails
+    pub fn run(&self) -> Result<(), String> {
+        let rom = fs::read(&self.rom).map_err(|e| format!("Failed to read {}: {}", self.rom.display(), e))?;
+        let mut emulator = ACEmulator::new();
+        emulator.quirks = ACQuirks::preset(&self.quirks).ok_or_else(|| format!("There is no quirks preset {:?}", self.quirks))?;
+        emulator.seed(self.seed);
+        emulator.load_rom(rom);
+        let mut keyboard = ACKeyboard::new();
+
+        for (n, step) in self.steps.iter().enumerate() {
+            let fail = |msg: String| Err(format!("step {} ({:?}) failed: {}", n + 1, step, msg));
+            match step {
+                Step::Wait(frames) => {
+                    for _ in 0..*frames {
+                        emulator.run_frame(&keyboard, None, self.cycles_per_frame);
+                    }
+                }
+                Step::Press { key, frames } => {
+                    let key = match ACKey::from_hex(*key) {
+                        Some(key) => key,
+                        None => return fail(format!("{:#X} is not a key", key)),
+                    };
+                    keyboard.press(key);
+                    for frame in 0..*frames {
+                        let new_keypress = if frame == 0 { Some(key) } else { None };
+                        emulator.run_frame(&keyboard, new_keypress, self.cycles_per_frame);
+                    }
+                    keyboard.release(key);
+                }
+                Step::AssertReg { reg, value } => {
+                    if *reg > 0xF {
+                        return fail(format!("V{:X} is not a register", reg));
+                    }
+                    let actual = emulator.reg(*reg as usize);
+                    if actual != *value {
+                        return fail(format!("V{:X} is {:#04X}", reg, actual));
+                    }
+                }
+                Step::AssertMemory { addr, bytes } => {
+                    let start = *addr as usize;
+                    let actual = match emulator.memory().get(start..start + bytes.len()) {
+                        Some(actual) => actual,
+                        None => return fail("range is outside of memory".to_string()),
+                    };
+                    if actual != bytes.as_slice() {
+                        return fail(format!("memory contains {:02X?}", actual));
+                    }
+                }
+                Step::AssertScreen { x, y, width, height, pattern } => {
+                    match screen_contains(&emulator, (*x, *y, *width, *height), pattern) {
+                        Ok(true) => {}
+                        Ok(false) => return fail("pattern not found".to_string()),
+                        Err(msg) => return fail(msg),
+                    }
+                }
+                Step::AssertSound(active) => {
+                    let actual = emulator.sound_timer() != 0;
+                    if actual != *active {
+                        return fail(format!("sound timer is {}", emulator.sound_timer()));
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Does `pattern` appear anywhere within the (x, y, width, height) `region` of the screen. An empty pattern is an
+/// error, as it would be found anywhere
+fn screen_contains(emulator: &ACEmulator, region: (usize, usize, usize, usize), pattern: &[String]) -> Result<bool, String> {
+    let (x, y, width, height) = region;
+    let pattern: Vec<Vec<bool>> = pattern.iter().map(|row| row.chars().map(|c| c == '#').collect()).collect();
+    let pat_height = pattern.len();
+    let pat_width = pattern.iter().map(|row| row.len()).max().unwrap_or(0);
+    if pat_width == 0 {
+        return Err("pattern is empty".to_string());
+    }
+    let screen_width = ate_chip::SCREEN_WIDTH as usize;
+    let screen_height = ate_chip::SCREEN_HEIGHT as usize;
+    let right = (x + width).min(screen_width);
+    let bottom = (y + height).min(screen_height);
+    if pat_width > right.saturating_sub(x) || pat_height > bottom.saturating_sub(y) {
+        return Ok(false);
+    }
+
+    Ok((y..=bottom - pat_height).any(|py| {
+        (x..=right - pat_width).any(|px| {
+            pattern.iter().enumerate().all(|(row_n, row)| {
+                row.iter()
+                    .enumerate()
+                    .all(|(col, lit)| emulator.framebuffer().get_pixel(px + col, py + row_n) == *lit)
+            })
+        })
+    }))
+}
+
+/// `ate-chip scenario`, runs each scenario file and reports which ones passed
+pub fn scenario(args: ScenarioArgs) -> Result<(), ACEmError> {
+    let results: Vec<TestResult> = args
+        .files
+        .iter()
+        .map(|path| {
+            let outcome = ACScenario::load(path).and_then(|scenario| {
+                panic::catch_unwind(AssertUnwindSafe(|| scenario.run()))
+                    .unwrap_or_else(|e| Err(format!("emulator panicked: {}", panic_message(&*e))))
+            });
+            TestResult {
+                name: path.display().to_string(),
+                outcome: match outcome {
+                    Ok(()) => Outcome::Pass,
+                    Err(msg) => Outcome::Fail(msg),
+                },
+            }
+        })
+        .collect();
+
+    let report = report(&results, &args.format);
+    match &args.report {
+        Some(path) => fs::write(path, report)?,
+        None => print!("{}", report),
+    }
+
+    let failed = results.iter().filter(|r| matches!(r.outcome, Outcome::Fail(_))).count();
+    if failed != 0 {
+        return Err(format!("{} of {} scenarios failed", failed, results.len()).into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rows(pattern: &[&str]) -> Vec<String> {
+        pattern.iter().map(|row| row.to_string()).collect()
+    }
+
+    /// The font's `0` drawn at the top left, a ring 4 wide and 5 tall
+    fn zero() -> ACEmulator {
+        let mut emulator = ACEmulator::new();
+        emulator.exec_oper(0xD005, &ACKeyboard::new());
+        emulator
+    }
+
+    #[test]
+    fn patterns_are_found_anywhere_in_the_region() {
+        let emulator = zero();
+        let whole = (0, 0, 64, 32);
+        assert_eq!(screen_contains(&emulator, whole, &rows(&["####", "#..#", "#..#"])), Ok(true));
+        // the inside of the ring, found away from the top left
+        assert_eq!(screen_contains(&emulator, whole, &rows(&["#..#", "#..#", "####"])), Ok(true));
+        assert_eq!(screen_contains(&emulator, whole, &rows(&["#.#"])), Ok(false));
+        // only inside the region
+        assert_eq!(screen_contains(&emulator, (1, 0, 10, 10), &rows(&["####"])), Ok(false));
+        assert_eq!(screen_contains(&emulator, (0, 0, 3, 3), &rows(&["####"])), Ok(false));
+        // unlit pixels have to match too
+        assert_eq!(screen_contains(&emulator, whole, &rows(&["....."])), Ok(true));
+        assert_eq!(screen_contains(&ACEmulator::new(), whole, &rows(&["#"])), Ok(false));
+    }
+
+    #[test]
+    fn empty_patterns_are_rejected() {
+        let emulator = zero();
+        assert!(screen_contains(&emulator, (0, 0, 64, 32), &[]).is_err());
+        assert!(screen_contains(&emulator, (0, 0, 64, 32), &rows(&["", ""])).is_err());
+    }
+
+    #[test]
+    fn scenarios_parse() {
+        let scenario: ACScenario = toml::from_str(
+            r#"
+            rom = "pong.ch8"
+            steps = [{ wait = 60 }, { press = { key = 5 } }, { assert_sound = true }]
+            "#,
+        )
+        .unwrap();
+        assert_eq!((scenario.cycles_per_frame, scenario.quirks.as_str(), scenario.seed), (10, "default", 0));
+        assert!(matches!(scenario.steps[1], Step::Press { key: 5, frames: 1 }));
+
+        // misspelt steps and fields are errors, not ignored
+        assert!(toml::from_str::<ACScenario>(r#"rom = "a.ch8"
+steps = [{ wiat = 1 }]"#).is_err());
+        assert!(toml::from_str::<ACScenario>(r#"rom = "a.ch8"
+steps = [{ assert_reg = { reg = 1, valeu = 2 } }]"#).is_err());
+        assert!(toml::from_str::<ACScenario>(r#"steps = []"#).is_err());
+    }
+
+    #[test]
+    fn scenarios_choose_quirks_and_seed() {
+        let scenario: ACScenario = toml::from_str(
+            r#"
+            rom = "pong.ch8"
+            quirks = "schip"
+            seed = 7
+            steps = []
+            "#,
+        )
+        .unwrap();
+        assert_eq!((scenario.quirks.as_str(), scenario.seed), ("schip", 7));
+        assert!(toml::from_str::<ACScenario>(r#"rom = "a.ch8"
+seed = -1
+steps = []"#).is_err());
+    }
+
+    #[test]
+    fn scenarios_are_seeded() {
+        let dir = std::env::temp_dir().join(format!("ate-chip-scenario-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        // V0 = a random number, then stop
+        fs::write(dir.join("random.ch8"), [0xC0, 0xFF, 0x12, 0x02]).unwrap();
+        let mut emulator = ACEmulator::new();
+        emulator.seed(7);
+        emulator.load_rom([0xC0, 0xFF]);
+        emulator.step(&ACKeyboard::new(), None);
+        let scenario = |quirks: &str, value: u8| ACScenario {
+            rom: dir.join("random.ch8"),
+            cycles_per_frame: 10,
+            quirks: quirks.to_string(),
+            seed: 7,
+            steps: vec![Step::Wait(1), Step::AssertReg { reg: 0, value }],
+        };
+        let result = scenario("schip", emulator.reg(0)).run();
+        let unknown = scenario("chip9", emulator.reg(0)).run();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(result, Ok(()));
+        assert_eq!(unknown, Err("There is no quirks preset \"chip9\"".to_string()));
+    }
+}
@@ -0,0 +1,398 @@
+//! `--script <file.rhai>`, for auto-splitters, bots, training modes and cheats without recompiling.
+//!
+//! The script is run once when the game starts, and registers functions to be called as it plays.
+//! For example `on_pc(0x2A4, || print("level " + peek(0x300)));`. Scripts have these functions
+//!
+//! | function | |
+//! |---|---|
+//! | `on_frame(f)` | calls `f(frame)` after each frame |
+//! | `on_pc(address, f)` | calls `f()` just before the instruction at `address` runs |
+//! | `on_write(address, f)`, `on_write(f)` | calls `f(address, value)` after the program writes to `address`, or anywhere |
+//! | `on_key(f)` | calls `f(key, down)` when a key is pressed or released |
+//! | `reg(x)`, `set_reg(x, value)` | register `VX` |
+//! | `index()`, `pc()`, `delay_timer()`, `sound_timer()` | the other registers, each with a `set_` function too |
+//! | `peek(address)`, `poke(address, value)` | memory |
+//! | `press(key)`, `release(key)` | the keypad, from the next frame |
+//! | `pixel(x, y, on)`, `text(x, y, hex)`, `clear_overlay()` | draw over the screen, `text` in the font `FX29` uses |
+//! | `frame()` | frames run so far |
+//!
+//! `print` goes to the log.
+//!
+//! With any `on_pc` or `on_write` hooks, frames are run an instruction at a time here so the hooks can be called in
+//! between, rather than by the frontend being wrapped. That skips the JIT, and any frame logic the wrapped frontend
+//! has of its own
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::path::Path;
+use std::rc::Rc;
+
+use rhai::{Engine, EvalAltResult, FnPtr, FuncArgs, AST};
+
+use ate_chip::emulator::{ACEmulator, ACFramebuffer, SPRITE_CHARS};
+use ate_chip::frontend::{ACFrontend, ACInput, ACRenderer};
+use ate_chip::keyboard::{ACKey, ACKeyboard};
+
+use crate::ACEmError;
+
+type ACScriptResult<T> = Result<T, Box<EvalAltResult>>;
+
+/// What the script can see and change, shared with the functions it calls
+struct ACScriptState {
+    /// the running emulator while the script runs, null the rest of the time, see `ACScripted::with_emulator`
+    emulator: *mut ACEmulator,
+    /// drawn over the screen
+    overlay: ACFramebuffer,
+    /// key presses (`true`) and releases, applied before the next frame
+    keys: Vec<(ACKey, bool)>,
+    frame: u64,
+    frame_hooks: Vec<FnPtr>,
+    pc_hooks: HashMap<u16, Vec<FnPtr>>,
+    /// an address, or `None` for every write
+    write_hooks: Vec<(Option<u16>, FnPtr)>,
+    key_hooks: Vec<FnPtr>,
+}
+
+fn key(n: i64) -> ACScriptResult<ACKey> {
+    u8::try_from(n)
+        .ok()
+        .and_then(ACKey::from_hex)
+        .ok_or_else(|| format!("there is no key {}", n).into())
+}
+
+/// Runs `f` on the emulator the script is running against
+fn with<T>(state: &RefCell<ACScriptState>, f: impl FnOnce(&mut ACEmulator) -> T) -> ACScriptResult<T> {
+    let emulator = state.borrow().emulator;
+    if emulator.is_null() {
+        return Err("the emulator can only be used while the script is running".into());
+    }
+    // SAFETY: only set by `with_emulator`, from a `&mut ACEmulator` it holds and doesn't use until the pointer is
+    // cleared again, and the script runs on the thread that set it
+    Ok(f(unsafe { &mut *emulator }))
+}
+
+fn register(x: i64) -> ACScriptResult<usize> {
+    match x {
+        0..=15 => Ok(x as usize),
+        _ => Err(format!("there is no register V{}", x).into()),
+    }
+}
+
+/// Sets up the functions in the module docs
+fn engine(state: &Rc<RefCell<ACScriptState>>) -> Engine {
+    let mut engine = Engine::new();
+    engine.on_print(|s| log::info!("{}", s));
+    engine.on_debug(|s, _, pos| log::debug!("{} {}", pos, s));
+
+    let s = state.clone();
+    engine.register_fn("on_frame", move |f: FnPtr| s.borrow_mut().frame_hooks.push(f));
+    let s = state.clone();
+    engine.register_fn("on_pc", move |address: i64, f: FnPtr| {
+        s.borrow_mut().pc_hooks.entry(address as u16 & 0xFFF).or_default().push(f)
+    });
+    let s = state.clone();
+    engine.register_fn("on_write", move |address: i64, f: FnPtr| {
+        s.borrow_mut().write_hooks.push((Some(address as u16 & 0xFFF), f))
+    });
+    let s = state.clone();
+    engine.register_fn("on_write", move |f: FnPtr| s.borrow_mut().write_hooks.push((None, f)));
+    let s = state.clone();
+    engine.register_fn("on_key", move |f: FnPtr| s.borrow_mut().key_hooks.push(f));
+
+    let s = state.clone();
+    engine.register_fn("reg", move |x: i64| -> ACScriptResult<i64> {
+        let x = register(x)?;
+        with(&s, |e| e.reg(x) as i64)
+    });
+    let s = state.clone();
+    engine.register_fn("set_reg", move |x: i64, v: i64| -> ACScriptResult<()> {
+        let x = register(x)?;
+        with(&s, |e| e.set_reg(x, v as u8))
+    });
+    let s = state.clone();
+    engine.register_fn("index", move || with(&s, |e| e.index() as i64));
+    let s = state.clone();
+    engine.register_fn("set_index", move |v: i64| with(&s, |e| e.set_index(v as u16)));
+    let s = state.clone();
+    engine.register_fn("pc", move || with(&s, |e| e.pc() as i64));
+    let s = state.clone();
+    engine.register_fn("set_pc", move |v: i64| with(&s, |e| e.set_pc(v as u16)));
+    let s = state.clone();
+    engine.register_fn("delay_timer", move || with(&s, |e| e.delay_timer() as i64));
+    let s = state.clone();
+    engine.register_fn("set_delay_timer", move |v: i64| with(&s, |e| e.set_delay_timer(v as u8)));
+    let s = state.clone();
+    engine.register_fn("sound_timer", move || with(&s, |e| e.sound_timer() as i64));
+    let s = state.clone();
+    engine.register_fn("set_sound_timer", move |v: i64| with(&s, |e| e.set_sound_timer(v as u8)));
+    let s = state.clone();
+    engine.register_fn("peek", move |address: i64| with(&s, |e| e.memory()[address as usize & 0xFFF] as i64));
+    let s = state.clone();
+    engine.register_fn("poke", move |address: i64, v: i64| with(&s, |e| e.poke(address as u16, &[v as u8])));
+
+    let s = state.clone();
+    engine.register_fn("press", move |n: i64| -> ACScriptResult<()> {
+        s.borrow_mut().keys.push((key(n)?, true));
+        Ok(())
+    });
+    let s = state.clone();
+    engine.register_fn("release", move |n: i64| -> ACScriptResult<()> {
+        s.borrow_mut().keys.push((key(n)?, false));
+        Ok(())
+    });
+
+    let s = state.clone();
+    engine.register_fn("pixel", move |x: i64, y: i64, on: bool| {
+        s.borrow_mut().overlay.set_pixel(x.rem_euclid(64) as i8, y.rem_euclid(32) as i8, on)
+    });
+    let s = state.clone();
+    engine.register_fn("text", move |x: i64, y: i64, hex: &str| {
+        let mut state = s.borrow_mut();
+        for (n, c) in hex.chars().enumerate() {
+            // anything that isn't a hex digit is left as a gap
+            let Some(glyph) = c.to_digit(16).map(|d| SPRITE_CHARS[d as usize]) else { continue };
+            for (dy, row) in glyph.iter().enumerate() {
+                for dx in 0..4 {
+                    if row & (0x80 >> dx) != 0 {
+                        let (px, py) = (x + n as i64 * 5 + dx, y + dy as i64);
+                        state.overlay.set_pixel(px.rem_euclid(64) as i8, py.rem_euclid(32) as i8, true);
+                    }
+                }
+            }
+        }
+    });
+    let s = state.clone();
+    engine.register_fn("clear_overlay", move || s.borrow_mut().overlay.clear());
+    let s = state.clone();
+    engine.register_fn("frame", move || s.borrow().frame as i64);
+    engine
+}
+
+/// Wraps a frontend, calling into a script as the game plays
+pub struct ACScripted<'a, F: ACFrontend> {
+    inner: &'a mut F,
+    engine: Engine,
+    ast: AST,
+    state: Rc<RefCell<ACScriptState>>,
+    /// the keypad as of the last frame, to tell when keys change
+    held: [bool; 16],
+    /// keys that changed, waiting for the emulator to be around to tell the script
+    key_events: Vec<(ACKey, bool)>,
+}
+
+impl<'a, F: ACFrontend> ACScripted<'a, F> {
+    /// Loads the script at `path` and runs it against `emulator`, ready to play in `frontend`
+    pub fn load(path: &Path, emulator: &mut ACEmulator, frontend: &'a mut F) -> Result<Self, ACEmError> {
+        let source = std::fs::read_to_string(path)?;
+        Ok(Self::new(&source, emulator, frontend).map_err(|e| format!("{}: {}", path.display(), e))?)
+    }
+
+    /// Compiles `source` and runs it against `emulator`
+    fn new(source: &str, emulator: &mut ACEmulator, frontend: &'a mut F) -> Result<Self, String> {
+        let state = Rc::new(RefCell::new(ACScriptState {
+            emulator: std::ptr::null_mut(),
+            overlay: ACFramebuffer::new(),
+            keys: vec![],
+            frame: 0,
+            frame_hooks: vec![],
+            pc_hooks: HashMap::new(),
+            write_hooks: vec![],
+            key_hooks: vec![],
+        }));
+        let engine = engine(&state);
+        let ast = engine.compile(source).map_err(|e| e.to_string())?;
+        let scripted = Self {
+            inner: frontend,
+            engine,
+            ast,
+            state,
+            held: [false; 16],
+            key_events: vec![],
+        };
+        scripted.with_emulator(emulator, |engine, ast| engine.run_ast(ast))?;
+        Ok(scripted)
+    }
+
+    /// Runs `f` with the script able to see `emulator`
+    fn with_emulator(&self, emulator: &mut ACEmulator, f: impl FnOnce(&Engine, &AST) -> ACScriptResult<()>) -> Result<(), String> {
+        /// Clears the pointer again however `f` finishes, panics included
+        struct ACLent<'s>(&'s RefCell<ACScriptState>);
+        impl Drop for ACLent<'_> {
+            fn drop(&mut self) {
+                self.0.borrow_mut().emulator = std::ptr::null_mut();
+            }
+        }
+
+        self.state.borrow_mut().emulator = emulator;
+        let _lent = ACLent(&self.state);
+        f(&self.engine, &self.ast).map_err(|e| format!("script error: {}", e))
+    }
+
+    /// Calls each of `hooks` with `args`
+    fn call(&self, emulator: &mut ACEmulator, hooks: Vec<FnPtr>, args: impl FuncArgs + Clone) -> Result<(), String> {
+        for hook in hooks {
+            self.with_emulator(emulator, |engine, ast| hook.call(engine, ast, args.clone()))?;
+        }
+        Ok(())
+    }
+}
+
+impl<F: ACFrontend> ACRenderer for ACScripted<'_, F> {
+    fn render(&mut self, framebuffer: &ACFramebuffer, tone: bool) -> Result<(), String> {
+        let state = self.state.borrow();
+        let overlay = &state.overlay;
+        if *overlay == ACFramebuffer::new() {
+            return self.inner.render(framebuffer, tone);
+        }
+        let mut framebuffer = framebuffer.clone();
+        for y in 0..32 {
+            for x in 0..64 {
+                if overlay.get_pixel(x, y) {
+                    framebuffer.set_pixel(x as i8, y as i8, true);
+                }
+            }
+        }
+        self.inner.render(&framebuffer, tone)
+    }
+}
+
+impl<F: ACFrontend> ACFrontend for ACScripted<'_, F> {
+    fn poll_input(&mut self, keyboard: &mut ACKeyboard) -> Result<ACInput, String> {
+        let mut input = self.inner.poll_input(keyboard)?;
+        let keys = std::mem::take(&mut self.state.borrow_mut().keys);
+        for (key, down) in keys {
+            if !down {
+                keyboard.release(key);
+                continue;
+            }
+            if let ACInput::Continue(new_keypress) = &mut input {
+                if !keyboard.is_pressed(&key) {
+                    *new_keypress = new_keypress.or(Some(key));
+                }
+            }
+            keyboard.press(key);
+        }
+        for (n, held) in self.held.iter_mut().enumerate() {
+            let key = ACKey::from_hex(n as u8).expect("0-15 are all keys");
+            if keyboard.is_pressed(&key) != *held {
+                *held = !*held;
+                self.key_events.push((key, *held));
+            }
+        }
+        Ok(input)
+    }
+
+    fn realtime(&self) -> bool {
+        self.inner.realtime()
+    }
+
+    fn run_frame(&mut self, emulator: &mut ACEmulator, keyboard: &ACKeyboard, mut new_keypress: Option<ACKey>, cycles: usize) -> Result<(), String> {
+        for (key, down) in std::mem::take(&mut self.key_events) {
+            let hooks = self.state.borrow().key_hooks.clone();
+            self.call(emulator, hooks, (key.to_hex() as i64, down))?;
+        }
+
+        let (watch_pc, watch_writes) = {
+            let state = self.state.borrow();
+            (!state.pc_hooks.is_empty(), !state.write_hooks.is_empty())
+        };
+        if !watch_pc && !watch_writes {
+            self.inner.run_frame(emulator, keyboard, new_keypress, cycles)?;
+        } else {
+            // not `self.inner.run_frame`, see the module docs
+            // the memory view may already be looking at writes, in which case they're left for it
+            let own_log = watch_writes && emulator.write_log.is_none();
+            if own_log {
+                emulator.write_log = Some(vec![]);
+            }
+            // `ACEmulator::run_frame` an instruction at a time
+            for _ in 0..cycles {
+                if watch_pc && !emulator.waiting_for_key() && emulator.fault().is_none() {
+                    let hooks = self.state.borrow().pc_hooks.get(&emulator.pc()).cloned().unwrap_or_default();
+                    self.call(emulator, hooks, ())?;
+                }
+                let written = emulator.write_log.as_ref().map_or(0, Vec::len);
+                let waiting = emulator.waiting_for_key();
+                emulator.step(keyboard, new_keypress);
+                // a key press can only be used by one `FX0A`
+                if waiting {
+                    new_keypress = None;
+                }
+                let writes = emulator.write_log.as_ref().map_or(vec![], |log| log[written..].to_vec());
+                for address in writes {
+                    let hooks = self.state.borrow().write_hooks.iter()
+                        .filter(|(a, _)| a.is_none_or(|a| a == address))
+                        .map(|(_, hook)| hook.clone())
+                        .collect();
+                    let value = emulator.memory()[address as usize];
+                    self.call(emulator, hooks, (address as i64, value as i64))?;
+                }
+            }
+            emulator.tick_timers();
+            if own_log {
+                emulator.write_log = None;
+            }
+        }
+
+        let (hooks, frame) = {
+            let mut state = self.state.borrow_mut();
+            state.frame += 1;
+            (state.frame_hooks.clone(), state.frame)
+        };
+        self.call(emulator, hooks, (frame as i64,))
+    }
+
+    fn inspect(&mut self, emulator: &mut ACEmulator) -> Result<(), String> {
+        self.inner.inspect(emulator)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ate_chip::frontend::{self, ACHeadless, ACNullRenderer};
+
+    /// Runs `program` for `frames` frames of 10 instructions with `script` hooked in
+    fn run(script: &str, program: &[u16], frames: usize) -> Result<ACEmulator, String> {
+        let mut emulator = ACEmulator::new();
+        emulator.load_rom(program.iter().flat_map(|instr| instr.to_be_bytes()).collect::<Vec<u8>>());
+        let mut headless = ACHeadless { renderer: ACNullRenderer, frames };
+        let mut scripted = ACScripted::new(script, &mut emulator, &mut headless)?;
+        frontend::run(&mut emulator, &mut scripted, 10)?;
+        Ok(emulator)
+    }
+
+    #[test]
+    fn hooks_fire() {
+        // stores V0 at 0x300 and adds 1 to it, forever
+        let program = [0x6005, 0xA300, 0xF055, 0x7001, 0x1202];
+        // each hook leaves what it saw at 0xF00 on, where the program won't touch it
+        let script = r#"
+            on_frame(|n| { poke(0xF00, n); if n == 1 { press(7); } });
+            on_pc(0x202, || poke(0xF01, peek(0xF01) + 1));
+            on_write(0x300, |address, value| poke(0xF02, value));
+            on_write(|address, value| poke(0xF03, address - 0x300));
+            on_key(|key, down| if down { poke(0xF04, key) });
+            set_reg(1, 0x42);
+        "#;
+        let emulator = run(script, &program, 5).unwrap();
+        let memory = emulator.memory();
+        assert_eq!(memory[0xF00], 5);
+        // once on the way in and once a loop, the loop being 4 instructions
+        assert_eq!(memory[0xF01], 13);
+        assert_eq!(memory[0xF02], memory[0x300]);
+        // the 12th store, of 5 + 11
+        assert_eq!(memory[0xF02], 16);
+        assert_eq!(memory[0xF03], 0);
+        assert_eq!(memory[0xF04], 7);
+        // the script runs against the emulator it is given
+        assert_eq!(emulator.reg(1), 0x42);
+    }
+
+    #[test]
+    fn errors_are_reported() {
+        let Err(err) = run("on_frame(", &[0x1200], 1) else { panic!("ran a broken script") };
+        assert!(err.contains("line 1"), "{}", err);
+        let Err(err) = run("on_frame(|n| reg(16));", &[0x1200], 1) else { panic!("V16 exists") };
+        assert!(err.contains("no register V16"), "{}", err);
+    }
+}
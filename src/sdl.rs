@@ -0,0 +1,229 @@
+use sdl2::audio::{AudioCallback, AudioSpecDesired};
+use sdl2::event::{Event, WindowEvent};
+use sdl2::keyboard::Keycode;
+use sdl2::pixels::{Color, PixelFormatEnum};
+use sdl2::audio::AudioDevice;
+use sdl2::render::{Canvas, Texture};
+use sdl2::video::Window;
+use sdl2::EventPump;
+
+use ate_chip::{SCREEN_HEIGHT, SCREEN_WIDTH};
+use ate_chip::emulator::{ACEmulator, ACFramebuffer};
+use ate_chip::frontend::{ACFrontend, ACInput, ACRenderer};
+use ate_chip::keyboard::{ACKeyboard, ACKey};
+
+use crate::memview::{self, ACMemView};
+use crate::settings::ACSettings;
+use crate::{ACEmError, RunArgs};
+
+const MEMVIEW_SCALE: u32 = 3;
+
+const SETTINGS: ACSettings = ACSettings {
+    audio: AudioSpecDesired {
+        freq: Some(44100),
+        channels: Some(1), // mono
+        samples: None,     // default sample size
+    }
+};
+
+struct SquareWave {
+    phase_inc: f32,
+    phase: f32,
+    volume: f32,
+}
+
+impl AudioCallback for SquareWave {
+    type Channel = f32;
+
+    fn callback(&mut self, out: &mut [f32]) {
+        // minecraft ocean
+        for x in out.iter_mut() {
+            *x = if self.phase <= 0.5 {
+                self.volume
+            } else {
+                -self.volume
+            };
+            self.phase = (self.phase + self.phase_inc) % 1.0;
+        }
+    }
+}
+
+/// Copies the screen to a `SCREEN_WIDTH` x `SCREEN_HEIGHT` RGB24 texture
+fn render_to_tex(framebuffer: &ACFramebuffer, texture: &mut Texture) {
+    texture.with_lock(None, |buffer: &mut [u8], pitch/* size of a row in bytes */: usize| {
+        for (line, row) in buffer.chunks_mut(pitch).zip(framebuffer.rows()) {
+            for (x, px) in line[..SCREEN_WIDTH as usize * 3].chunks_exact_mut(3).enumerate() {
+                px.fill(if row << x >> 63 != 0 { 255 } else { 0 });
+            }
+        }
+    }).expect("Rendered the current frame");
+}
+
+/// The keypad key for an SDL key, laid out the same as on a qwerty keyboard
+fn keypad_key(key: Keycode) -> Option<ACKey> {
+    let name = key.name();
+    let mut chars = name.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) => ACKey::from_qwerty(c),
+        _ => None,
+    }
+}
+
+/// A window with sound, and optionally a second window showing memory
+struct ACSdlFrontend<'a> {
+    canvas: Canvas<Window>,
+    texture: Texture<'a>,
+    noise_player: AudioDevice<SquareWave>,
+    event_pump: EventPump,
+    memview: Option<(ACMemView, Canvas<Window>, Texture<'a>)>,
+    /// the memory view was closed since the last frame
+    memview_closed: bool,
+}
+
+impl ACRenderer for ACSdlFrontend<'_> {
+    fn render(&mut self, framebuffer: &ACFramebuffer, tone: bool) -> Result<(), String> {
+        render_to_tex(framebuffer, &mut self.texture);
+        if tone {
+            self.noise_player.resume();
+        } else {
+            self.noise_player.pause();
+        }
+
+        self.canvas.clear();
+        self.canvas.copy(&self.texture, None, None)?;
+        self.canvas.present();
+        Ok(())
+    }
+}
+
+impl ACFrontend for ACSdlFrontend<'_> {
+    fn poll_input(&mut self, keyboard: &mut ACKeyboard) -> Result<ACInput, String> {
+        let mut key_pressed = None;
+        for event in self.event_pump.poll_iter() {
+            match event {
+                Event::Quit {..} => return Ok(ACInput::Quit),
+                Event::Window { window_id, win_event: WindowEvent::Close, .. } => {
+                    // closing the memory view only closes the memory view
+                    if self.memview.as_ref().map(|(_, canvas, _)| canvas.window().id()) == Some(window_id) {
+                        self.memview = None;
+                        self.memview_closed = true;
+                    } else {
+                        return Ok(ACInput::Quit);
+                    }
+                }
+                Event::MouseButtonDown { window_id, x, y, .. } => {
+                    if let Some((view, canvas, _)) = &mut self.memview {
+                        if canvas.window().id() == window_id {
+                            view.click(x as usize / MEMVIEW_SCALE as usize, y as usize / MEMVIEW_SCALE as usize);
+                        }
+                    }
+                }
+                Event::KeyDown { keycode: Some(key), repeat: false, .. } => {
+                    if let Some(key) = keypad_key(key) {
+                        keyboard.press(key);
+                        key_pressed = key_pressed.or(Some(key));
+                    }
+                }
+                Event::KeyUp { keycode: Some(key), .. } => {
+                    if let Some(key) = keypad_key(key) {
+                        keyboard.release(key);
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok(ACInput::Continue(key_pressed))
+    }
+
+    fn inspect(&mut self, emulator: &mut ACEmulator) -> Result<(), String> {
+        if self.memview_closed {
+            emulator.write_log = None;
+            self.memview_closed = false;
+        }
+        if let Some((view, canvas, texture)) = &mut self.memview {
+            view.update(emulator);
+            view.render_to_tex(emulator, texture);
+            canvas.copy(texture, None, None)?;
+            canvas.present();
+        }
+        Ok(())
+    }
+}
+
+/// Plays `emulator` in a window until it is closed
+pub fn run(args: &RunArgs, emulator: &mut ACEmulator) -> Result<(), ACEmError> {
+    let sdl_context = sdl2::init()?;
+    let video_subsystem = sdl_context.video()?;
+    let audio_subsystem = sdl_context.audio()?;
+
+    // for beep thing
+    let noise_player = audio_subsystem.open_playback(None, &SETTINGS.audio, |spec| {
+        // initialize the audio callback
+        SquareWave {
+            phase_inc: 440.0 / spec.freq as f32,
+            phase: 0.0,
+            volume: 0.25,
+        }
+    })?;
+
+    let window = video_subsystem
+        .window(
+            "Ate-Chip",
+            SCREEN_WIDTH as u32 * args.scale,
+            SCREEN_HEIGHT as u32 * args.scale,
+        )
+        .position_centered()
+        .opengl()
+        .build()
+        .map_err(|e| e.to_string())?;
+
+    let mut canvas = window.into_canvas().build().map_err(|e| e.to_string())?;
+    canvas.set_draw_color(Color::RGB(0, 0, 0));
+    canvas.clear();
+    canvas.present();
+
+    let texture_creator = canvas.texture_creator();
+    let texture = texture_creator
+        .create_texture_streaming(
+            PixelFormatEnum::RGB24,
+            SCREEN_WIDTH as u32,
+            SCREEN_HEIGHT as u32,
+        )
+        .map_err(|e| e.to_string())?;
+
+    // memory visualiser
+    let memview_canvas = if args.memview {
+        let window = video_subsystem
+            .window(
+                "Ate-Chip memory",
+                memview::WIDTH as u32 * MEMVIEW_SCALE,
+                memview::HEIGHT as u32 * MEMVIEW_SCALE,
+            )
+            .build()
+            .map_err(|e| e.to_string())?;
+        emulator.write_log = Some(Vec::new());
+        Some(window.into_canvas().build().map_err(|e| e.to_string())?)
+    } else {
+        None
+    };
+    let memview_texture_creator = memview_canvas.as_ref().map(|canvas| canvas.texture_creator());
+    let memview = match (memview_canvas, &memview_texture_creator) {
+        (Some(canvas), Some(creator)) => {
+            let texture = creator
+                .create_texture_streaming(PixelFormatEnum::RGB24, memview::WIDTH as u32, memview::HEIGHT as u32)
+                .map_err(|e| e.to_string())?;
+            Some((ACMemView::new(), canvas, texture))
+        }
+        _ => None,
+    };
+
+    let mut frontend = ACSdlFrontend {
+        canvas,
+        texture,
+        noise_player,
+        event_pump: sdl_context.event_pump()?,
+        memview,
+        memview_closed: false,
+    };
+    crate::run_frontend(args, emulator, &mut frontend)
+}
@@ -0,0 +1,210 @@
+use std::collections::BTreeSet;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+
+use crate::emulator::SPRITE_CHARS;
+
+/// A sprite that a rom draws, `height` bytes starting at `addr`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ACSpriteRef {
+    pub addr: u16,
+    pub height: u8,
+}
+
+/// Statically finds sprites by looking for `ANNN` followed by a `DXYN` that uses it.
+///
+/// `rom` is assumed to be loaded at `base`. This is a best guess, anything that changes
+/// `I` or jumps between the two instructions stops the search
+pub fn find_sprites(rom: &[u8], base: u16) -> BTreeSet<ACSpriteRef> {
+    let mut found = BTreeSet::new();
+    // try both alignments, since data in the middle of the rom can shift the code over by a byte
+    for start in 0..2 {
+        let mut index: Option<u16> = None;
+        for word in rom[start.min(rom.len())..].chunks_exact(2) {
+            let instr = (word[0] as u16) << 8 | word[1] as u16;
+            match instr & 0xF000 {
+                0xA000 => index = Some(instr & 0x0FFF),
+                0xD000 => {
+                    if let Some(addr) = index {
+                        let height = (instr & 0x000F) as u8;
+                        if height != 0 && addr >= base {
+                            found.insert(ACSpriteRef { addr, height });
+                        }
+                    }
+                }
+                // control flow, the next instruction might not run after this one
+                0x0000 | 0x1000 | 0x2000 | 0xB000 => index = None,
+                // anything that changes I
+                0xF000 if matches!(instr & 0x00FF, 0x1E | 0x29 | 0x65) => index = None,
+                _ => (),
+            }
+        }
+    }
+    found
+}
+
+/// Renders the `N` bytes at `addr` as a sprite, one row of pixels per byte
+pub fn sprite_rows(memory: &[u8; 4096], sprite: ACSpriteRef) -> Vec<[bool; 8]> {
+    (0..sprite.height as usize)
+        .map(|row| {
+            let byte = memory[(sprite.addr as usize + row) & 0xFFF];
+            let mut px = [false; 8];
+            for (bit, p) in px.iter_mut().enumerate() {
+                *p = byte & (0x80 >> bit) != 0;
+            }
+            px
+        })
+        .collect()
+}
+
+/// Size of each sprite when drawn on the sheet
+const SHEET_SCALE: usize = 4;
+/// Cells are big enough for the largest sprite (8x15) and a label of 3 hex digits and 1 for the height
+const CELL_WIDTH: usize = 8 * SHEET_SCALE + 8;
+const CELL_HEIGHT: usize = 6 + 15 * SHEET_SCALE + 8;
+const SHEET_COLUMNS: usize = 8;
+
+/// A greyscale sprite sheet containing every sprite in `sprites`, each labeled with its
+/// address and height in hex. Returns (width, height, pixels)
+pub fn sprite_sheet(memory: &[u8; 4096], sprites: &BTreeSet<ACSpriteRef>) -> (usize, usize, Vec<u8>) {
+    let columns = SHEET_COLUMNS.min(sprites.len()).max(1);
+    let rows = sprites.len().div_ceil(columns);
+    let (width, height) = (columns * CELL_WIDTH, rows.max(1) * CELL_HEIGHT);
+    let mut pixels = vec![0u8; width * height];
+
+    for (n, sprite) in sprites.iter().enumerate() {
+        let cell_x = (n % columns) * CELL_WIDTH + 4;
+        let cell_y = (n / columns) * CELL_HEIGHT + 4;
+
+        // label, using the chip-8 font
+        let digits = [(sprite.addr >> 8) as u8 & 0xF, (sprite.addr >> 4) as u8 & 0xF, sprite.addr as u8 & 0xF];
+        let label = digits.iter().chain(std::iter::once(&sprite.height)).enumerate();
+        for (d, digit) in label {
+            // leave a gap between the address and the height
+            let x = cell_x + d * 5 + if d == 3 { 3 } else { 0 };
+            for (gy, glyph_row) in SPRITE_CHARS[*digit as usize & 0xF].iter().enumerate() {
+                for gx in 0..4 {
+                    if glyph_row & (0x80 >> gx) != 0 {
+                        pixels[(cell_y + gy) * width + x + gx] = 0x80;
+                    }
+                }
+            }
+        }
+
+        for (row, px) in sprite_rows(memory, *sprite).iter().enumerate() {
+            for (col, on) in px.iter().enumerate() {
+                if *on {
+                    for sy in 0..SHEET_SCALE {
+                        let y = cell_y + 6 + row * SHEET_SCALE + sy;
+                        let x = cell_x + col * SHEET_SCALE;
+                        pixels[y * width + x..y * width + x + SHEET_SCALE].fill(0xFF);
+                    }
+                }
+            }
+        }
+    }
+
+    (width, height, pixels)
+}
+
+/// Writes a sprite sheet of `sprites` to `path` as a png
+pub fn write_sprite_sheet(path: &Path, memory: &[u8; 4096], sprites: &BTreeSet<ACSpriteRef>) -> Result<(), String> {
+    let (width, height, pixels) = sprite_sheet(memory, sprites);
+    let file = File::create(path).map_err(|e| e.to_string())?;
+    let mut encoder = png::Encoder::new(BufWriter::new(file), width as u32, height as u32);
+    encoder.set_color(png::ColorType::Grayscale);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header().map_err(|e| e.to_string())?;
+    writer.write_image_data(&pixels).map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sprite(addr: u16, height: u8) -> ACSpriteRef {
+        ACSpriteRef { addr, height }
+    }
+
+    #[test]
+    fn sprites_are_found_at_both_alignments() {
+        // LD I, 300; DRW V0, V1, 5
+        let rom = [0xA3, 0x00, 0xD0, 0x15];
+        assert_eq!(find_sprites(&rom, 0x200), BTreeSet::from([sprite(0x300, 5)]));
+
+        // a byte of data shifts the rest of the code over by one
+        let rom = [0xFF, 0xA3, 0x10, 0x60, 0x01, 0xD0, 0x18];
+        assert_eq!(find_sprites(&rom, 0x200), BTreeSet::from([sprite(0x310, 8)]));
+
+        // I stays set for later draws, and each is found once
+        let rom = [0xA3, 0x00, 0xD0, 0x15, 0x70, 0x08, 0xD0, 0x15, 0xD0, 0x13];
+        assert_eq!(find_sprites(&rom, 0x200), BTreeSet::from([sprite(0x300, 5), sprite(0x300, 3)]));
+    }
+
+    #[test]
+    fn the_search_stops_at_control_flow_and_changes_to_i() {
+        for between in [0x1208u16, 0x2208, 0xB208, 0x00EE, 0xF01E, 0xF029, 0xF065] {
+            let [hi, lo] = between.to_be_bytes();
+            let rom = [0xA3, 0x00, hi, lo, 0xD0, 0x15];
+            assert!(find_sprites(&rom, 0x200).is_empty(), "{:04X}", between);
+        }
+        // FX55 and FX33 read I without changing it
+        for between in [0xF055u16, 0xF033] {
+            let [hi, lo] = between.to_be_bytes();
+            let rom = [0xA3, 0x00, hi, lo, 0xD0, 0x15];
+            assert_eq!(find_sprites(&rom, 0x200), BTreeSet::from([sprite(0x300, 5)]), "{:04X}", between);
+        }
+    }
+
+    #[test]
+    fn large_and_builtin_sprites_are_ignored() {
+        // DXY0 is a 16x16 schip sprite, and 0x050 is in the font
+        let rom = [0xA3, 0x00, 0xD0, 0x10, 0xA0, 0x50, 0xD0, 0x15, 0xA1, 0xFF, 0xD0, 0x15, 0xA2, 0x00, 0xD0, 0x15];
+        assert_eq!(find_sprites(&rom, 0x200), BTreeSet::from([sprite(0x200, 5)]));
+        assert!(find_sprites(&[0xA3], 0x200).is_empty());
+        assert!(find_sprites(&[], 0x200).is_empty());
+    }
+
+    #[test]
+    fn sprite_rows_wrap_around_memory() {
+        let mut memory = [0u8; 4096];
+        memory[0xFFE] = 0x80;
+        memory[0xFFF] = 0x01;
+        memory[0x000] = 0xF0;
+        let rows = sprite_rows(&memory, sprite(0xFFE, 3));
+        assert_eq!(
+            rows,
+            vec![
+                [true, false, false, false, false, false, false, false],
+                [false, false, false, false, false, false, false, true],
+                [true, true, true, true, false, false, false, false],
+            ]
+        );
+    }
+
+    #[test]
+    fn sheets_fit_every_sprite() {
+        let mut memory = [0u8; 4096];
+        memory[0x300..0x30F].fill(0xFF);
+        let sheet = |count: u16| {
+            let sprites = (0..count).map(|n| sprite(0x300, n as u8 + 1)).collect();
+            sprite_sheet(&memory, &sprites)
+        };
+
+        let (width, height, pixels) = sheet(1);
+        assert_eq!((width, height), (CELL_WIDTH, CELL_HEIGHT));
+        assert_eq!(pixels.len(), width * height);
+        // the top left pixel of the sprite, below the label
+        assert_eq!(pixels[(4 + 6) * width + 4], 0xFF);
+        assert!(pixels.contains(&0x80));
+
+        let (width, height, _) = sheet(8);
+        assert_eq!((width, height), (8 * CELL_WIDTH, CELL_HEIGHT));
+        let (width, height, pixels) = sheet(9);
+        assert_eq!((width, height), (8 * CELL_WIDTH, 2 * CELL_HEIGHT));
+        // the ninth sprite starts the second row
+        assert_eq!(pixels[(CELL_HEIGHT + 4 + 6) * width + 4], 0xFF);
+        assert_eq!(pixels[(CELL_HEIGHT + 4 + 6) * width + CELL_WIDTH + 4], 0);
+    }
+}
@@ -0,0 +1,459 @@
+//! `ate-chip serve-telnet`, plays a rom for a room full of terminals.
+//!
+//! Whoever connected first has the keypad and everyone else watches, each connection gets the screen drawn
+//! by the terminal frontend with a chat under it. Works with `telnet`, and with `nc` too
+//! (`stty -icanon -echo; nc host 2323` to send keys without pressing enter)
+use std::collections::VecDeque;
+use std::fs;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread;
+use std::time::Instant;
+
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use crossterm::style::Print;
+use crossterm::{cursor, queue, terminal};
+
+use ate_chip::emulator::{ACEmulator, ACFramebuffer};
+use ate_chip::frontend::{self, ACFrontend, ACInput, ACRenderer};
+use ate_chip::keyboard::ACKeyboard;
+use ate_chip::quirks::ACQuirks;
+use ate_chip::textmode::ACTextMode;
+
+use crate::terminal::{ACTerminalKeys, ACTerminalScreen};
+use crate::{ACEmError, TextMode, QUIRK_PRESETS};
+
+/// how many chat messages are shown
+const CHAT_LINES: usize = 5;
+
+/// telnet commands, see https://datatracker.ietf.org/doc/html/rfc854
+const IAC: u8 = 255;
+const WILL: u8 = 251;
+const SB: u8 = 250;
+const SE: u8 = 240;
+/// options, the server echoing and not waiting for go aheads is what puts a telnet client into character mode
+const ECHO: u8 = 1;
+const SUPPRESS_GO_AHEAD: u8 = 3;
+
+#[derive(clap::Args, Debug)]
+pub struct TelnetArgs {
+    #[clap(help = "path to the rom file")]
+    rom: PathBuf,
+    #[clap(long, default_value_t = 2323, help = "port to listen on")]
+    port: u16,
+    #[clap(long, default_value = "0.0.0.0", help = "address to listen on, 127.0.0.1 to only allow this machine")]
+    bind: String,
+    #[clap(long, arg_enum, default_value = "half-blocks", help = "how the screen is drawn")]
+    text_mode: TextMode,
+    #[clap(long, default_value_t = 10, help = "instructions run per frame, there are 60 frames a second")]
+    cycles_per_frame: usize,
+    #[clap(long, default_value = "default", possible_values = QUIRK_PRESETS, help = "which interpreter to behave like")]
+    quirks: String,
+}
+
+/// Someone connected
+#[derive(Debug)]
+struct ACTelnetClient {
+    id: usize,
+    /// the chat message being typed
+    composing: String,
+    /// whether the player is typing a chat message rather than pressing keys
+    chatting: bool,
+}
+
+impl ACTelnetClient {
+    fn name(&self) -> String {
+        format!("player {}", self.id)
+    }
+}
+
+/// What the emulator and the connections share
+struct ACTelnetState {
+    framebuffer: ACFramebuffer,
+    tone: bool,
+    /// counts up each time anything the clients show changes
+    generation: u64,
+    /// everyone connected, oldest first, the first has the keypad
+    clients: Vec<ACTelnetClient>,
+    next_id: usize,
+    /// the last `CHAT_LINES` messages
+    chat: VecDeque<String>,
+    /// keys typed by whoever has the keypad, that the emulator hasn't seen yet
+    keys: Vec<char>,
+}
+
+impl ACTelnetState {
+    fn new(framebuffer: ACFramebuffer) -> Self {
+        Self {
+            framebuffer,
+            tone: false,
+            generation: 0,
+            clients: vec![],
+            next_id: 1,
+            chat: VecDeque::new(),
+            keys: vec![],
+        }
+    }
+
+    fn say(&mut self, message: String) {
+        log::info!("{}", message);
+        self.chat.push_back(message);
+        while self.chat.len() > CHAT_LINES {
+            self.chat.pop_front();
+        }
+    }
+
+    /// Adds a client, who gets the keypad if nobody else is connected, returning their id
+    fn join(&mut self) -> usize {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.clients.push(ACTelnetClient {
+            id,
+            composing: String::new(),
+            chatting: false,
+        });
+        self.say(format!("player {} joined", id));
+        id
+    }
+
+    /// Removes client `id`, handing the keypad to whoever has been connected longest if they had it
+    fn leave(&mut self, id: usize) {
+        let had_keypad = self.clients.first().map(|c| c.id) == Some(id);
+        self.clients.retain(|c| c.id != id);
+        self.say(format!("player {} left", id));
+        if had_keypad {
+            if let Some(message) = self.clients.first().map(|c| format!("{} has the keypad", c.name())) {
+                self.say(message);
+            }
+        }
+    }
+
+    /// Handles a byte typed by client `id`, returning `false` if they asked to leave
+    fn typed(&mut self, id: usize, byte: u8) -> bool {
+        let Some(position) = self.clients.iter().position(|c| c.id == id) else { return false };
+        let client = &mut self.clients[position];
+        match byte {
+            // ctrl-c and ctrl-d, when the terminal is in character mode
+            0x03 | 0x04 => return false,
+            b'\r' | b'\n' => {
+                client.chatting = false;
+                if !client.composing.trim().is_empty() {
+                    let message = format!("{}: {}", client.name(), client.composing.trim());
+                    client.composing.clear();
+                    self.say(message);
+                }
+            }
+            // backspace, or delete which most terminals send for it
+            0x08 | 0x7F => {
+                client.composing.pop();
+            }
+            b'/' if position == 0 && !client.chatting => client.chatting = true,
+            _ if position == 0 && !client.chatting => self.keys.push(byte as char),
+            b' '..=b'~' => client.composing.push(byte as char),
+            _ => {}
+        }
+        true
+    }
+
+    /// The lines under the screen for client `id`
+    fn status(&self, id: usize) -> Vec<String> {
+        let watching = self.clients.len().saturating_sub(1);
+        let mut lines = vec![match self.clients.first() {
+            Some(player) => format!("{} has the keypad, {} watching", player.name(), watching),
+            None => String::new(),
+        }];
+        lines.extend(self.chat.iter().cloned());
+        lines.resize(CHAT_LINES + 1, String::new());
+        if let Some(client) = self.clients.iter().find(|c| c.id == id) {
+            if client.chatting || !client.composing.is_empty() {
+                lines.push(format!("> {}", client.composing));
+            } else {
+                lines.push(String::new());
+            }
+        }
+        lines
+    }
+}
+
+struct ACTelnetServer {
+    state: Mutex<ACTelnetState>,
+    /// notified whenever `generation` goes up
+    changed: Condvar,
+}
+
+impl ACTelnetServer {
+    fn lock(&self) -> MutexGuard<'_, ACTelnetState> {
+        self.state.lock().expect("no connection panics while holding the lock")
+    }
+
+    /// Tells every connection that something changed
+    fn changed(&self, state: &mut ACTelnetState) {
+        state.generation += 1;
+        self.changed.notify_all();
+    }
+}
+
+/// The frontend side of the server, run by `frontend::run` like any other frontend
+struct ACTelnetFrontend {
+    server: Arc<ACTelnetServer>,
+    /// telnet only says when a key is typed, so keys are held the same way as in a terminal
+    keys: ACTerminalKeys,
+}
+
+impl ACRenderer for ACTelnetFrontend {
+    fn render(&mut self, framebuffer: &ACFramebuffer, tone: bool) -> Result<(), String> {
+        let mut state = self.server.lock();
+        if state.framebuffer != *framebuffer || state.tone != tone {
+            state.framebuffer = framebuffer.clone();
+            state.tone = tone;
+            self.server.changed(&mut state);
+        }
+        Ok(())
+    }
+}
+
+impl ACFrontend for ACTelnetFrontend {
+    fn poll_input(&mut self, keyboard: &mut ACKeyboard) -> Result<ACInput, String> {
+        let mut new_keypress = None;
+        let typed: Vec<char> = self.server.lock().keys.drain(..).collect();
+        for c in typed {
+            if let Some(key) = self.keys.handle(keyboard, &KeyEvent::new(KeyCode::Char(c), KeyModifiers::NONE)) {
+                new_keypress = new_keypress.or(Some(key));
+            }
+        }
+        self.keys.expire(keyboard, Instant::now());
+        Ok(ACInput::Continue(new_keypress))
+    }
+}
+
+/// Reads what client `id` types until they disconnect, skipping over telnet negotiation
+fn read_client(stream: impl Read, server: &ACTelnetServer, id: usize) -> io::Result<()> {
+    /// where we are in a telnet command
+    enum Telnet {
+        Data,
+        Command,
+        /// the option after WILL, WONT, DO or DONT
+        Option,
+        Subnegotiation,
+        SubnegotiationCommand,
+    }
+    let mut telnet = Telnet::Data;
+    for byte in BufReader::new(stream).bytes() {
+        let byte = byte?;
+        telnet = match (telnet, byte) {
+            (Telnet::Data, IAC) => Telnet::Command,
+            (Telnet::Data, _) => {
+                let mut state = server.lock();
+                if !state.typed(id, byte) {
+                    return Ok(());
+                }
+                server.changed(&mut state);
+                Telnet::Data
+            }
+            (Telnet::Command, SB) => Telnet::Subnegotiation,
+            (Telnet::Command, 251..=254) => Telnet::Option,
+            (Telnet::Command, _) | (Telnet::Option, _) => Telnet::Data,
+            (Telnet::Subnegotiation, IAC) => Telnet::SubnegotiationCommand,
+            (Telnet::Subnegotiation, _) => Telnet::Subnegotiation,
+            (Telnet::SubnegotiationCommand, SE) => Telnet::Data,
+            (Telnet::SubnegotiationCommand, _) => Telnet::Subnegotiation,
+        };
+    }
+    Ok(())
+}
+
+/// Draws the screen and chat for client `id` whenever they change, until they disconnect
+fn write_client(stream: TcpStream, server: &ACTelnetServer, id: usize, mode: ACTextMode) -> io::Result<()> {
+    let mut stream = BufWriter::new(stream);
+    stream.write_all(&[IAC, WILL, ECHO, IAC, WILL, SUPPRESS_GO_AHEAD])?;
+    queue!(stream, terminal::Clear(terminal::ClearType::All), cursor::Hide)?;
+
+    let mut screen = ACTerminalScreen::new(mode, None, "");
+    let (_, height) = mode.size();
+    let mut drawn_status: Vec<String> = vec![];
+    let mut seen = None;
+    loop {
+        let mut out = vec![];
+        {
+            let mut state = server.lock();
+            while seen == Some(state.generation) {
+                state = server.changed.wait(state).expect("no connection panics while holding the lock");
+            }
+            seen = Some(state.generation);
+            let Some(position) = state.clients.iter().position(|c| c.id == id) else { return Ok(()) };
+            screen.set_hint(if position == 0 {
+                "you have the keypad: 1234 qwer asdf zxcv, / to chat"
+            } else {
+                "you are watching, type to chat"
+            });
+            screen.draw(&mut out, &state.framebuffer, state.tone)?;
+
+            let status = state.status(id);
+            for (n, line) in status.iter().enumerate() {
+                if drawn_status.get(n) != Some(line) {
+                    let y = (height + 3 + n) as u16;
+                    queue!(out, cursor::MoveTo(0, y), Print(line), terminal::Clear(terminal::ClearType::UntilNewLine))?;
+                }
+            }
+            drawn_status = status;
+        }
+        stream.write_all(&out)?;
+        stream.flush()?;
+    }
+}
+
+/// Talks to one connection until it goes away
+fn serve_client(stream: TcpStream, server: Arc<ACTelnetServer>, mode: ACTextMode) -> io::Result<()> {
+    let id = {
+        let mut state = server.lock();
+        let id = state.join();
+        server.changed(&mut state);
+        id
+    };
+
+    let reader = {
+        let (stream, server) = (stream.try_clone()?, server.clone());
+        thread::spawn(move || {
+            let result = read_client(stream, &server, id);
+            let mut state = server.lock();
+            state.leave(id);
+            server.changed(&mut state);
+            result
+        })
+    };
+    let written = write_client(stream.try_clone()?, &server, id, mode);
+    // stop the reader if writing failed first
+    let _ = stream.shutdown(std::net::Shutdown::Both);
+    let read = reader.join().expect("the reader doesn't panic");
+    written.and(read)
+}
+
+/// `ate-chip serve-telnet`, plays a rom for whoever connects until the process is killed
+pub fn serve(args: TelnetArgs) -> Result<(), ACEmError> {
+    let rom = fs::read(&args.rom)?;
+    let mut emulator = ACEmulator::new();
+    emulator.quirks = ACQuirks::preset(&args.quirks).expect("clap checked that the preset exists");
+    emulator.load_rom(rom);
+
+    let server = Arc::new(ACTelnetServer {
+        state: Mutex::new(ACTelnetState::new(emulator.framebuffer().clone())),
+        changed: Condvar::new(),
+    });
+
+    let listener = TcpListener::bind((args.bind.as_str(), args.port))?;
+    log::info!("serving telnet on {}", listener.local_addr()?);
+    let mode: ACTextMode = args.text_mode.into();
+    {
+        let server = server.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let stream = match stream {
+                    Ok(stream) => stream,
+                    Err(e) => {
+                        log::warn!("couldn't accept a connection: {}", e);
+                        continue;
+                    }
+                };
+                let peer = stream.peer_addr().map_or("?".to_string(), |a| a.to_string());
+                log::info!("{} connected", peer);
+                let server = server.clone();
+                thread::spawn(move || match serve_client(stream, server, mode) {
+                    Ok(()) => log::info!("{} disconnected", peer),
+                    Err(e) => log::info!("{} disconnected: {}", peer, e),
+                });
+            }
+        });
+    }
+
+    let mut frontend = ACTelnetFrontend {
+        server,
+        keys: ACTerminalKeys::new(false),
+    };
+    frontend::run(&mut emulator, &mut frontend, args.cycles_per_frame)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn typed(state: &mut ACTelnetState, id: usize, text: &str) {
+        for byte in text.bytes() {
+            assert!(state.typed(id, byte), "{:?} made player {} leave", byte as char, id);
+        }
+    }
+
+    #[test]
+    fn only_the_first_player_has_the_keypad() {
+        let mut state = ACTelnetState::new(ACFramebuffer::new());
+        let (first, second) = (state.join(), state.join());
+        typed(&mut state, first, "1q");
+        typed(&mut state, second, "zx");
+        assert_eq!(state.keys, ['1', 'q']);
+        assert_eq!(state.status(first)[0], "player 1 has the keypad, 1 watching");
+        // what the watcher typed is a chat message waiting to be sent
+        assert_eq!(state.status(second).last().unwrap(), "> zx");
+        assert_eq!(state.status(first).last().unwrap(), "");
+
+        state.leave(first);
+        assert_eq!(state.chat.iter().rev().take(2).collect::<Vec<_>>(), ["player 2 has the keypad", "player 1 left"]);
+        typed(&mut state, second, "\rv");
+        assert_eq!(state.keys, ['1', 'q', 'v']);
+        assert_eq!(state.status(second)[0], "player 2 has the keypad, 0 watching");
+        // someone leaving who didn't have the keypad doesn't hand it over
+        let third = state.join();
+        state.leave(third);
+        assert_eq!(state.chat.back().unwrap(), "player 3 left");
+    }
+
+    #[test]
+    fn slash_starts_a_chat_message() {
+        let mut state = ACTelnetState::new(ACFramebuffer::new());
+        let player = state.join();
+        typed(&mut state, player, "/hi q\x7F");
+        assert!(state.keys.is_empty());
+        assert_eq!(state.status(player).last().unwrap(), "> hi ");
+        typed(&mut state, player, "there\r");
+        assert_eq!(state.chat.back().unwrap(), "player 1: hi there");
+        assert_eq!(state.status(player).last().unwrap(), "");
+        // back to pressing keys once the message is sent
+        typed(&mut state, player, "w");
+        assert_eq!(state.keys, ['w']);
+
+        // only the last few messages are kept
+        for n in 0..CHAT_LINES {
+            typed(&mut state, player, &format!("/{}\r", n));
+        }
+        assert_eq!(state.status(player)[1..=CHAT_LINES], ["player 1: 0", "player 1: 1", "player 1: 2", "player 1: 3", "player 1: 4"]);
+        assert!(!state.typed(player, 0x03));
+        assert!(!state.typed(42, b'a'), "nobody is player 42");
+    }
+
+    #[test]
+    fn telnet_commands_are_skipped() {
+        let server = ACTelnetServer {
+            state: Mutex::new(ACTelnetState::new(ACFramebuffer::new())),
+            changed: Condvar::new(),
+        };
+        let id = server.lock().join();
+        #[rustfmt::skip]
+        let input = [
+            b'1',
+            IAC, 253, ECHO, // DO ECHO
+            b'2',
+            IAC, SB, 31, 0, 80, IAC, IAC, 0, 24, IAC, SE, // the window size
+            b'3',
+            IAC, 241, // no operation
+            IAC, IAC, // an escaped 255
+            b'4',
+        ];
+        read_client(&input[..], &server, id).unwrap();
+        assert_eq!(server.lock().keys, ['1', '2', '3', '4']);
+
+        // stops reading once they ask to leave
+        read_client(&b"5\x036"[..], &server, id).unwrap();
+        assert_eq!(server.lock().keys, ['1', '2', '3', '4', '5']);
+    }
+}
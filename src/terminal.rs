@@ -0,0 +1,346 @@
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::time::{Duration, Instant};
+
+use crossterm::event::{
+    self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers, KeyboardEnhancementFlags, PopKeyboardEnhancementFlags,
+    PushKeyboardEnhancementFlags,
+};
+use crossterm::style::{Color, Print, ResetColor, SetBackgroundColor, SetForegroundColor};
+use crossterm::{cursor, queue, terminal};
+
+use ate_chip::emulator::{ACEmulator, ACFramebuffer};
+use ate_chip::frontend::{ACFrontend, ACInput, ACRenderer};
+use ate_chip::graphics::{self, ACGraphicsProtocol};
+use ate_chip::image::ACBitmap;
+use ate_chip::keyboard::{ACKey, ACKeyboard};
+use ate_chip::textmode::ACTextMode;
+use ate_chip::{SCREEN_HEIGHT, SCREEN_WIDTH};
+
+use crate::{ACEmError, Graphics, RunArgs};
+
+/// Without the kitty keyboard protocol terminals only say when a key goes down (and again as it auto repeats),
+/// so a key counts as held for this long after it is pressed. Long enough to cover the delay before auto repeat kicks in
+const FIRST_PRESS_HOLD: Duration = Duration::from_millis(300);
+/// how long a key stays held after each auto repeat
+const REPEAT_HOLD: Duration = Duration::from_millis(100);
+
+const COLOR_ON: Color = Color::Rgb { r: 105, g: 237, b: 44 };
+const COLOR_OFF: Color = Color::Black;
+const COLOR_BORDER: Color = Color::DarkGrey;
+/// the border while the sound timer is running
+const COLOR_BELL: Color = Color::Yellow;
+/// the same colours, for images
+const RGB_ON: [u8; 3] = [105, 237, 44];
+const RGB_OFF: [u8; 3] = [0, 0, 0];
+/// the background while the sound timer is running
+const RGB_BELL: [u8; 3] = [80, 70, 0];
+/// how long to wait for the terminal to answer queries
+const QUERY_TIMEOUT: Duration = Duration::from_millis(500);
+/// id of the screen image, for the kitty graphics protocol
+const KITTY_IMAGE_ID: u32 = 1;
+
+/// Puts the terminal into raw mode on the alternate screen, and puts it back how it was when dropped (even on a panic)
+struct ACRawTerminal {
+    /// whether the terminal reports key releases
+    enhanced: bool,
+}
+
+impl ACRawTerminal {
+    fn enter() -> io::Result<Self> {
+        terminal::enable_raw_mode()?;
+        let mut stdout = io::stdout();
+        crossterm::execute!(stdout, terminal::EnterAlternateScreen, cursor::Hide)?;
+        let enhanced = terminal::supports_keyboard_enhancement().unwrap_or(false);
+        if enhanced {
+            crossterm::execute!(stdout, PushKeyboardEnhancementFlags(KeyboardEnhancementFlags::REPORT_EVENT_TYPES))?;
+        }
+        Ok(Self { enhanced })
+    }
+}
+
+impl Drop for ACRawTerminal {
+    fn drop(&mut self) {
+        let mut stdout = io::stdout();
+        if self.enhanced {
+            let _ = crossterm::execute!(stdout, PopKeyboardEnhancementFlags);
+        }
+        let _ = crossterm::execute!(stdout, ResetColor, cursor::Show, terminal::LeaveAlternateScreen);
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+/// Asks the terminal which image protocol it supports, if any.
+///
+/// This sends a kitty graphics query followed by a request for the primary device attributes, which every terminal answers.
+/// Terminals that understand kitty graphics answer the first query before the second, and sixel support is attribute 4
+#[cfg(unix)]
+fn query_graphics() -> io::Result<Option<ACGraphicsProtocol>> {
+    let mut stdout = io::stdout();
+    write!(stdout, "\x1b_Gi=31,s=1,v=1,a=q,t=d,f=24;AAAA\x1b\\\x1b[c")?;
+    stdout.flush()?;
+
+    let deadline = Instant::now() + QUERY_TIMEOUT;
+    let mut reply = vec![];
+    // read until the end of the device attributes, `ESC [ ? <attributes> c`
+    let attributes = loop {
+        if let Some(start) = reply.windows(3).position(|w| w == b"\x1b[?") {
+            if let Some(len) = reply[start..].iter().position(|b| *b == b'c') {
+                break String::from_utf8_lossy(&reply[start + 3..start + len]).to_string();
+            }
+        }
+        let timeout = deadline.saturating_duration_since(Instant::now());
+        let mut fd = libc::pollfd { fd: libc::STDIN_FILENO, events: libc::POLLIN, revents: 0 };
+        // SAFETY: one valid pollfd is passed
+        if timeout.is_zero() || unsafe { libc::poll(&mut fd, 1, timeout.as_millis() as i32) } <= 0 {
+            log::debug!("The terminal did not answer, assuming it cannot show images");
+            return Ok(None);
+        }
+        let mut buf = [0u8; 256];
+        // SAFETY: reading into a buffer of the given length
+        let read = unsafe { libc::read(libc::STDIN_FILENO, buf.as_mut_ptr().cast(), buf.len()) };
+        if read <= 0 {
+            return Ok(None);
+        }
+        reply.extend_from_slice(&buf[..read as usize]);
+    };
+
+    Ok(if reply.windows(10).any(|w| w == b"\x1b_Gi=31;OK") {
+        Some(ACGraphicsProtocol::Kitty)
+    } else if attributes.split(';').any(|a| a == "4") {
+        Some(ACGraphicsProtocol::Sixel)
+    } else {
+        None
+    })
+}
+
+#[cfg(not(unix))]
+fn query_graphics() -> io::Result<Option<ACGraphicsProtocol>> {
+    Ok(None)
+}
+
+/// Draws the screen as an image if the terminal can show one, otherwise as text,
+/// only repainting what changed since the last frame
+pub struct ACTerminalScreen {
+    mode: ACTextMode,
+    /// what is currently on the terminal, row by row, `None` if it needs repainting regardless
+    drawn: Vec<Option<char>>,
+    /// the colour the border was last drawn in
+    border: Option<Color>,
+    /// the protocol and scale to draw images with, instead of text
+    graphics: Option<(ACGraphicsProtocol, usize)>,
+    /// the last image drawn, and whether it was drawn with the bell showing
+    drawn_image: Option<(ACBitmap, bool)>,
+    /// shown under the screen
+    hint: String,
+}
+
+impl ACTerminalScreen {
+    pub fn new(mode: ACTextMode, graphics: Option<(ACGraphicsProtocol, usize)>, hint: &str) -> Self {
+        let (w, h) = mode.cell_size();
+        Self {
+            mode,
+            drawn: vec![None; (SCREEN_WIDTH as usize / w) * (SCREEN_HEIGHT as usize / h)],
+            border: None,
+            graphics,
+            drawn_image: None,
+            hint: hint.to_string(),
+        }
+    }
+
+    /// Changes the line under the screen, it is drawn with the next frame
+    pub fn set_hint(&mut self, hint: &str) {
+        if self.hint != hint {
+            self.hint = hint.to_string();
+            self.border = None;
+        }
+    }
+
+    /// Forgets what is on the terminal, so the next draw repaints everything
+    pub fn invalidate(&mut self) {
+        self.drawn.fill(None);
+        self.border = None;
+        self.drawn_image = None;
+    }
+
+    fn draw_image(&mut self, out: &mut impl Write, framebuffer: &ACFramebuffer, bell: bool, protocol: ACGraphicsProtocol, scale: usize) -> io::Result<()> {
+        let bitmap = ACBitmap::from_framebuffer(framebuffer);
+        if self.drawn_image.as_ref() == Some(&(bitmap.clone(), bell)) {
+            return Ok(());
+        }
+        let background = if bell { RGB_BELL } else { RGB_OFF };
+        let scaled = bitmap.scaled(scale);
+        let image = match protocol {
+            ACGraphicsProtocol::Sixel => graphics::sixel(&scaled, RGB_ON, background),
+            ACGraphicsProtocol::Kitty => {
+                let png = scaled.encode_png(RGB_ON, background).map_err(io::Error::other)?;
+                graphics::kitty(&png, KITTY_IMAGE_ID)
+            }
+        };
+        queue!(out, cursor::MoveTo(0, 0), Print(image))?;
+        self.drawn_image = Some((bitmap, bell));
+        out.flush()
+    }
+
+    fn draw_border(&self, out: &mut impl Write, color: Color) -> io::Result<()> {
+        let (width, height) = self.mode.size();
+        queue!(out, SetBackgroundColor(Color::Reset), SetForegroundColor(color))?;
+        queue!(out, cursor::MoveTo(0, 0), Print(format!("┌{}┐", "─".repeat(width))))?;
+        for y in 1..=height as u16 {
+            queue!(out, cursor::MoveTo(0, y), Print("│"), cursor::MoveTo(width as u16 + 1, y), Print("│"))?;
+        }
+        queue!(
+            out,
+            cursor::MoveTo(0, height as u16 + 1),
+            Print(format!("└{}┘", "─".repeat(width))),
+            cursor::MoveTo(0, height as u16 + 2),
+            SetForegroundColor(COLOR_BORDER),
+            Print(&self.hint),
+            terminal::Clear(terminal::ClearType::UntilNewLine),
+        )
+    }
+
+    pub fn draw(&mut self, out: &mut impl Write, framebuffer: &ACFramebuffer, bell: bool) -> io::Result<()> {
+        if let Some((protocol, scale)) = self.graphics {
+            return self.draw_image(out, framebuffer, bell, protocol, scale);
+        }
+        let border = if bell { COLOR_BELL } else { COLOR_BORDER };
+        if self.border != Some(border) {
+            self.draw_border(out, border)?;
+            self.border = Some(border);
+        }
+
+        let (cell_width, _) = self.mode.cell_size();
+        let cells_across = SCREEN_WIDTH as usize / cell_width;
+        let repeat = self.mode.repeat();
+        queue!(out, SetBackgroundColor(COLOR_OFF), SetForegroundColor(COLOR_ON))?;
+        for (n, drawn) in self.drawn.iter_mut().enumerate() {
+            let (cx, cy) = (n % cells_across, n / cells_across);
+            let c = self.mode.cell(framebuffer, cx, cy);
+            if *drawn == Some(c) {
+                continue;
+            }
+            let text: String = std::iter::repeat_n(c, repeat).collect();
+            queue!(out, cursor::MoveTo(1 + (cx * repeat) as u16, 1 + cy as u16), Print(text))?;
+            *drawn = Some(c);
+        }
+        queue!(out, ResetColor)?;
+        out.flush()
+    }
+}
+
+/// Which keys are down, working around terminals that never say when a key is let go
+pub struct ACTerminalKeys {
+    /// when each held key should be released, for terminals without key release events
+    held_until: HashMap<ACKey, Instant>,
+    enhanced: bool,
+}
+
+impl ACTerminalKeys {
+    /// `enhanced` is whether key releases are reported
+    pub fn new(enhanced: bool) -> Self {
+        Self {
+            held_until: HashMap::new(),
+            enhanced,
+        }
+    }
+
+    /// Updates the keypad for `event`, returning the key if it was newly pressed
+    pub fn handle(&mut self, keyboard: &mut ACKeyboard, event: &KeyEvent) -> Option<ACKey> {
+        let key = match event.code {
+            KeyCode::Char(c) => ACKey::from_qwerty(c)?,
+            _ => return None,
+        };
+        let was_pressed = keyboard.is_pressed(&key);
+        match event.kind {
+            KeyEventKind::Release => {
+                keyboard.release(key);
+                return None;
+            }
+            _ if self.enhanced => keyboard.press(key),
+            _ => {
+                let hold = if was_pressed { REPEAT_HOLD } else { FIRST_PRESS_HOLD };
+                self.held_until.insert(key, Instant::now() + hold);
+                keyboard.press(key);
+            }
+        }
+        if was_pressed {
+            None
+        } else {
+            Some(key)
+        }
+    }
+
+    /// Lets go of keys that haven't been repeated for a while
+    pub fn expire(&mut self, keyboard: &mut ACKeyboard, now: Instant) {
+        self.held_until.retain(|key, until| {
+            if *until <= now {
+                keyboard.release(*key);
+                false
+            } else {
+                true
+            }
+        });
+    }
+}
+
+/// The terminal, drawn to as the screen and read from as the keypad
+struct ACTerminalFrontend {
+    /// put the terminal back how it was when this is dropped
+    _raw: ACRawTerminal,
+    keys: ACTerminalKeys,
+    screen: ACTerminalScreen,
+    out: io::BufWriter<io::Stdout>,
+}
+
+impl ACRenderer for ACTerminalFrontend {
+    fn render(&mut self, framebuffer: &ACFramebuffer, tone: bool) -> Result<(), String> {
+        self.screen.draw(&mut self.out, framebuffer, tone).map_err(|e| e.to_string())
+    }
+}
+
+impl ACFrontend for ACTerminalFrontend {
+    fn poll_input(&mut self, keyboard: &mut ACKeyboard) -> Result<ACInput, String> {
+        let mut new_keypress = None;
+        while event::poll(Duration::ZERO).map_err(|e| e.to_string())? {
+            match event::read().map_err(|e| e.to_string())? {
+                Event::Key(KeyEvent { code: KeyCode::Esc, .. }) => return Ok(ACInput::Quit),
+                Event::Key(KeyEvent { code: KeyCode::Char('c'), modifiers, .. }) if modifiers.contains(KeyModifiers::CONTROL) => {
+                    return Ok(ACInput::Quit)
+                }
+                Event::Key(event) => {
+                    if let Some(key) = self.keys.handle(keyboard, &event) {
+                        new_keypress = new_keypress.or(Some(key));
+                    }
+                }
+                Event::Resize(..) => {
+                    queue!(self.out, terminal::Clear(terminal::ClearType::All)).map_err(|e| e.to_string())?;
+                    self.screen.invalidate();
+                }
+                _ => {}
+            }
+        }
+        self.keys.expire(keyboard, Instant::now());
+        Ok(ACInput::Continue(new_keypress))
+    }
+}
+
+/// Plays `emulator` in the terminal until escape (or ctrl-c) is pressed.
+///
+/// The screen is drawn as an image `--scale` times its size if the terminal supports it, otherwise as text in `--text-mode`
+pub fn run(args: &RunArgs, emulator: &mut ACEmulator) -> Result<(), ACEmError> {
+    let raw = ACRawTerminal::enter()?;
+    let protocol = match args.graphics {
+        Graphics::Auto => query_graphics()?,
+        Graphics::Sixel => Some(ACGraphicsProtocol::Sixel),
+        Graphics::Kitty => Some(ACGraphicsProtocol::Kitty),
+        Graphics::None => None,
+    };
+    let mut frontend = ACTerminalFrontend {
+        keys: ACTerminalKeys::new(raw.enhanced),
+        _raw: raw,
+        screen: ACTerminalScreen::new(args.text_mode.into(), protocol.map(|p| (p, (args.scale as usize).max(1))), "keypad: 1234 qwer asdf zxcv, esc to quit"),
+        out: io::BufWriter::new(io::stdout()),
+    };
+    crate::run_frontend(args, emulator, &mut frontend)
+}
@@ -0,0 +1,106 @@
+use crate::emulator::ACFramebuffer;
+use crate::{SCREEN_HEIGHT, SCREEN_WIDTH};
+
+/// Ways of drawing the screen with text, trading how square the pixels look for how much room it takes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ACTextMode {
+    /// `██` per pixel, 128x32 columns
+    Blocks,
+    /// `▀`/`▄` for two pixels stacked in each character, 64x16 columns
+    HalfBlocks,
+    /// braille dots, 2x4 pixels in each character, 32x8 columns
+    Braille,
+}
+
+impl ACTextMode {
+    /// Pixels (across, down) covered by each character
+    pub fn cell_size(self) -> (usize, usize) {
+        match self {
+            Self::Blocks => (1, 1),
+            Self::HalfBlocks => (1, 2),
+            Self::Braille => (2, 4),
+        }
+    }
+
+    /// How many times each character is printed, to make up for characters being taller than they are wide
+    pub fn repeat(self) -> usize {
+        match self {
+            Self::Blocks => 2,
+            _ => 1,
+        }
+    }
+
+    /// The size of the screen in characters (columns, rows)
+    pub fn size(self) -> (usize, usize) {
+        let (w, h) = self.cell_size();
+        (SCREEN_WIDTH as usize / w * self.repeat(), SCREEN_HEIGHT as usize / h)
+    }
+
+    /// The character (before repeating) showing the cell at `cx`, `cy`, lit pixels being the foreground
+    pub fn cell(self, framebuffer: &ACFramebuffer, cx: usize, cy: usize) -> char {
+        let (w, h) = self.cell_size();
+        let px = |dx: usize, dy: usize| framebuffer.get_pixel(cx * w + dx, cy * h + dy);
+        match self {
+            Self::Blocks => if px(0, 0) { '█' } else { ' ' },
+            Self::HalfBlocks => match (px(0, 0), px(0, 1)) {
+                (false, false) => ' ',
+                (true, false) => '▀',
+                (false, true) => '▄',
+                (true, true) => '█',
+            },
+            Self::Braille => {
+                // dots are numbered down the left column then the right, with the bottom row added on the end
+                const DOTS: [(usize, usize); 8] = [(0, 0), (0, 1), (0, 2), (1, 0), (1, 1), (1, 2), (0, 3), (1, 3)];
+                let bits = DOTS
+                    .iter()
+                    .enumerate()
+                    .fold(0, |acc, (bit, (dx, dy))| acc | (px(*dx, *dy) as u32) << bit);
+                char::from_u32(0x2800 + bits).expect("the braille block is 256 characters")
+            }
+        }
+    }
+
+    /// The whole screen, one line per row of characters
+    pub fn render(self, framebuffer: &ACFramebuffer) -> String {
+        let (w, h) = self.cell_size();
+        let mut out = String::new();
+        for cy in 0..SCREEN_HEIGHT as usize / h {
+            for cx in 0..SCREEN_WIDTH as usize / w {
+                let c = self.cell(framebuffer, cx, cy);
+                out.extend(std::iter::repeat_n(c, self.repeat()));
+            }
+            out.push('\n');
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emulator::ACEmulator;
+    use crate::keyboard::ACKeyboard;
+
+    #[test]
+    fn text_modes() {
+        // the 0 from the font, ####, #..#, #..#, #..#, ####
+        let mut emu = ACEmulator::new();
+        emu.load_rom([0xD0, 0x05]);
+        emu.step(&ACKeyboard::new(), None);
+        let r = emu.framebuffer();
+        assert_eq!(ACTextMode::Blocks.cell(r, 0, 0), '█');
+        assert_eq!(ACTextMode::Blocks.cell(r, 1, 1), ' ');
+        assert_eq!(ACTextMode::HalfBlocks.cell(r, 0, 0), '█');
+        assert_eq!(ACTextMode::HalfBlocks.cell(r, 1, 0), '▀');
+        assert_eq!(ACTextMode::HalfBlocks.cell(r, 1, 1), ' ');
+        assert_eq!(ACTextMode::Braille.cell(r, 0, 0), '⡏');
+        assert_eq!(ACTextMode::Braille.cell(r, 0, 1), '⠉');
+
+        for mode in [ACTextMode::Blocks, ACTextMode::HalfBlocks, ACTextMode::Braille] {
+            let (width, height) = mode.size();
+            let rendered = mode.render(r);
+            assert_eq!(rendered.lines().count(), height);
+            assert!(rendered.lines().all(|line| line.chars().count() == width));
+        }
+    }
+}
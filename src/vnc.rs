@@ -0,0 +1,592 @@
+//! `ate-chip serve-vnc`, a frontend that any VNC viewer can play (or watch) through.
+//!
+//! Implements just enough of RFB 3.8 (https://datatracker.ietf.org/doc/html/rfc6143): no authentication,
+//! raw encoding only, and every client shares the one game
+use std::fs;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread;
+
+use ate_chip::emulator::{ACEmulator, ACFramebuffer};
+use ate_chip::frontend::{self, ACFrontend, ACInput, ACRenderer};
+use ate_chip::image::ACBitmap;
+use ate_chip::keyboard::{ACKey, ACKeyboard};
+use ate_chip::quirks::ACQuirks;
+
+use crate::{ACEmError, QUIRK_PRESETS};
+
+const COLOR_ON: [u8; 3] = [255, 255, 255];
+const COLOR_OFF: [u8; 3] = [0, 0, 0];
+
+#[derive(clap::Args, Debug)]
+pub struct VncArgs {
+    #[clap(help = "path to the rom file")]
+    rom: PathBuf,
+    #[clap(long, default_value_t = 5900, help = "port to listen on")]
+    port: u16,
+    #[clap(long, default_value = "0.0.0.0", help = "address to listen on, 127.0.0.1 to only allow this machine")]
+    bind: String,
+    #[clap(short, long, default_value_t = 8, help = "how many times bigger than 64x32 the screen is")]
+    scale: usize,
+    #[clap(long, default_value_t = 10, help = "instructions run per frame, there are 60 frames a second")]
+    cycles_per_frame: usize,
+    #[clap(long, default_value = "default", possible_values = QUIRK_PRESETS, help = "which interpreter to behave like")]
+    quirks: String,
+}
+
+/// What the emulator and the clients share
+struct ACVncState {
+    /// the screen, already scaled
+    frame: ACBitmap,
+    /// counts up each time `frame` changes, so clients can tell whether they are up to date
+    generation: u64,
+    /// how many times the beep has started
+    bells: u64,
+    /// key presses (`true`) and releases from every client, in the order they came in
+    keys: Vec<(ACKey, bool)>,
+}
+
+struct ACVncServer {
+    state: Mutex<ACVncState>,
+    /// notified whenever `state`, or what a client is waiting for, changes
+    changed: Condvar,
+}
+
+impl ACVncServer {
+    fn lock(&self) -> MutexGuard<'_, ACVncState> {
+        self.state.lock().expect("no client panics while holding the lock")
+    }
+
+    /// Wakes every client, holding the lock so a client can't miss it between checking and waiting
+    fn notify(&self) {
+        let _state = self.lock();
+        self.changed.notify_all();
+    }
+}
+
+/// How a client wants pixels sent, from `SetPixelFormat`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct ACPixelFormat {
+    bits_per_pixel: u8,
+    depth: u8,
+    big_endian: bool,
+    /// pixels are colours made from `max` and `shift`, rather than indexes into a colour map
+    true_colour: bool,
+    /// largest value of red, green and blue
+    max: [u16; 3],
+    /// how far red, green and blue are shifted left
+    shift: [u8; 3],
+}
+
+impl ACPixelFormat {
+    /// 32 bit xRGB, what the server offers
+    const DEFAULT: Self = Self {
+        bits_per_pixel: 32,
+        depth: 24,
+        big_endian: false,
+        true_colour: true,
+        max: [255, 255, 255],
+        shift: [16, 8, 0],
+    };
+
+    fn parse(b: &[u8; 16]) -> Self {
+        Self {
+            bits_per_pixel: b[0],
+            depth: b[1],
+            big_endian: b[2] != 0,
+            true_colour: b[3] != 0,
+            max: [
+                u16::from_be_bytes([b[4], b[5]]),
+                u16::from_be_bytes([b[6], b[7]]),
+                u16::from_be_bytes([b[8], b[9]]),
+            ],
+            shift: [b[10], b[11], b[12]],
+        }
+    }
+
+    fn to_bytes(self) -> [u8; 16] {
+        let [r, g, b] = self.max.map(u16::to_be_bytes);
+        [
+            self.bits_per_pixel,
+            self.depth,
+            self.big_endian as u8,
+            self.true_colour as u8,
+            r[0], r[1], g[0], g[1], b[0], b[1],
+            self.shift[0], self.shift[1], self.shift[2],
+            0, 0, 0,
+        ]
+    }
+
+    /// `lit` as a pixel in this format, for colour maps entry 1 is lit and 0 isn't (see `colour_map`)
+    fn pixel(self, lit: bool) -> Vec<u8> {
+        let value = if self.true_colour {
+            let rgb = if lit { COLOR_ON } else { COLOR_OFF };
+            (0..3).fold(0u32, |acc, c| acc | (rgb[c] as u32 * self.max[c] as u32 / 255) << self.shift[c])
+        } else {
+            lit as u32
+        };
+        let bytes = self.bits_per_pixel as usize / 8;
+        if self.big_endian {
+            value.to_be_bytes()[4 - bytes..].to_vec()
+        } else {
+            value.to_le_bytes()[..bytes].to_vec()
+        }
+    }
+}
+
+/// What a client has asked for, shared between the thread reading from it and the one writing to it
+#[derive(Debug)]
+struct ACVncClient {
+    format: ACPixelFormat,
+    /// an update was asked for, and whether only the changes since the last one are needed
+    requested: Option<bool>,
+    /// the format changed, and the colour map needs sending before the next update
+    format_changed: bool,
+    closed: bool,
+}
+
+/// The frontend side of the server, run by `frontend::run` like any other frontend
+struct ACVncFrontend {
+    server: Arc<ACVncServer>,
+    scale: usize,
+    tone: bool,
+}
+
+impl ACRenderer for ACVncFrontend {
+    fn render(&mut self, framebuffer: &ACFramebuffer, tone: bool) -> Result<(), String> {
+        let frame = ACBitmap::from_framebuffer(framebuffer).scaled(self.scale);
+        let bell = tone && !self.tone;
+        self.tone = tone;
+        let mut state = self.server.lock();
+        let changed = state.frame != frame;
+        if changed {
+            state.frame = frame;
+            state.generation += 1;
+        }
+        if bell {
+            state.bells += 1;
+        }
+        if changed || bell {
+            self.server.changed.notify_all();
+        }
+        Ok(())
+    }
+}
+
+impl ACFrontend for ACVncFrontend {
+    fn poll_input(&mut self, keyboard: &mut ACKeyboard) -> Result<ACInput, String> {
+        let mut new_keypress = None;
+        for (key, down) in self.server.lock().keys.drain(..) {
+            if !down {
+                keyboard.release(key);
+                continue;
+            }
+            if !keyboard.is_pressed(&key) {
+                new_keypress = new_keypress.or(Some(key));
+            }
+            keyboard.press(key);
+        }
+        Ok(ACInput::Continue(new_keypress))
+    }
+}
+
+/// The keypad key for an X11 keysym, which are the same as ascii for letters and numbers
+fn keypad_key(keysym: u32) -> Option<ACKey> {
+    char::from_u32(keysym)
+        .filter(char::is_ascii)
+        .and_then(|c| ACKey::from_qwerty(c.to_ascii_lowercase()))
+}
+
+/// Version, security and init messages, up to the point where the client starts sending requests
+fn handshake(stream: &mut TcpStream, width: u16, height: u16, name: &str) -> io::Result<()> {
+    stream.write_all(b"RFB 003.008\n")?;
+    let mut version = [0; 12];
+    stream.read_exact(&mut version)?;
+    let minor: u32 = std::str::from_utf8(&version[8..11]).ok().and_then(|v| v.parse().ok()).unwrap_or(0);
+    log::debug!("client speaks {:?}", String::from_utf8_lossy(&version).trim());
+    if minor < 7 {
+        // 3.3 clients are told the security type, rather than choosing it
+        stream.write_all(&1u32.to_be_bytes())?;
+    } else {
+        // one type, None
+        stream.write_all(&[1, 1])?;
+        let mut chosen = [0];
+        stream.read_exact(&mut chosen)?;
+        if chosen[0] != 1 {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "the client chose a security type we don't offer"));
+        }
+        if minor >= 8 {
+            stream.write_all(&0u32.to_be_bytes())?;
+        }
+    }
+    // whether to share the screen with other clients, which they always do
+    let mut shared = [0];
+    stream.read_exact(&mut shared)?;
+
+    let mut init = vec![];
+    init.extend_from_slice(&width.to_be_bytes());
+    init.extend_from_slice(&height.to_be_bytes());
+    init.extend_from_slice(&ACPixelFormat::DEFAULT.to_bytes());
+    init.extend_from_slice(&(name.len() as u32).to_be_bytes());
+    init.extend_from_slice(name.as_bytes());
+    stream.write_all(&init)
+}
+
+/// Reads messages from a client until it disconnects, passing key presses on to the emulator
+fn read_client(stream: impl Read, server: &ACVncServer, client: &Mutex<ACVncClient>) -> io::Result<()> {
+    let mut stream = BufReader::new(stream);
+    // only for the fixed size parts of messages, anything the client says the length of is skipped with `discard`
+    fn read<const N: usize>(stream: &mut impl Read) -> io::Result<[u8; N]> {
+        let mut buf = [0; N];
+        stream.read_exact(&mut buf)?;
+        Ok(buf)
+    }
+    fn discard(stream: &mut impl Read, len: u64) -> io::Result<()> {
+        let skipped = io::copy(&mut stream.take(len), &mut io::sink())?;
+        if skipped < len {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        Ok(())
+    }
+    loop {
+        let [message] = read(&mut stream)?;
+        match message {
+            // SetPixelFormat
+            0 => {
+                let b: [u8; 19] = read(&mut stream)?;
+                let format = ACPixelFormat::parse(b[3..].try_into().expect("read 16 bytes"));
+                if ![8, 16, 32].contains(&format.bits_per_pixel) {
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, "unsupported bits per pixel"));
+                }
+                let mut client = client.lock().expect("no client panics while holding the lock");
+                client.format = format;
+                client.format_changed = true;
+            }
+            // SetEncodings, raw is always allowed so which others the client knows doesn't matter
+            2 => {
+                let b: [u8; 3] = read(&mut stream)?;
+                let count = u16::from_be_bytes([b[1], b[2]]);
+                discard(&mut stream, count as u64 * 4)?;
+            }
+            // FramebufferUpdateRequest, the region asked for is ignored and everything that changed is sent
+            3 => {
+                let b: [u8; 9] = read(&mut stream)?;
+                let incremental = b[0] != 0;
+                let mut client = client.lock().expect("no client panics while holding the lock");
+                client.requested = Some(client.requested.map_or(incremental, |i| i && incremental));
+            }
+            // KeyEvent
+            4 => {
+                let b: [u8; 7] = read(&mut stream)?;
+                let down = b[0] != 0;
+                let keysym = u32::from_be_bytes([b[3], b[4], b[5], b[6]]);
+                if let Some(key) = keypad_key(keysym) {
+                    server.lock().keys.push((key, down));
+                }
+                continue;
+            }
+            // PointerEvent, there is nothing to point at
+            5 => {
+                read::<5>(&mut stream)?;
+                continue;
+            }
+            // ClientCutText, which can claim to be up to 4GB long so is never read into memory
+            6 => {
+                let b: [u8; 7] = read(&mut stream)?;
+                let len = u32::from_be_bytes([b[3], b[4], b[5], b[6]]);
+                discard(&mut stream, len as u64)?;
+                continue;
+            }
+            _ => return Err(io::Error::new(io::ErrorKind::InvalidData, format!("unknown message type {}", message))),
+        }
+        server.notify();
+    }
+}
+
+/// The smallest rectangle (x, y, width, height) holding every pixel that differs between `a` and `b`
+fn changed_rect(a: &ACBitmap, b: &ACBitmap) -> Option<(usize, usize, usize, usize)> {
+    let mut rect: Option<(usize, usize, usize, usize)> = None;
+    for y in 0..a.height {
+        for x in 0..a.width {
+            if a.pixels[y * a.width + x] != b.pixels[y * b.width + x] {
+                rect = Some(match rect {
+                    None => (x, y, x, y),
+                    Some((x0, y0, x1, y1)) => (x0.min(x), y0.min(y), x1.max(x), y1.max(y)),
+                });
+            }
+        }
+    }
+    rect.map(|(x0, y0, x1, y1)| (x0, y0, x1 - x0 + 1, y1 - y0 + 1))
+}
+
+/// A `FramebufferUpdate` with `rect` of `frame` in raw encoding
+fn update(frame: &ACBitmap, (x, y, width, height): (usize, usize, usize, usize), format: ACPixelFormat) -> Vec<u8> {
+    let on = format.pixel(true);
+    let off = format.pixel(false);
+    let mut out = vec![0, 0];
+    out.extend_from_slice(&1u16.to_be_bytes());
+    for n in [x, y, width, height] {
+        out.extend_from_slice(&(n as u16).to_be_bytes());
+    }
+    // raw encoding
+    out.extend_from_slice(&0i32.to_be_bytes());
+    for py in y..y + height {
+        for px in x..x + width {
+            out.extend_from_slice(if frame.pixels[py * frame.width + px] { &on } else { &off });
+        }
+    }
+    out
+}
+
+/// `SetColourMapEntries`, for clients that use a colour map: 0 is unlit and 1 is lit
+fn colour_map() -> Vec<u8> {
+    let mut out = vec![1, 0, 0, 0, 0, 2];
+    for rgb in [COLOR_OFF, COLOR_ON] {
+        for c in rgb {
+            out.extend_from_slice(&(c as u16 * 257).to_be_bytes());
+        }
+    }
+    out
+}
+
+/// Sends a client updates as it asks for them, and bells, until it disconnects
+fn write_client(stream: TcpStream, server: &ACVncServer, client: &Mutex<ACVncClient>) -> io::Result<()> {
+    let mut stream = BufWriter::new(stream);
+    // what the client was last sent, `None` before the first update
+    let mut sent: Option<(ACBitmap, u64)> = None;
+    let mut rung = server.lock().bells;
+    loop {
+        let mut messages = vec![];
+        {
+            let mut state = server.lock();
+            loop {
+                let mut client = client.lock().expect("no client panics while holding the lock");
+                if client.closed {
+                    return Ok(());
+                }
+                if client.format_changed {
+                    client.format_changed = false;
+                    if !client.format.true_colour {
+                        messages.push(colour_map());
+                    }
+                }
+                if state.bells != rung {
+                    rung = state.bells;
+                    // Bell
+                    messages.push(vec![2]);
+                }
+                let up_to_date = matches!(&sent, Some((_, generation)) if *generation == state.generation);
+                match client.requested {
+                    Some(true) if up_to_date => {}
+                    Some(incremental) => {
+                        client.requested = None;
+                        let rect = match &sent {
+                            Some((last, _)) if incremental => changed_rect(last, &state.frame),
+                            _ => Some((0, 0, state.frame.width, state.frame.height)),
+                        };
+                        // an incremental update with nothing changed only happens if the screen changed back
+                        let rect = rect.unwrap_or((0, 0, 1, 1));
+                        messages.push(update(&state.frame, rect, client.format));
+                        sent = Some((state.frame.clone(), state.generation));
+                    }
+                    None => {}
+                }
+                if !messages.is_empty() {
+                    break;
+                }
+                drop(client);
+                state = server.changed.wait(state).expect("no client panics while holding the lock");
+            }
+        }
+        for message in messages {
+            stream.write_all(&message)?;
+        }
+        stream.flush()?;
+    }
+}
+
+/// Talks to one client until it disconnects
+fn serve_client(mut stream: TcpStream, server: Arc<ACVncServer>, name: &str) -> io::Result<()> {
+    let (width, height) = {
+        let state = server.lock();
+        (state.frame.width as u16, state.frame.height as u16)
+    };
+    handshake(&mut stream, width, height, name)?;
+    let client = Arc::new(Mutex::new(ACVncClient {
+        format: ACPixelFormat::DEFAULT,
+        requested: None,
+        format_changed: false,
+        closed: false,
+    }));
+    let reader = {
+        let (stream, server, client) = (stream.try_clone()?, server.clone(), client.clone());
+        thread::spawn(move || {
+            let result = read_client(stream, &server, &client);
+            client.lock().expect("no client panics while holding the lock").closed = true;
+            server.notify();
+            result
+        })
+    };
+    let written = write_client(stream.try_clone()?, &server, &client);
+    // stop the reader if writing failed first
+    let _ = stream.shutdown(std::net::Shutdown::Both);
+    let read = reader.join().expect("the reader doesn't panic");
+    match (written, read) {
+        (Err(e), _) => Err(e),
+        (_, Err(e)) if e.kind() != io::ErrorKind::UnexpectedEof => Err(e),
+        _ => Ok(()),
+    }
+}
+
+/// `ate-chip serve-vnc`, plays a rom for whoever connects until the process is killed
+pub fn serve(args: VncArgs) -> Result<(), ACEmError> {
+    let rom = fs::read(&args.rom)?;
+    let mut emulator = ACEmulator::new();
+    emulator.quirks = ACQuirks::preset(&args.quirks).expect("clap checked that the preset exists");
+    emulator.load_rom(rom);
+
+    let scale = args.scale.max(1);
+    let server = Arc::new(ACVncServer {
+        state: Mutex::new(ACVncState {
+            frame: ACBitmap::from_framebuffer(emulator.framebuffer()).scaled(scale),
+            generation: 0,
+            bells: 0,
+            keys: vec![],
+        }),
+        changed: Condvar::new(),
+    });
+
+    let listener = TcpListener::bind((args.bind.as_str(), args.port))?;
+    log::info!("serving VNC on {}", listener.local_addr()?);
+    let name = format!("ate-chip: {}", args.rom.file_name().unwrap_or_default().to_string_lossy());
+    {
+        let server = server.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let stream = match stream {
+                    Ok(stream) => stream,
+                    Err(e) => {
+                        log::warn!("couldn't accept a connection: {}", e);
+                        continue;
+                    }
+                };
+                let peer = stream.peer_addr().map_or("?".to_string(), |a| a.to_string());
+                log::info!("{} connected", peer);
+                let (server, name) = (server.clone(), name.clone());
+                thread::spawn(move || match serve_client(stream, server, &name) {
+                    Ok(()) => log::info!("{} disconnected", peer),
+                    Err(e) => log::info!("{} disconnected: {}", peer, e),
+                });
+            }
+        });
+    }
+
+    let mut frontend = ACVncFrontend { server, scale, tone: false };
+    frontend::run(&mut emulator, &mut frontend, args.cycles_per_frame)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn server() -> ACVncServer {
+        ACVncServer {
+            state: Mutex::new(ACVncState {
+                frame: ACBitmap { width: 4, height: 2, pixels: vec![false; 8] },
+                generation: 0,
+                bells: 0,
+                keys: vec![],
+            }),
+            changed: Condvar::new(),
+        }
+    }
+
+    fn client() -> Mutex<ACVncClient> {
+        Mutex::new(ACVncClient { format: ACPixelFormat::DEFAULT, requested: None, format_changed: false, closed: false })
+    }
+
+    /// Feeds `messages` to `read_client` as if a client sent them and hung up, returning how it finished
+    fn read_all(messages: &[u8], server: &ACVncServer, client: &Mutex<ACVncClient>) -> io::ErrorKind {
+        read_client(messages, server, client).expect_err("reading stops when the client hangs up").kind()
+    }
+
+    #[test]
+    fn key_events_reach_the_emulator() {
+        let (server, client) = (server(), client());
+        // `w` down, `w` up, then `p`, which isn't on the keypad
+        let messages = [[4, 1, 0, 0, 0, 0, 0, b'w'], [4, 0, 0, 0, 0, 0, 0, b'w'], [4, 1, 0, 0, 0, 0, 0, b'p']].concat();
+        assert_eq!(read_all(&messages, &server, &client), io::ErrorKind::UnexpectedEof);
+        let w = ACKey::from_qwerty('w').unwrap();
+        assert_eq!(server.lock().keys, [(w, true), (w, false)]);
+    }
+
+    #[test]
+    fn cut_text_and_encodings_are_skipped() {
+        let (server, client) = (server(), client());
+        let mut messages = vec![6, 0, 0, 0, 0, 0, 0, 3, b'a', b'b', b'c'];
+        // two encodings
+        messages.extend_from_slice(&[2, 0, 0, 2, 0, 0, 0, 0, 0, 0, 0, 1]);
+        messages.extend_from_slice(&[4, 1, 0, 0, 0, 0, 0, b'1']);
+        assert_eq!(read_all(&messages, &server, &client), io::ErrorKind::UnexpectedEof);
+        assert_eq!(server.lock().keys, [(ACKey::K1, true)]);
+    }
+
+    #[test]
+    fn huge_cut_text_is_not_read_into_memory() {
+        let (server, client) = (server(), client());
+        // claims to be 4GB, and hangs up after a few bytes
+        let messages = [6, 0, 0, 0, 0xFF, 0xFF, 0xFF, 0xFF, b'x', b'y'];
+        assert_eq!(read_all(&messages, &server, &client), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn pixel_formats_are_checked() {
+        let (server, client) = (server(), client());
+        let format = ACPixelFormat { bits_per_pixel: 16, depth: 16, max: [31, 63, 31], shift: [11, 5, 0], ..ACPixelFormat::DEFAULT };
+        let messages = [&[0, 0, 0, 0][..], &format.to_bytes()].concat();
+        assert_eq!(read_all(&messages, &server, &client), io::ErrorKind::UnexpectedEof);
+        let set = client.lock().unwrap();
+        assert_eq!(set.format, format);
+        assert!(set.format_changed);
+        drop(set);
+
+        let format = ACPixelFormat { bits_per_pixel: 24, ..ACPixelFormat::DEFAULT };
+        let messages = [&[0, 0, 0, 0][..], &format.to_bytes()].concat();
+        assert_eq!(read_all(&messages, &server, &client), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn update_requests_are_only_incremental_if_every_one_was() {
+        let (server, client) = (server(), client());
+        let request = |incremental: u8| [3, incremental, 0, 0, 0, 0, 0, 4, 0, 2];
+        read_all(&[request(1), request(1)].concat(), &server, &client);
+        assert_eq!(client.lock().unwrap().requested, Some(true));
+        read_all(&[request(1), request(0), request(1)].concat(), &server, &client);
+        assert_eq!(client.lock().unwrap().requested, Some(false));
+    }
+
+    #[test]
+    fn unknown_messages_are_an_error() {
+        let (server, client) = (server(), client());
+        assert_eq!(read_all(&[7], &server, &client), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn changed_rect_bounds_every_change() {
+        let blank = ACBitmap { width: 4, height: 3, pixels: vec![false; 12] };
+        assert_eq!(changed_rect(&blank, &blank), None);
+
+        let mut one = blank.clone();
+        one.pixels[4 + 2] = true;
+        assert_eq!(changed_rect(&blank, &one), Some((2, 1, 1, 1)));
+
+        let mut two = one.clone();
+        two.pixels[2 * 4] = true;
+        assert_eq!(changed_rect(&blank, &two), Some((0, 1, 3, 2)));
+        // only what differs counts, not what is lit
+        assert_eq!(changed_rect(&one, &two), Some((0, 2, 1, 1)));
+    }
+}